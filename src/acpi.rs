@@ -0,0 +1,219 @@
+use crate::memory::phys_to_virt;
+use x86_64::PhysAddr;
+
+/// The maximum number of processors recorded from the MADT.
+pub const MAX_PROCESSORS: usize = 16;
+
+/// The maximum number of I/O APICs recorded from the MADT.
+pub const MAX_IO_APICS: usize = 4;
+
+/// The maximum number of interrupt source overrides recorded from the MADT.
+pub const MAX_OVERRIDES: usize = 16;
+
+/// The size of the header shared by every system description table.
+const SDT_HEADER_SIZE: usize = 36;
+
+/// A processor with a local APIC, from a MADT entry of type 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Processor {
+    /// The ACPI processor UID.
+    pub processor_id: u8,
+    /// The ID of the processor's local APIC.
+    pub apic_id: u8,
+}
+
+/// An I/O APIC, from a MADT entry of type 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicInfo {
+    /// The ID of the I/O APIC.
+    pub id: u8,
+    /// The physical address of its registers.
+    pub address: PhysAddr,
+    /// The first global system interrupt it handles.
+    pub gsi_base: u32,
+}
+
+/// A legacy ISA IRQ wired to a different global system interrupt, or with non-standard
+/// polarity or trigger mode, from a MADT entry of type 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    /// The ISA IRQ.
+    pub irq: u8,
+    /// The global system interrupt it is connected to.
+    pub gsi: u32,
+    /// `true` if the interrupt is active low instead of the ISA default of active high.
+    pub active_low: bool,
+    /// `true` if the interrupt is level-triggered instead of the ISA default of edge-triggered.
+    pub level_triggered: bool,
+}
+
+/// The interrupt controller layout described by the MADT.
+#[derive(Debug, Clone, Copy)]
+pub struct Madt {
+    /// The physical address of the local APIC registers.
+    pub local_apic_address: PhysAddr,
+    /// `true` if the system also has 8259 PICs, which must be masked when using the APICs.
+    pub has_legacy_pics: bool,
+    /// The enabled processors.
+    pub processors: [Option<Processor>; MAX_PROCESSORS],
+    /// The I/O APICs.
+    pub io_apics: [Option<IoApicInfo>; MAX_IO_APICS],
+    /// The ISA interrupt source overrides.
+    pub overrides: [Option<InterruptOverride>; MAX_OVERRIDES],
+}
+
+impl Madt {
+    /// Returns the global system interrupt, polarity and trigger mode of an ISA IRQ as
+    /// `(gsi, active_low, level_triggered)`.
+    pub fn isa_irq(&self, irq: u8) -> (u32, bool, bool) {
+        match self.overrides.iter().flatten().find(|o| o.irq == irq) {
+            Some(o) => (o.gsi, o.active_low, o.level_triggered),
+            None => (irq as u32, false, false),
+        }
+    }
+}
+
+/// Locates the RSDP and returns the parsed Multiple APIC Description Table.
+///
+/// The RSDP is searched for in the first KiB of the extended BIOS data area and in the BIOS
+/// area between `0xE0000` and `0x100000`, as the ACPI specification requires on BIOS systems.
+/// The tables are read through the physical memory mapping. Entries beyond the fixed capacities
+/// of `Madt` are ignored.
+///
+/// # Returns
+/// The MADT, or `None` if there is no RSDP, no MADT, or a checksum does not match.
+///
+/// # Panics
+/// Panics if the memory globals are not initialized yet.
+pub fn find_madt() -> Option<Madt> {
+    let rsdp = find_rsdp()?;
+    let table = find_table(rsdp, b"APIC")?;
+    Some(parse_madt(table))
+}
+
+/// Returns the physical address of a valid RSDP.
+fn find_rsdp() -> Option<PhysAddr> {
+    let ebda = (read::<u16>(PhysAddr::new(0x40E)) as u64) << 4;
+    let candidates = (ebda..ebda + 1024)
+        .step_by(16)
+        .filter(|_| ebda != 0)
+        .chain((0xE0000..0x100000).step_by(16));
+    for addr in candidates {
+        let addr = PhysAddr::new(addr);
+        if bytes(addr, 8) == b"RSD PTR " && checksum(addr, 20) {
+            return Some(addr);
+        }
+    }
+    None
+}
+
+/// Returns the physical address of the table with the given signature, using the XSDT on
+/// ACPI 2.0+ and the RSDT otherwise.
+fn find_table(rsdp: PhysAddr, signature: &[u8; 4]) -> Option<PhysAddr> {
+    let revision = read::<u8>(rsdp + 15u64);
+    let (root, entry_size) = if revision >= 2 && checksum(rsdp, read::<u32>(rsdp + 20u64) as usize)
+    {
+        (PhysAddr::new(read::<u64>(rsdp + 24u64)), 8)
+    } else {
+        (PhysAddr::new(read::<u32>(rsdp + 16u64) as u64), 4)
+    };
+
+    let length = read::<u32>(root + 4u64) as usize;
+    if !checksum(root, length) {
+        return None;
+    }
+    let entries = (length - SDT_HEADER_SIZE) / entry_size;
+    (0..entries)
+        .map(|i| {
+            let entry = root + (SDT_HEADER_SIZE + i * entry_size) as u64;
+            if entry_size == 8 {
+                PhysAddr::new(read::<u64>(entry))
+            } else {
+                PhysAddr::new(read::<u32>(entry) as u64)
+            }
+        })
+        .find(|&table| {
+            bytes(table, 4) == signature && checksum(table, read::<u32>(table + 4u64) as usize)
+        })
+}
+
+/// Parses the MADT at `table`.
+fn parse_madt(table: PhysAddr) -> Madt {
+    let length = read::<u32>(table + 4u64) as u64;
+    let mut madt = Madt {
+        local_apic_address: PhysAddr::new(read::<u32>(table + 36u64) as u64),
+        has_legacy_pics: read::<u32>(table + 40u64) & 1 != 0,
+        processors: [None; MAX_PROCESSORS],
+        io_apics: [None; MAX_IO_APICS],
+        overrides: [None; MAX_OVERRIDES],
+    };
+
+    let mut offset = 44;
+    while offset + 2 <= length {
+        let entry = table + offset;
+        let entry_type = read::<u8>(entry);
+        let entry_length = read::<u8>(entry + 1u64) as u64;
+        if entry_length < 2 {
+            break;
+        }
+        match entry_type {
+            0 if read::<u32>(entry + 4u64) & 1 != 0 => push(
+                &mut madt.processors,
+                Processor {
+                    processor_id: read(entry + 2u64),
+                    apic_id: read(entry + 3u64),
+                },
+            ),
+            1 => push(
+                &mut madt.io_apics,
+                IoApicInfo {
+                    id: read(entry + 2u64),
+                    address: PhysAddr::new(read::<u32>(entry + 4u64) as u64),
+                    gsi_base: read(entry + 8u64),
+                },
+            ),
+            2 => {
+                let flags = read::<u16>(entry + 8u64);
+                push(
+                    &mut madt.overrides,
+                    InterruptOverride {
+                        irq: read(entry + 3u64),
+                        gsi: read(entry + 4u64),
+                        active_low: flags & 0b11 == 0b11,
+                        level_triggered: (flags >> 2) & 0b11 == 0b11,
+                    },
+                );
+            }
+            5 => madt.local_apic_address = PhysAddr::new(read(entry + 4u64)),
+            _ => {}
+        }
+        offset += entry_length;
+    }
+
+    madt
+}
+
+/// Stores `value` in the first free slot of `slots`, dropping it if there is none.
+fn push<T>(slots: &mut [Option<T>], value: T) {
+    if let Some(slot) = slots.iter_mut().find(|slot| slot.is_none()) {
+        *slot = Some(value);
+    }
+}
+
+/// Reads a possibly unaligned value from physical memory.
+fn read<T: Copy>(addr: PhysAddr) -> T {
+    unsafe { phys_to_virt(addr).as_ptr::<T>().read_unaligned() }
+}
+
+/// Returns `len` bytes of physical memory.
+fn bytes(addr: PhysAddr, len: usize) -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts(phys_to_virt(addr).as_ptr(), len) }
+}
+
+/// Returns `true` if the bytes of an ACPI structure add up to zero.
+fn checksum(addr: PhysAddr, len: usize) -> bool {
+    bytes(addr, len)
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        == 0
+}
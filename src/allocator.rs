@@ -1,77 +1,167 @@
 use crate::{
-    allocator::fixed_size_block::FixedSizeBlockAllocator, boot_splash::BootScreen, log::LogType,
+    allocator::fixed_size_block::BLOCK_SIZES,
+    boot_splash::BootScreen,
+    log::LogType,
+    memory::{self, buddy::BuddyFrameAllocator},
+    println, serial_println,
 };
 use alloc::alloc::{GlobalAlloc, Layout};
-use core::ptr::null_mut;
+use alloc::boxed::Box;
+use core::fmt;
+use core::ptr::{null_mut, NonNull};
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 use x86_64::{
     structures::paging::{
-        mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB,
+        mapper::MapToError, OffsetPageTable, PageSize, PageTableFlags, Size2MiB, Size4KiB,
     },
     VirtAddr,
 };
 
 pub mod bump;
+#[cfg(feature = "heap-debug")]
+pub mod debug;
 pub mod fixed_size_block;
 pub mod linked_list;
+pub mod reserve;
+pub mod slab;
+
+#[cfg(not(any(
+    feature = "alloc-bump",
+    feature = "alloc-linked-list",
+    feature = "alloc-fixed-block"
+)))]
+compile_error!(
+    "no heap allocator selected; enable one of `alloc-bump`, `alloc-linked-list` or `alloc-fixed-block`"
+);
+
+#[cfg(any(
+    all(feature = "alloc-bump", feature = "alloc-linked-list"),
+    all(feature = "alloc-bump", feature = "alloc-fixed-block"),
+    all(feature = "alloc-linked-list", feature = "alloc-fixed-block")
+))]
+compile_error!(
+    "only one heap allocator feature may be enabled; build with `--no-default-features` to replace `alloc-fixed-block`"
+);
+
+/// The allocator backing the kernel heap, selected through the `alloc-*` cargo features.
+#[cfg(feature = "alloc-bump")]
+pub type HeapAllocator = bump::BumpAllocator;
+/// The allocator backing the kernel heap, selected through the `alloc-*` cargo features.
+#[cfg(feature = "alloc-linked-list")]
+pub type HeapAllocator = linked_list::LinkedListAllocator;
+/// The allocator backing the kernel heap, selected through the `alloc-*` cargo features.
+#[cfg(feature = "alloc-fixed-block")]
+pub type HeapAllocator = fixed_size_block::FixedSizeBlockAllocator;
+
+/// The name of the allocator backing the kernel heap.
+#[cfg(feature = "alloc-bump")]
+pub const HEAP_ALLOCATOR_NAME: &str = "bump";
+/// The name of the allocator backing the kernel heap.
+#[cfg(feature = "alloc-linked-list")]
+pub const HEAP_ALLOCATOR_NAME: &str = "linked list";
+/// The name of the allocator backing the kernel heap.
+#[cfg(feature = "alloc-fixed-block")]
+pub const HEAP_ALLOCATOR_NAME: &str = "fixed-size block";
 
 /// The global allocator used by the system.
 ///
-/// This allocator is an instance of `FixedSizeBlockAllocator`, which is locked for safe concurrent access.
+/// Allocations are served by `ALLOCATOR`. Once the heap is exhausted and the emergency reserve
+/// has been released, failing allocations fall back to the reserve.
 #[global_allocator]
-static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
+static KERNEL_HEAP: KernelHeap = KernelHeap;
+
+/// The heap allocator selected through the `alloc-*` features, locked for safe concurrent access.
+static ALLOCATOR: Locked<HeapAllocator> = Locked::new(HeapAllocator::new());
 
 /// The start address of the heap in memory.
 pub const HEAP_START: usize = 0x444444440000;
-/// The size of the heap in bytes.
+/// The initial size of the heap in bytes.
 pub const HEAP_SIZE: usize = 100 * 1024;
+/// The size in bytes up to which the heap may grow on demand.
+pub const HEAP_MAX_SIZE: usize = 16 * 1024 * 1024;
+/// The minimum number of bytes mapped each time the heap grows.
+const HEAP_GROWTH_STEP: usize = 64 * 1024;
+
+/// The current end of the mapped heap region.
+static HEAP_END: AtomicUsize = AtomicUsize::new(HEAP_START);
+
+/// The reason the last attempt to grow the heap failed, encoded by `GrowError::code`, or 0 if
+/// it never failed.
+static LAST_GROW_ERROR: AtomicU8 = AtomicU8::new(0);
+
+/// The reasons the heap can fail to grow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrowError {
+    /// Growing would exceed `HEAP_MAX_SIZE`.
+    LimitReached,
+    /// The kernel's mapper or frame allocator was held, typically by the code the allocation
+    /// interrupted, so the heap could not grow without deadlocking.
+    Busy,
+    /// Physical memory ran out while mapping the new pages.
+    OutOfFrames,
+}
+
+impl GrowError {
+    /// Returns the non-zero value stored in `LAST_GROW_ERROR` for this error.
+    const fn code(self) -> u8 {
+        match self {
+            GrowError::LimitReached => 1,
+            GrowError::Busy => 2,
+            GrowError::OutOfFrames => 3,
+        }
+    }
+
+    /// Decodes a value stored in `LAST_GROW_ERROR`.
+    const fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(GrowError::LimitReached),
+            2 => Some(GrowError::Busy),
+            3 => Some(GrowError::OutOfFrames),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for GrowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            GrowError::LimitReached => "maximum heap size reached",
+            GrowError::Busy => "mapper or frame allocator held by the interrupted code",
+            GrowError::OutOfFrames => "out of physical frames",
+        })
+    }
+}
 
 /// Initializes the heap by mapping the required memory pages and setting up the allocator.
-/// This function maps a range of pages for the heap, allocates frames, and sets up the heap allocator.
-///
-/// # Arguments
-/// * `mapper` - A mutable reference to the `Mapper` for mapping pages.
-/// * `frame_allocator` - A mutable reference to the `FrameAllocator` used to allocate physical frames.
+/// This function maps `HEAP_SIZE` bytes at `HEAP_START` using the kernel's mapper and frame
+/// allocator, so `memory::init_globals` must have been called before.
 ///
 /// # Returns
 /// A `Result` indicating success or failure. Returns `Ok(())` if the heap is successfully initialized,
 /// or an error if frame allocation or mapping fails.
-pub fn init_heap(
-    mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
+pub fn init_heap() -> Result<(), MapToError<Size4KiB>> {
     BootScreen::log(LogType::Info, "Initializing heap");
-
-    // Define the page range for the heap.
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE - 1u64;
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
-
     BootScreen::log(LogType::Info, "Allocating frames for heap pages");
 
-    // Allocate and map frames for each page in the heap range.
-    for page in page_range {
-        let frame = match frame_allocator.allocate_frame() {
-            Some(frame) => frame,
-            None => {
-                BootScreen::log(
-                    LogType::Failed,
-                    "Frame allocation failed during heap initialization",
-                );
-                return Err(MapToError::FrameAllocationFailed);
-            }
-        };
-
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-        unsafe {
-            // Map the page to the allocated frame.
-            mapper.map_to(page, frame, flags, frame_allocator)?.flush();
+    // Keep the whole range the heap may grow into away from other kernel mappings. The heap
+    // owns it for the lifetime of the kernel, so the region is never freed.
+    memory::region::reserve(VirtAddr::new(HEAP_START as u64), HEAP_MAX_SIZE as u64)
+        .expect("heap range is already in use");
+
+    {
+        let mut mapper = memory::mapper();
+        let mut frame_allocator = memory::frame_allocator();
+        let mapped = map_heap_pages(HEAP_START, HEAP_SIZE, &mut mapper, &mut frame_allocator);
+        if mapped.is_err() {
+            BootScreen::log(
+                LogType::Failed,
+                "Frame allocation failed during heap initialization",
+            );
         }
+        mapped?;
     }
 
+    HEAP_END.store(HEAP_START + HEAP_SIZE, Ordering::SeqCst);
     BootScreen::log(LogType::Success, "Heap pages mapped successfully");
 
     unsafe {
@@ -85,6 +175,222 @@ pub fn init_heap(
     Ok(())
 }
 
+/// Maps fresh frames for every page in `start..start + size`.
+///
+/// Parts of the range that cover whole 2 MiB aligned chunks are mapped with 2 MiB pages when
+/// the frame allocator can provide huge frames, which saves page tables and TLB entries.
+///
+/// # Arguments
+/// * `start` - The page-aligned start address of the range.
+/// * `size` - The size of the range in bytes, a multiple of the page size.
+/// * `mapper` - The kernel's mapper.
+/// * `frame_allocator` - The kernel's frame allocator.
+///
+/// # Returns
+/// An error if frame allocation or mapping fails, in which case every page mapped by this call
+/// is unmapped again and its frame released.
+fn map_heap_pages(
+    start: usize,
+    size: usize,
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BuddyFrameAllocator,
+) -> Result<(), MapToError<Size4KiB>> {
+    let start = VirtAddr::new(start as u64);
+    let flags = PageTableFlags::WRITABLE | memory::protect::no_execute();
+    memory::region::map_fresh(mapper, frame_allocator, start, start + size, flags)
+}
+
+/// Maps more memory directly after the current end of the heap.
+///
+/// Called by the global allocator when it runs out of memory. At least `min_size` bytes (and
+/// at least `HEAP_GROWTH_STEP`) are requested, without exceeding `HEAP_MAX_SIZE`. Once the heap
+/// spans 2 MiB, it grows up to the next 2 MiB boundary, so that later growth is mapped with
+/// 2 MiB pages. The kernel's mapper and frame allocator are only tried, not waited for, so
+/// growing fails instead of deadlocking if the allocation happened while one of them was held.
+///
+/// Failures are recorded and reported by `heap_info` and the out-of-memory report.
+///
+/// # Arguments
+/// * `min_size` - The number of bytes the caller needs.
+///
+/// # Returns
+/// The number of bytes the heap grew by, which is at least `min_size`, or the reason it could
+/// not grow. Nothing stays mapped after a failure.
+fn grow_heap(min_size: usize) -> Result<usize, GrowError> {
+    let mut grown = try_grow_heap(min_size);
+    if grown == Err(GrowError::OutOfFrames) {
+        // Evicting cold pages may free enough frames to grow after all.
+        let pages = min_size
+            .max(HEAP_GROWTH_STEP)
+            .div_ceil(Size4KiB::SIZE as usize);
+        if memory::swap::try_reclaim(pages) > 0 {
+            grown = try_grow_heap(min_size);
+        }
+    }
+    if let Err(err) = grown {
+        LAST_GROW_ERROR.store(err.code(), Ordering::Relaxed);
+    }
+    grown
+}
+
+/// Maps at least `min_size` bytes after the current end of the heap, for `grow_heap`.
+fn try_grow_heap(min_size: usize) -> Result<usize, GrowError> {
+    let page_size = Size4KiB::SIZE as usize;
+    let heap_end = HEAP_END.load(Ordering::SeqCst);
+    let max_end = HEAP_START + HEAP_MAX_SIZE;
+    if min_size > max_end - heap_end {
+        return Err(GrowError::LimitReached);
+    }
+    let mut new_end = align_up(heap_end + min_size.max(HEAP_GROWTH_STEP), page_size);
+    if heap_end - HEAP_START >= Size2MiB::SIZE as usize {
+        new_end = align_up(new_end, Size2MiB::SIZE as usize);
+    }
+    let size = new_end.min(max_end) - heap_end;
+
+    let mut mapper = memory::try_mapper().ok_or(GrowError::Busy)?;
+    let mut frame_allocator = memory::try_frame_allocator().ok_or(GrowError::Busy)?;
+    map_heap_pages(heap_end, size, &mut mapper, &mut frame_allocator)
+        .map_err(|_| GrowError::OutOfFrames)?;
+
+    HEAP_END.store(heap_end + size, Ordering::SeqCst);
+    Ok(size)
+}
+
+/// Returns the reason the heap last failed to grow, if it ever did.
+pub fn last_grow_error() -> Option<GrowError> {
+    GrowError::from_code(LAST_GROW_ERROR.load(Ordering::Relaxed))
+}
+
+/// Reports an allocation that could not be satisfied and panics.
+///
+/// The emergency reserve is released first, so that formatting the report and unwinding into
+/// the panic handler can still allocate.
+///
+/// # Arguments
+/// * `layout` - The layout of the failed allocation.
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    reserve::release();
+
+    let report = OutOfMemoryReport {
+        layout,
+        info: heap_info(),
+    };
+    println!("{}", report);
+    serial_println!("{}", report);
+
+    panic!("allocation error: {:?}", layout)
+}
+
+/// The message printed by `alloc_error_handler`.
+struct OutOfMemoryReport {
+    /// The layout of the failed allocation.
+    layout: Layout,
+    /// The state of the heap at the time of the failure.
+    info: HeapInfo,
+}
+
+impl fmt::Display for OutOfMemoryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stats = self.info.stats;
+        writeln!(
+            f,
+            "out of memory: failed to allocate {} bytes aligned to {}",
+            self.layout.size(),
+            self.layout.align()
+        )?;
+        writeln!(
+            f,
+            "heap ({}): {} / {} bytes mapped, {} free",
+            HEAP_ALLOCATOR_NAME, self.info.heap_size, HEAP_MAX_SIZE, self.info.free_bytes
+        )?;
+        write!(
+            f,
+            "allocated: {} bytes in {} allocations, peak {} bytes",
+            stats.allocated_bytes, stats.live_allocations, stats.peak_bytes
+        )?;
+        if let Some(err) = self.info.last_grow_error {
+            write!(f, "\nheap growth failed: {}", err)?;
+        }
+        Ok(())
+    }
+}
+
+/// The global allocator, falling back to the emergency reserve once it has been released.
+struct KernelHeap;
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = ALLOCATOR.alloc(layout);
+        if ptr.is_null() {
+            reserve::alloc(layout)
+        } else {
+            ptr
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Memory taken from the reserve is never reused.
+        if !reserve::contains(ptr) {
+            ALLOCATOR.dealloc(ptr, layout);
+        }
+    }
+}
+
+/// The error returned by the fallible allocation functions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError {
+    /// The layout of the allocation that failed.
+    pub layout: Layout,
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "failed to allocate {} bytes aligned to {}",
+            self.layout.size(),
+            self.layout.align()
+        )
+    }
+}
+
+/// Allocates memory from the kernel heap without invoking the allocation error handler.
+///
+/// The emergency reserve is never used, so callers can handle the failure and keep the reserve
+/// for the error path.
+///
+/// # Arguments
+/// * `layout` - The size and alignment of the allocation.
+///
+/// # Returns
+/// A pointer to the allocated memory, which must be released with `alloc::alloc::dealloc` using
+/// the same layout, or an `AllocError` if the heap is exhausted. Zero-sized layouts return a
+/// dangling, well-aligned pointer that must not be deallocated.
+pub fn try_alloc(layout: Layout) -> Result<NonNull<u8>, AllocError> {
+    ALLOCATOR.try_alloc(layout)
+}
+
+/// Moves `value` into a new `Box` without invoking the allocation error handler.
+///
+/// # Arguments
+/// * `value` - The value to place on the heap.
+///
+/// # Returns
+/// The boxed value, or an `AllocError` if the heap is exhausted, in which case `value` is dropped.
+pub fn try_box<T>(value: T) -> Result<Box<T>, AllocError> {
+    let layout = Layout::new::<T>();
+    if layout.size() == 0 {
+        return Ok(Box::new(value));
+    }
+
+    let ptr = try_alloc(layout)?.cast::<T>();
+    unsafe {
+        ptr.as_ptr().write(value);
+        Ok(Box::from_raw(ptr.as_ptr()))
+    }
+}
+
 /// A dummy allocator that does not perform any actual allocation or deallocation.
 /// This is useful for handling cases where no memory allocation is required or should be allowed.
 pub struct Dummy;
@@ -147,6 +453,124 @@ impl<A> Locked<A> {
     }
 }
 
+impl<A> Locked<A>
+where
+    Locked<A>: GlobalAlloc,
+{
+    /// Allocates memory, returning an error instead of a null pointer on failure.
+    ///
+    /// # Arguments
+    /// * `layout` - The size and alignment of the allocation.
+    ///
+    /// # Returns
+    /// A pointer to the allocated memory, or an `AllocError` if the allocator is exhausted.
+    /// Zero-sized layouts return a dangling, well-aligned pointer that must not be deallocated.
+    pub fn try_alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return NonNull::new(layout.align() as *mut u8).ok_or(AllocError { layout });
+        }
+        NonNull::new(unsafe { self.alloc(layout) }).ok_or(AllocError { layout })
+    }
+
+    /// Allocates zero-initialized memory, returning an error instead of a null pointer on failure.
+    ///
+    /// # Arguments
+    /// * `layout` - The size and alignment of the allocation.
+    ///
+    /// # Returns
+    /// A pointer to the zeroed memory, or an `AllocError` if the allocator is exhausted.
+    /// Zero-sized layouts return a dangling, well-aligned pointer that must not be deallocated.
+    pub fn try_alloc_zeroed(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.size() == 0 {
+            return self.try_alloc(layout);
+        }
+        NonNull::new(unsafe { self.alloc_zeroed(layout) }).ok_or(AllocError { layout })
+    }
+}
+
+/// Usage counters kept by each heap allocator.
+///
+/// The counters are updated while the allocator's lock is held, so they are always consistent
+/// with each other.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllocStats {
+    /// The number of bytes currently handed out to callers.
+    pub allocated_bytes: usize,
+    /// The highest value `allocated_bytes` has ever reached.
+    pub peak_bytes: usize,
+    /// The number of allocations that have not been freed yet.
+    pub live_allocations: usize,
+}
+
+impl AllocStats {
+    /// Creates a new set of counters, all starting at zero.
+    pub const fn new() -> Self {
+        AllocStats {
+            allocated_bytes: 0,
+            peak_bytes: 0,
+            live_allocations: 0,
+        }
+    }
+
+    /// Records a successful allocation of `size` bytes.
+    fn record_alloc(&mut self, size: usize) {
+        self.allocated_bytes += size;
+        self.peak_bytes = self.peak_bytes.max(self.allocated_bytes);
+        self.live_allocations += 1;
+    }
+
+    /// Records the deallocation of `size` bytes.
+    fn record_dealloc(&mut self, size: usize) {
+        self.allocated_bytes -= size;
+        self.live_allocations -= 1;
+    }
+}
+
+/// Introspection shared by every allocator that can back the kernel heap.
+pub trait HeapUsage {
+    /// Returns the allocation counters of the allocator.
+    fn stats(&self) -> AllocStats;
+
+    /// Returns the total size of the heap region managed by the allocator.
+    fn heap_size(&self) -> usize;
+
+    /// Returns the number of bytes the allocator can still hand out without growing the heap.
+    fn free_bytes(&self) -> usize;
+
+    /// Returns the length of the free list for each entry of `BLOCK_SIZES`, if the allocator
+    /// keeps such lists.
+    fn free_blocks(&self) -> Option<[usize; BLOCK_SIZES.len()]> {
+        None
+    }
+}
+
+/// A snapshot of the global heap allocator's state.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapInfo {
+    /// The allocation counters of the global allocator.
+    pub stats: AllocStats,
+    /// The number of bytes currently mapped for the heap.
+    pub heap_size: usize,
+    /// The number of bytes that can be allocated without growing the heap.
+    pub free_bytes: usize,
+    /// The number of cached free blocks for each entry of `BLOCK_SIZES`, if any.
+    pub free_blocks: Option<[usize; BLOCK_SIZES.len()]>,
+    /// The reason the heap last failed to grow, if it ever did.
+    pub last_grow_error: Option<GrowError>,
+}
+
+/// Returns a snapshot of the global heap allocator's counters and free lists.
+pub fn heap_info() -> HeapInfo {
+    let allocator = ALLOCATOR.lock();
+    HeapInfo {
+        stats: allocator.stats(),
+        heap_size: allocator.heap_size(),
+        free_bytes: allocator.free_bytes(),
+        free_blocks: allocator.free_blocks(),
+        last_grow_error: last_grow_error(),
+    }
+}
+
 /// Aligns a given address upwards to the nearest multiple of the specified alignment.
 /// This function ensures that the address returned is aligned according to the given boundary.
 ///
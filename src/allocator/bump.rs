@@ -1,4 +1,4 @@
-use super::{align_up, Locked};
+use super::{align_up, AllocStats, HeapUsage, Locked};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::ptr;
 
@@ -9,6 +9,7 @@ pub struct BumpAllocator {
     heap_end: usize,
     next: usize,
     allocations: usize,
+    stats: AllocStats,
 }
 
 impl BumpAllocator {
@@ -19,6 +20,7 @@ impl BumpAllocator {
             heap_end: 0,
             next: 0,
             allocations: 0,
+            stats: AllocStats::new(),
         }
     }
 
@@ -33,6 +35,20 @@ impl BumpAllocator {
     }
 }
 
+impl HeapUsage for BumpAllocator {
+    fn stats(&self) -> AllocStats {
+        self.stats
+    }
+
+    fn heap_size(&self) -> usize {
+        self.heap_end - self.heap_start
+    }
+
+    fn free_bytes(&self) -> usize {
+        self.heap_end - self.next
+    }
+}
+
 impl Default for BumpAllocator {
     fn default() -> Self {
         Self::new()
@@ -53,11 +69,19 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
             None => return ptr::null_mut(),
         };
 
+        // Grow the heap if the allocation does not fit into the mapped region.
+        if alloc_end > bump.heap_end {
+            if let Ok(grown) = super::grow_heap(alloc_end - bump.heap_end) {
+                bump.heap_end += grown;
+            }
+        }
+
         if alloc_end > bump.heap_end {
             ptr::null_mut()
         } else {
             bump.next = alloc_end;
             bump.allocations += 1;
+            bump.stats.record_alloc(layout.size());
             alloc_start as *mut u8
         }
     }
@@ -67,8 +91,9 @@ unsafe impl GlobalAlloc for Locked<BumpAllocator> {
     ///
     /// # Safety
     /// The provided pointer must be valid and previously allocated.
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+    unsafe fn dealloc(&self, _ptr: *mut u8, layout: Layout) {
         let mut bump = self.lock();
+        bump.stats.record_dealloc(layout.size());
 
         bump.allocations -= 1;
         if bump.allocations == 0 {
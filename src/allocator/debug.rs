@@ -0,0 +1,216 @@
+use super::{align_up, HEAP_END, HEAP_START};
+use core::alloc::Layout;
+use core::fmt;
+use core::mem;
+use core::sync::atomic::Ordering;
+
+/// The byte pattern freshly allocated memory is filled with.
+pub const ALLOC_POISON: u8 = 0xAA;
+/// The byte pattern freed memory is filled with.
+pub const FREE_POISON: u8 = 0xDD;
+/// The byte pattern of the redzones around each allocation.
+pub const REDZONE_POISON: u8 = 0xFD;
+/// The minimum size of the redzones before and after each allocation.
+pub const REDZONE_SIZE: usize = 32;
+
+/// Marks a block as allocated by the debug allocator ("marcelos" in ASCII).
+const MAGIC: u64 = 0x6d61_7263_656c_6f73;
+/// Header state of a block that is currently allocated.
+const STATE_ALLOCATED: u64 = 0xa110_ca7e_a110_ca7e;
+/// Header state of a block that has been freed.
+const STATE_FREED: u64 = 0xf4ee_f4ee_f4ee_f4ee;
+
+/// Bookkeeping stored at the start of every debug block.
+///
+/// The layout of a block is `header | front redzone | user data | back redzone`, where the
+/// front redzone is padded so that the user data keeps the requested alignment.
+#[repr(C)]
+struct Header {
+    /// Space left untouched because the underlying allocator reuses the first bytes of a freed
+    /// block for its free lists.
+    _reserved: [usize; 2],
+    /// Always `MAGIC` for blocks handed out by the debug allocator.
+    magic: u64,
+    /// Either `STATE_ALLOCATED` or `STATE_FREED`.
+    state: u64,
+    /// The size requested by the caller.
+    size: usize,
+    /// The alignment requested by the caller.
+    align: usize,
+}
+
+/// Returns the distance between the start of a block and the user data.
+fn prefix_size(layout: &Layout) -> usize {
+    align_up(mem::size_of::<Header>() + REDZONE_SIZE, layout.align())
+}
+
+/// Returns the layout of the block backing an allocation with the given user layout.
+pub(super) fn block_layout(layout: Layout) -> Layout {
+    let size = prefix_size(&layout) + layout.size() + REDZONE_SIZE;
+    let align = layout.align().max(mem::align_of::<Header>());
+    Layout::from_size_align(size, align).expect("debug block layout overflow")
+}
+
+/// Writes the header and redzones into a fresh block and poisons the user data.
+///
+/// # Arguments
+/// * `block` - The block returned by the underlying allocator for `block_layout(layout)`.
+/// * `layout` - The layout requested by the caller.
+///
+/// # Returns
+/// The pointer to hand out to the caller, or null if `block` is null.
+///
+/// # Safety
+/// `block` must be null or valid for `block_layout(layout)`.
+pub(super) unsafe fn prepare(block: *mut u8, layout: Layout) -> *mut u8 {
+    if block.is_null() {
+        return block;
+    }
+
+    let prefix = prefix_size(&layout);
+    let header = block as *mut Header;
+    (*header).magic = MAGIC;
+    (*header).state = STATE_ALLOCATED;
+    (*header).size = layout.size();
+    (*header).align = layout.align();
+
+    let ptr = block.add(prefix);
+    let header_size = mem::size_of::<Header>();
+    block
+        .add(header_size)
+        .write_bytes(REDZONE_POISON, prefix - header_size);
+    ptr.write_bytes(ALLOC_POISON, layout.size());
+    ptr.add(layout.size())
+        .write_bytes(REDZONE_POISON, REDZONE_SIZE);
+
+    ptr
+}
+
+/// A problem found by `release` in an allocation that is being freed.
+pub(super) struct FreeError {
+    /// The pointer passed to `dealloc`.
+    ptr: *mut u8,
+    /// The layout passed to `dealloc`.
+    layout: Layout,
+    /// What is wrong with the allocation.
+    kind: FreeErrorKind,
+}
+
+/// The kinds of invalid frees detected by `release`.
+enum FreeErrorKind {
+    /// The pointer lies outside the heap.
+    Foreign,
+    /// The block does not start with a valid header.
+    ForeignOrCorrupted,
+    /// The block was already freed.
+    DoubleFree,
+    /// The header state is neither allocated nor freed.
+    CorruptedHeader,
+    /// The block was allocated with a different size or alignment.
+    LayoutMismatch { size: usize, align: usize },
+    /// A redzone byte at the given address was overwritten.
+    RedzoneOverwritten(*const u8),
+}
+
+impl fmt::Display for FreeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (ptr, layout) = (self.ptr, self.layout);
+        match self.kind {
+            FreeErrorKind::Foreign => {
+                write!(f, "HEAP: free of foreign pointer {:p} ({:?})", ptr, layout)
+            }
+            FreeErrorKind::ForeignOrCorrupted => write!(
+                f,
+                "HEAP: free of foreign or corrupted pointer {:p} ({:?})",
+                ptr, layout
+            ),
+            FreeErrorKind::DoubleFree => {
+                write!(f, "HEAP: double free of {:p} ({:?})", ptr, layout)
+            }
+            FreeErrorKind::CorruptedHeader => write!(
+                f,
+                "HEAP: corrupted allocation header for {:p} ({:?})",
+                ptr, layout
+            ),
+            FreeErrorKind::LayoutMismatch { size, align } => write!(
+                f,
+                "HEAP: free of {:p} with {:?}, but it was allocated with size {} and align {}",
+                ptr, layout, size, align
+            ),
+            FreeErrorKind::RedzoneOverwritten(byte) => write!(
+                f,
+                "HEAP: redzone overwritten at {:p} for allocation {:p} ({:?})",
+                byte, ptr, layout
+            ),
+        }
+    }
+}
+
+/// Validates an allocation that is about to be freed and poisons its user data.
+///
+/// Must be called with the allocator locked, so that a concurrent free of the same pointer
+/// cannot pass the checks as well.
+///
+/// # Arguments
+/// * `ptr` - The pointer passed to `dealloc`.
+/// * `layout` - The layout passed to `dealloc`.
+///
+/// # Returns
+/// The start of the block to return to the underlying allocator, or a `FreeError` describing
+/// the offending address and layout if the pointer does not belong to the heap, was already
+/// freed, was allocated with a different layout or if one of its redzones was overwritten.
+/// The caller is expected to unlock the allocator and panic with the error.
+///
+/// # Safety
+/// `ptr` must point into the mapped heap for the checks themselves to be memory safe, which is
+/// verified before anything is read.
+pub(super) unsafe fn release(ptr: *mut u8, layout: Layout) -> Result<*mut u8, FreeError> {
+    let error = |kind| FreeError { ptr, layout, kind };
+    let prefix = prefix_size(&layout);
+    let addr = ptr as usize;
+    if addr < HEAP_START + prefix || addr >= HEAP_END.load(Ordering::SeqCst) {
+        return Err(error(FreeErrorKind::Foreign));
+    }
+
+    let block = ptr.sub(prefix);
+    let header = block as *mut Header;
+    if (*header).magic != MAGIC {
+        return Err(error(FreeErrorKind::ForeignOrCorrupted));
+    }
+    match (*header).state {
+        STATE_ALLOCATED => {}
+        STATE_FREED => return Err(error(FreeErrorKind::DoubleFree)),
+        _ => return Err(error(FreeErrorKind::CorruptedHeader)),
+    }
+    if (*header).size != layout.size() || (*header).align != layout.align() {
+        return Err(error(FreeErrorKind::LayoutMismatch {
+            size: (*header).size,
+            align: (*header).align,
+        }));
+    }
+
+    let header_size = mem::size_of::<Header>();
+    for (start, len) in [
+        (block.add(header_size), prefix - header_size),
+        (ptr.add(layout.size()), REDZONE_SIZE),
+    ] {
+        if let Some(byte) = overwritten_byte(start, len) {
+            return Err(error(FreeErrorKind::RedzoneOverwritten(byte)));
+        }
+    }
+
+    (*header).state = STATE_FREED;
+    ptr.write_bytes(FREE_POISON, layout.size());
+
+    Ok(block)
+}
+
+/// Returns the first byte of the given redzone that was overwritten, if any.
+///
+/// # Safety
+/// The redzone must be valid for reads of `len` bytes.
+unsafe fn overwritten_byte(start: *const u8, len: usize) -> Option<*const u8> {
+    (0..len)
+        .map(|offset| start.add(offset))
+        .find(|&byte| *byte != REDZONE_POISON)
+}
@@ -1,4 +1,6 @@
-use super::Locked;
+#[cfg(feature = "heap-debug")]
+use super::debug;
+use super::{AllocStats, HeapUsage, Locked};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::{
     mem,
@@ -6,7 +8,7 @@ use core::{
 };
 
 /// Block sizes available for allocation.
-const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+pub const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
 
 /// Returns the index of the smallest block size that fits the given layout.
 fn list_index(layout: &Layout) -> Option<usize> {
@@ -23,6 +25,7 @@ struct ListNode {
 pub struct FixedSizeBlockAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
+    stats: AllocStats,
 }
 
 impl FixedSizeBlockAllocator {
@@ -32,6 +35,7 @@ impl FixedSizeBlockAllocator {
         FixedSizeBlockAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
+            stats: AllocStats::new(),
         }
     }
 
@@ -43,15 +47,97 @@ impl FixedSizeBlockAllocator {
         self.fallback_allocator.init(heap_start, heap_size);
     }
 
-    /// Allocates memory using the fallback allocator.
+    /// Allocates a block for the given layout, from the matching free list if possible.
+    fn allocate_block(&mut self, layout: Layout) -> *mut u8 {
+        match list_index(&layout) {
+            Some(index) => match self.list_heads[index].take() {
+                Some(node) => {
+                    self.list_heads[index] = node.next.take();
+                    node as *mut ListNode as *mut u8
+                }
+                None => {
+                    let block_size = BLOCK_SIZES[index];
+                    let block_align = block_size;
+                    let layout = Layout::from_size_align(block_size, block_align).unwrap();
+                    self.fallback_alloc(layout)
+                }
+            },
+            None => self.fallback_alloc(layout),
+        }
+    }
+
+    /// Returns a block to the matching free list, or to the fallback allocator.
+    ///
+    /// # Safety
+    /// The block must have been returned by `allocate_block` for the same layout.
+    unsafe fn deallocate_block(&mut self, ptr: *mut u8, layout: Layout) {
+        match list_index(&layout) {
+            Some(index) => {
+                let new_node = ListNode {
+                    next: self.list_heads[index].take(),
+                };
+                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
+                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
+                let new_node_ptr = ptr as *mut ListNode;
+                new_node_ptr.write(new_node);
+                self.list_heads[index] = Some(&mut *new_node_ptr);
+            }
+            None => {
+                let ptr = NonNull::new(ptr).unwrap();
+                self.fallback_allocator.deallocate(ptr, layout);
+            }
+        }
+    }
+
+    /// Allocates memory using the fallback allocator, growing the heap if it is exhausted.
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
-        match self.fallback_allocator.allocate_first_fit(layout) {
-            Ok(ptr) => ptr.as_ptr(),
+        if let Ok(ptr) = self.fallback_allocator.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+
+        // Request enough extra memory to fit the layout even at the worst alignment.
+        match super::grow_heap(layout.size() + layout.align()) {
+            Ok(grown) => {
+                unsafe { self.fallback_allocator.extend(grown) };
+                match self.fallback_allocator.allocate_first_fit(layout) {
+                    Ok(ptr) => ptr.as_ptr(),
+                    Err(_) => ptr::null_mut(),
+                }
+            }
             Err(_) => ptr::null_mut(),
         }
     }
 }
 
+impl HeapUsage for FixedSizeBlockAllocator {
+    fn stats(&self) -> AllocStats {
+        self.stats
+    }
+
+    fn heap_size(&self) -> usize {
+        self.fallback_allocator.size()
+    }
+
+    /// Returns the number of bytes that are still free in the fallback allocator.
+    ///
+    /// Blocks cached in the free lists count as used here, since they were taken from it.
+    fn free_bytes(&self) -> usize {
+        self.fallback_allocator.free()
+    }
+
+    fn free_blocks(&self) -> Option<[usize; BLOCK_SIZES.len()]> {
+        let mut lengths = [0; BLOCK_SIZES.len()];
+        for (length, head) in lengths.iter_mut().zip(self.list_heads.iter()) {
+            let mut current = head.as_deref();
+            while let Some(node) = current {
+                *length += 1;
+                current = node.next.as_deref();
+            }
+        }
+        Some(lengths)
+    }
+}
+
 impl Default for FixedSizeBlockAllocator {
     fn default() -> Self {
         Self::new()
@@ -61,48 +147,52 @@ impl Default for FixedSizeBlockAllocator {
 unsafe impl GlobalAlloc for Locked<FixedSizeBlockAllocator> {
     /// Allocates memory with the requested layout.
     ///
+    /// With the `heap-debug` feature, the block is surrounded by redzones and poisoned before it
+    /// is handed out (see `allocator::debug`).
+    ///
     /// # Safety
     /// This function must be called only in a thread-safe context.
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
-        match list_index(&layout) {
-            Some(index) => match allocator.list_heads[index].take() {
-                Some(node) => {
-                    allocator.list_heads[index] = node.next.take();
-                    node as *mut ListNode as *mut u8
-                }
-                None => {
-                    let block_size = BLOCK_SIZES[index];
-                    let block_align = block_size;
-                    let layout = Layout::from_size_align(block_size, block_align).unwrap();
-                    allocator.fallback_alloc(layout)
-                }
-            },
-            None => allocator.fallback_alloc(layout),
+
+        #[cfg(feature = "heap-debug")]
+        let ptr = debug::prepare(
+            allocator.allocate_block(debug::block_layout(layout)),
+            layout,
+        );
+        #[cfg(not(feature = "heap-debug"))]
+        let ptr = allocator.allocate_block(layout);
+
+        if !ptr.is_null() {
+            allocator.stats.record_alloc(layout.size());
         }
+        ptr
     }
 
     /// Deallocates memory and inserts it back into the free list if applicable.
     ///
+    /// With the `heap-debug` feature, the block is validated first and the kernel panics on
+    /// double frees, foreign pointers and corrupted redzones.
+    ///
     /// # Safety
     /// The provided pointer must be valid and previously allocated.
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let mut allocator = self.lock();
-        match list_index(&layout) {
-            Some(index) => {
-                let new_node = ListNode {
-                    next: allocator.list_heads[index].take(),
-                };
-                assert!(mem::size_of::<ListNode>() <= BLOCK_SIZES[index]);
-                assert!(mem::align_of::<ListNode>() <= BLOCK_SIZES[index]);
-                let new_node_ptr = ptr as *mut ListNode;
-                new_node_ptr.write(new_node);
-                allocator.list_heads[index] = Some(&mut *new_node_ptr);
-            }
-            None => {
-                let ptr = NonNull::new(ptr).unwrap();
-                allocator.fallback_allocator.deallocate(ptr, layout);
+
+        // Validate under the lock, so that two racing frees of the same block cannot both pass.
+        // The lock is released before panicking, so that the panic handler can still allocate.
+        #[cfg(feature = "heap-debug")]
+        let (ptr, block_layout) = match debug::release(ptr, layout) {
+            Ok(block) => (block, debug::block_layout(layout)),
+            Err(error) => {
+                drop(allocator);
+                panic!("{}", error);
             }
-        }
+        };
+        #[cfg(not(feature = "heap-debug"))]
+        let block_layout = layout;
+
+        allocator.stats.record_dealloc(layout.size());
+        allocator.deallocate_block(ptr, block_layout);
     }
 }
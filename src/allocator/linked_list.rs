@@ -1,4 +1,4 @@
-use super::{align_up, Locked};
+use super::{align_up, AllocStats, HeapUsage, Locked};
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::{mem, ptr};
 
@@ -31,6 +31,12 @@ impl ListNode {
 pub struct LinkedListAllocator {
     /// Head node of the free list.
     head: ListNode,
+    /// The start address of the heap region.
+    heap_start: usize,
+    /// The end address of the heap region.
+    heap_end: usize,
+    /// Allocation counters of this allocator.
+    stats: AllocStats,
 }
 
 impl LinkedListAllocator {
@@ -38,6 +44,9 @@ impl LinkedListAllocator {
     pub const fn new() -> Self {
         Self {
             head: ListNode::new(0),
+            heap_start: 0,
+            heap_end: 0,
+            stats: AllocStats::new(),
         }
     }
 
@@ -46,22 +55,73 @@ impl LinkedListAllocator {
     /// # Safety
     /// This function must be called only once and with a valid heap memory range.
     pub unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
         self.add_free_region(heap_start, heap_size);
     }
 
+    /// Grows the heap by mapping more memory after its end and adding it as a free region.
+    ///
+    /// # Returns
+    /// `true` if the heap grew by at least `min_size` bytes.
+    fn grow(&mut self, min_size: usize) -> bool {
+        match super::grow_heap(min_size) {
+            Ok(grown) => {
+                unsafe { self.add_free_region(self.heap_end, grown) };
+                self.heap_end += grown;
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
     /// Adds a new free memory region to the allocator.
     ///
+    /// The free list is kept sorted by address, and the region is merged with the free regions
+    /// directly before and after it, so that neighbouring blocks form a single larger region
+    /// again once they are all freed.
+    ///
     /// # Safety
     /// The given memory region must be valid and not already allocated.
     unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
         assert_eq!(align_up(addr, mem::align_of::<ListNode>()), addr);
         assert!(size >= mem::size_of::<ListNode>());
 
+        // Find the last free region that starts before the new one.
+        let mut current = &mut self.head;
+        let mut current_is_head = true;
+        while current
+            .next
+            .as_ref()
+            .is_some_and(|next| next.start_addr() < addr)
+        {
+            current = current.next.as_mut().unwrap();
+            current_is_head = false;
+        }
+
         let mut node = ListNode::new(size);
-        node.next = self.head.next.take();
+        node.next = current.next.take();
+
+        // Merge with the following region if it starts right where the new one ends.
+        if let Some(next) = node.next.take() {
+            if addr + node.size == next.start_addr() {
+                node.size += next.size;
+                node.next = next.next.take();
+            } else {
+                node.next = Some(next);
+            }
+        }
+
+        // Merge into the preceding region if it ends right where the new one starts.
+        if !current_is_head && current.end_addr() == addr {
+            current.size += node.size;
+            current.next = node.next.take();
+            return;
+        }
+
         let node_ptr = addr as *mut ListNode;
         node_ptr.write(node);
-        self.head.next = Some(&mut *node_ptr);
+        current.next = Some(&mut *node_ptr);
     }
 
     /// Searches for a suitable free memory region that satisfies the requested size and alignment.
@@ -108,6 +168,26 @@ impl LinkedListAllocator {
     }
 }
 
+impl HeapUsage for LinkedListAllocator {
+    fn stats(&self) -> AllocStats {
+        self.stats
+    }
+
+    fn heap_size(&self) -> usize {
+        self.heap_end - self.heap_start
+    }
+
+    fn free_bytes(&self) -> usize {
+        let mut free = 0;
+        let mut current = self.head.next.as_deref();
+        while let Some(region) = current {
+            free += region.size;
+            current = region.next.as_deref();
+        }
+        free
+    }
+}
+
 impl Default for LinkedListAllocator {
     fn default() -> Self {
         Self::new()
@@ -124,12 +204,18 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
         let (size, align) = LinkedListAllocator::size_align(layout);
         let mut allocator = self.lock();
 
-        if let Some((region, alloc_start)) = allocator.find_region(size, align) {
+        let mut found = allocator.find_region(size, align);
+        if found.is_none() && allocator.grow(size + align) {
+            found = allocator.find_region(size, align);
+        }
+
+        if let Some((region, alloc_start)) = found {
             let alloc_end = alloc_start.checked_add(size).expect("overflow");
             let excess_size = region.end_addr() - alloc_end;
             if excess_size > 0 {
                 allocator.add_free_region(alloc_end, excess_size);
             }
+            allocator.stats.record_alloc(layout.size());
             alloc_start as *mut u8
         } else {
             ptr::null_mut()
@@ -142,6 +228,8 @@ unsafe impl GlobalAlloc for Locked<LinkedListAllocator> {
     /// The caller must ensure that the pointer and layout are valid and that the memory block was allocated by this allocator.
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
         let (size, _) = LinkedListAllocator::size_align(layout);
-        self.lock().add_free_region(ptr as usize, size);
+        let mut allocator = self.lock();
+        allocator.stats.record_dealloc(layout.size());
+        allocator.add_free_region(ptr as usize, size);
     }
 }
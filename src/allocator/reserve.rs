@@ -0,0 +1,74 @@
+use core::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// The size of the emergency reserve in bytes.
+pub const RESERVE_SIZE: usize = 16 * 1024;
+
+/// The backing memory of the reserve, placed in the kernel image so that it exists even before
+/// the heap is initialized.
+#[repr(C, align(4096))]
+struct ReserveMemory(UnsafeCell<[u8; RESERVE_SIZE]>);
+
+// The memory is only handed out in disjoint chunks through the atomic `NEXT` offset.
+unsafe impl Sync for ReserveMemory {}
+
+static MEMORY: ReserveMemory = ReserveMemory(UnsafeCell::new([0; RESERVE_SIZE]));
+
+/// Whether the reserve may be used. It stays locked until the heap has been exhausted.
+static RELEASED: AtomicBool = AtomicBool::new(false);
+
+/// The offset of the first unused byte of the reserve.
+static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+/// Makes the reserve available to the global allocator.
+///
+/// Called by the allocation error handler so that reporting the failure and panicking can still
+/// allocate after the heap is exhausted. There is no way to lock the reserve again.
+pub fn release() {
+    RELEASED.store(true, Ordering::SeqCst);
+}
+
+/// Returns `true` once the reserve has been released.
+pub fn is_released() -> bool {
+    RELEASED.load(Ordering::SeqCst)
+}
+
+/// Returns the number of bytes of the reserve that have not been handed out yet.
+pub fn remaining() -> usize {
+    RESERVE_SIZE - NEXT.load(Ordering::SeqCst)
+}
+
+/// Returns `true` if `ptr` points into the reserve.
+pub fn contains(ptr: *const u8) -> bool {
+    let start = MEMORY.0.get() as usize;
+    (start..start + RESERVE_SIZE).contains(&(ptr as usize))
+}
+
+/// Allocates from the reserve like a bump allocator.
+///
+/// Memory taken from the reserve is never reused; the reserve only has to last until the kernel
+/// has reported the failure.
+///
+/// # Returns
+/// A pointer to the allocated memory, or null if the reserve has not been released or is used up.
+pub(super) fn alloc(layout: Layout) -> *mut u8 {
+    if !is_released() {
+        return null_mut();
+    }
+
+    let start = MEMORY.0.get() as usize;
+    let mut next = NEXT.load(Ordering::SeqCst);
+    loop {
+        let alloc_start = super::align_up(start + next, layout.align());
+        let alloc_end = match alloc_start.checked_add(layout.size()) {
+            Some(end) if end <= start + RESERVE_SIZE => end,
+            _ => return null_mut(),
+        };
+        match NEXT.compare_exchange(next, alloc_end - start, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => return alloc_start as *mut u8,
+            Err(current) => next = current,
+        }
+    }
+}
@@ -0,0 +1,381 @@
+use super::Locked;
+use crate::memory;
+use core::alloc::Layout;
+use core::mem;
+use core::ptr::NonNull;
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB};
+
+/// The size of a single slab. Every slab occupies exactly one physical frame.
+const SLAB_SIZE: usize = Size4KiB::SIZE as usize;
+
+/// The number of empty slabs a cache keeps around before returning them to the frame allocator.
+const EMPTY_SLABS_KEPT: usize = 1;
+
+/// The maximum number of caches that can be registered for inspection.
+const MAX_CACHES: usize = 32;
+
+/// The caches registered with `register`, used by diagnostics such as `meminfo`.
+static CACHES: Mutex<[Option<&'static Locked<SlabCache>>; MAX_CACHES]> =
+    Mutex::new([None; MAX_CACHES]);
+
+/// A free object slot, linked into the free list of its slab.
+struct FreeObject {
+    next: Option<NonNull<FreeObject>>,
+}
+
+/// The header stored at the start of every slab, followed by the object slots.
+struct Slab {
+    /// The frame backing this slab.
+    frame: PhysFrame,
+    /// The cache owning this slab, used to catch frees through the wrong cache.
+    cache: *const SlabCache,
+    /// The free object slots of this slab.
+    free_list: Option<NonNull<FreeObject>>,
+    /// The number of objects currently allocated from this slab.
+    in_use: usize,
+    /// The previous slab in the list this slab belongs to.
+    prev: Option<NonNull<Slab>>,
+    /// The next slab in the list this slab belongs to.
+    next: Option<NonNull<Slab>>,
+}
+
+/// An intrusive doubly linked list of slabs.
+struct SlabList {
+    head: Option<NonNull<Slab>>,
+    len: usize,
+}
+
+impl SlabList {
+    /// Creates an empty list.
+    const fn new() -> Self {
+        SlabList { head: None, len: 0 }
+    }
+
+    /// Pushes a slab to the front of the list.
+    ///
+    /// # Safety
+    /// The slab must be valid and not part of any list.
+    unsafe fn push(&mut self, mut slab: NonNull<Slab>) {
+        slab.as_mut().prev = None;
+        slab.as_mut().next = self.head;
+        if let Some(mut head) = self.head {
+            head.as_mut().prev = Some(slab);
+        }
+        self.head = Some(slab);
+        self.len += 1;
+    }
+
+    /// Unlinks a slab from the list.
+    ///
+    /// # Safety
+    /// The slab must be valid and part of this list.
+    unsafe fn remove(&mut self, slab: NonNull<Slab>) {
+        let (prev, next) = (slab.as_ref().prev, slab.as_ref().next);
+        match prev {
+            Some(mut prev) => prev.as_mut().next = next,
+            None => self.head = next,
+        }
+        if let Some(mut next) = next {
+            next.as_mut().prev = prev;
+        }
+        self.len -= 1;
+    }
+}
+
+/// Usage counters of a single slab cache.
+#[derive(Debug, Clone, Copy)]
+pub struct SlabStats {
+    /// The size of each object slot in bytes, including padding.
+    pub object_size: usize,
+    /// The number of object slots in each slab.
+    pub objects_per_slab: usize,
+    /// The number of objects currently allocated.
+    pub objects_in_use: usize,
+    /// The number of slabs with both free and allocated objects.
+    pub partial_slabs: usize,
+    /// The number of slabs without free objects.
+    pub full_slabs: usize,
+    /// The number of slabs without allocated objects.
+    pub empty_slabs: usize,
+}
+
+/// A cache of equally sized objects, carved from whole-page slabs.
+///
+/// Slabs are taken directly from the kernel's frame allocator and accessed through the
+/// physical memory mapping, so caches work independently of the heap. Each slab is kept in
+/// one of three lists depending on how many of its objects are in use; once a cache holds more
+/// than `EMPTY_SLABS_KEPT` empty slabs, the surplus frames are returned to the frame allocator.
+///
+/// The frame allocator is only tried, never waited for, so a cache can be used from interrupt
+/// handlers and by code holding the frame allocator. Allocations that need a new slab fail while
+/// it is held, and empty slabs are kept until it is free again.
+///
+/// Slabs remember the cache they belong to, so a cache must not be moved once it has allocated
+/// objects. Caches are meant to live in a `static Locked<SlabCache>`:
+///
+/// ```ignore
+/// static NODE_CACHE: Locked<SlabCache> = Locked::new(SlabCache::for_type::<Node>("node"));
+/// ```
+pub struct SlabCache {
+    /// The name of the cache, used for diagnostics.
+    name: &'static str,
+    /// The size of each object slot, rounded up to the object alignment.
+    object_size: usize,
+    /// The offset of the first object slot from the start of a slab.
+    first_object_offset: usize,
+    /// The number of object slots in each slab.
+    objects_per_slab: usize,
+    /// Slabs with both free and allocated objects.
+    partial: SlabList,
+    /// Slabs without free objects.
+    full: SlabList,
+    /// Slabs without allocated objects.
+    empty: SlabList,
+    /// The number of objects currently allocated.
+    objects_in_use: usize,
+}
+
+// The raw slab pointers are only ever accessed through `&mut SlabCache`.
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    /// Creates a new, empty cache for objects with the given layout.
+    ///
+    /// No memory is allocated until the first object is requested.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the cache, used for diagnostics.
+    /// * `layout` - The size and alignment of the objects in this cache.
+    ///
+    /// # Panics
+    /// Panics if a single object does not fit into a slab next to the slab header.
+    pub const fn new(name: &'static str, layout: Layout) -> Self {
+        let align = if layout.align() > mem::align_of::<FreeObject>() {
+            layout.align()
+        } else {
+            mem::align_of::<FreeObject>()
+        };
+        let size = if layout.size() > mem::size_of::<FreeObject>() {
+            layout.size()
+        } else {
+            mem::size_of::<FreeObject>()
+        };
+        let object_size = (size + align - 1) & !(align - 1);
+        let first_object_offset = (mem::size_of::<Slab>() + align - 1) & !(align - 1);
+        assert!(
+            first_object_offset + object_size <= SLAB_SIZE,
+            "object too large for a slab"
+        );
+
+        SlabCache {
+            name,
+            object_size,
+            first_object_offset,
+            objects_per_slab: (SLAB_SIZE - first_object_offset) / object_size,
+            partial: SlabList::new(),
+            full: SlabList::new(),
+            empty: SlabList::new(),
+            objects_in_use: 0,
+        }
+    }
+
+    /// Creates a new, empty cache for objects of type `T`.
+    ///
+    /// # Arguments
+    /// * `name` - The name of the cache, used for diagnostics.
+    pub const fn for_type<T>(name: &'static str) -> Self {
+        Self::new(name, Layout::new::<T>())
+    }
+
+    /// Returns the name of this cache.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the usage counters of this cache.
+    pub fn stats(&self) -> SlabStats {
+        SlabStats {
+            object_size: self.object_size,
+            objects_per_slab: self.objects_per_slab,
+            objects_in_use: self.objects_in_use,
+            partial_slabs: self.partial.len,
+            full_slabs: self.full.len,
+            empty_slabs: self.empty.len,
+        }
+    }
+
+    /// Allocates an object from this cache.
+    ///
+    /// Partially used slabs are preferred over empty ones to keep memory compact. A new slab
+    /// is created if neither exists.
+    ///
+    /// # Returns
+    /// A pointer to uninitialized memory for one object, or `None` if a new slab is needed but no
+    /// frame is available or the frame allocator is held.
+    pub fn alloc(&mut self) -> Option<NonNull<u8>> {
+        let slab = match self.partial.head.or(self.empty.head) {
+            Some(slab) => slab,
+            None => self.grow()?,
+        };
+        let slab_ptr = slab.as_ptr();
+
+        unsafe {
+            if (*slab_ptr).in_use == 0 {
+                self.empty.remove(slab);
+                self.partial.push(slab);
+            }
+
+            let object = (*slab_ptr)
+                .free_list
+                .expect("slab in partial list without free objects");
+            (*slab_ptr).free_list = object.as_ref().next;
+            (*slab_ptr).in_use += 1;
+            self.objects_in_use += 1;
+
+            if (*slab_ptr).in_use == self.objects_per_slab {
+                self.partial.remove(slab);
+                self.full.push(slab);
+            }
+
+            Some(object.cast())
+        }
+    }
+
+    /// Returns an object to this cache.
+    ///
+    /// # Arguments
+    /// * `ptr` - A pointer previously returned by `alloc` on this cache.
+    ///
+    /// # Safety
+    /// The object must have been allocated from this cache and must not be used afterwards.
+    ///
+    /// # Panics
+    /// Panics if the object belongs to a slab of a different cache.
+    pub unsafe fn free(&mut self, ptr: NonNull<u8>) {
+        let slab_ptr = (ptr.as_ptr() as usize & !(SLAB_SIZE - 1)) as *mut Slab;
+        let slab = NonNull::new_unchecked(slab_ptr);
+        assert!(
+            core::ptr::eq((*slab_ptr).cache, self),
+            "object {:p} freed through the wrong slab cache ({})",
+            ptr,
+            self.name
+        );
+
+        let was_full = (*slab_ptr).in_use == self.objects_per_slab;
+
+        let object = ptr.cast::<FreeObject>();
+        object.as_ptr().write(FreeObject {
+            next: (*slab_ptr).free_list,
+        });
+        (*slab_ptr).free_list = Some(object);
+        (*slab_ptr).in_use -= 1;
+        self.objects_in_use -= 1;
+
+        if was_full {
+            self.full.remove(slab);
+            self.partial.push(slab);
+        }
+
+        if (*slab_ptr).in_use == 0 {
+            self.partial.remove(slab);
+            if self.empty.len >= EMPTY_SLABS_KEPT && Self::release(slab) {
+                return;
+            }
+            self.empty.push(slab);
+        }
+    }
+
+    /// Returns every empty slab of this cache to the frame allocator.
+    ///
+    /// # Returns
+    /// The number of frames that were released, which is 0 if the frame allocator is held.
+    pub fn reclaim(&mut self) -> usize {
+        let Some(mut frame_allocator) = memory::try_frame_allocator() else {
+            return 0;
+        };
+        let mut released = 0;
+        while let Some(slab) = self.empty.head {
+            unsafe {
+                self.empty.remove(slab);
+                frame_allocator.deallocate_frame(slab.as_ref().frame);
+            }
+            released += 1;
+        }
+        released
+    }
+
+    /// Creates a new slab from a fresh frame and adds it to the empty list.
+    ///
+    /// # Returns
+    /// The new slab, or `None` if no frame is available or the frame allocator is held.
+    fn grow(&mut self) -> Option<NonNull<Slab>> {
+        let frame = memory::try_frame_allocator()?.allocate_frame()?;
+        let base: *mut u8 = memory::phys_to_virt(frame.start_address()).as_mut_ptr();
+
+        unsafe {
+            // Thread every object slot into the free list, lowest address first.
+            let mut free_list = None;
+            for i in (0..self.objects_per_slab).rev() {
+                let object = base.add(self.first_object_offset + i * self.object_size);
+                let object = object as *mut FreeObject;
+                object.write(FreeObject { next: free_list });
+                free_list = NonNull::new(object);
+            }
+
+            let slab_ptr = base as *mut Slab;
+            slab_ptr.write(Slab {
+                frame,
+                cache: self,
+                free_list,
+                in_use: 0,
+                prev: None,
+                next: None,
+            });
+
+            let slab = NonNull::new_unchecked(slab_ptr);
+            self.empty.push(slab);
+            Some(slab)
+        }
+    }
+
+    /// Returns the frame backing a slab to the frame allocator.
+    ///
+    /// # Returns
+    /// `false` if the frame allocator is held, in which case the slab is left untouched.
+    ///
+    /// # Safety
+    /// The slab must not be part of any list and none of its objects may be in use.
+    unsafe fn release(slab: NonNull<Slab>) -> bool {
+        let Some(mut frame_allocator) = memory::try_frame_allocator() else {
+            return false;
+        };
+        frame_allocator.deallocate_frame(slab.as_ref().frame);
+        true
+    }
+}
+
+/// Registers a cache so that it shows up in memory diagnostics.
+///
+/// # Panics
+/// Panics if `MAX_CACHES` caches are already registered.
+pub fn register(cache: &'static Locked<SlabCache>) {
+    let mut caches = CACHES.lock();
+    let slot = caches
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("too many slab caches registered");
+    *slot = Some(cache);
+}
+
+/// Calls `f` with the name and counters of every registered cache.
+pub fn for_each_cache(mut f: impl FnMut(&'static str, SlabStats)) {
+    let caches = *CACHES.lock();
+    for cache in caches.iter().flatten() {
+        let (name, stats) = {
+            let cache = cache.lock();
+            (cache.name(), cache.stats())
+        };
+        f(name, stats);
+    }
+}
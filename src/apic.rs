@@ -0,0 +1,265 @@
+use crate::acpi::{self, Madt};
+use crate::boot_splash::BootScreen;
+use crate::interrupts::{InterruptIndex, PICS, PIC_1_OFFSET};
+use crate::log::LogType;
+use crate::time;
+use conquer_once::spin::OnceCell;
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::{hlt, interrupts};
+
+pub mod ioapic;
+pub mod lapic;
+
+use ioapic::IoApic;
+use lapic::LocalApic;
+
+/// The vector the local APIC raises for spurious interrupts.
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+/// The number of legacy ISA IRQs.
+pub const ISA_IRQS: u8 = 16;
+
+/// The number of PIT ticks the local APIC timer is measured against.
+const CALIBRATION_TICKS: u64 = 10;
+
+/// The local APIC of the boot processor, once enabled.
+static LOCAL_APIC: OnceCell<LocalApic> = OnceCell::uninit();
+
+/// The I/O APIC handling the ISA IRQs, once enabled.
+static IO_APIC: OnceCell<Mutex<IoApic>> = OnceCell::uninit();
+
+/// The MADT the APICs were set up from, used to translate ISA IRQs.
+static MADT: OnceCell<Madt> = OnceCell::uninit();
+
+/// Set once interrupts are delivered through the APICs instead of the 8259 PICs.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// The local APIC timer's rate in cycles per second (bus clock divided by 16).
+static TIMER_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Switches interrupt delivery from the 8259 PICs to the local APIC and the I/O APIC.
+///
+/// The APICs are discovered through the ACPI MADT. The local APIC timer is calibrated against
+/// the PIT and takes over the timer interrupt at the current `time::frequency`; the ISA IRQs are
+/// routed through the I/O APIC to the vectors the PICs used, unmasked only if they have handlers
+/// registered with `interrupts::irq::register_irq`.
+/// The PICs and LINT0 are masked and those lines unmasked last, in one step with interrupts
+/// disabled. If the CPU has no APIC, no MADT is found, mapping the registers fails or the timer
+/// cannot be calibrated, LINT0 is restored and the PICs stay in charge.
+///
+/// Must be called after the memory globals are initialized, with interrupts enabled and the PIT
+/// running, since the calibration waits for PIT ticks.
+///
+/// # Returns
+/// `true` if the APICs are now in use.
+pub fn init() -> bool {
+    BootScreen::log(LogType::Info, "Initializing APIC");
+    match try_init() {
+        Ok(()) => {
+            BootScreen::log(LogType::Success, "APIC initialized, 8259 PICs masked");
+            true
+        }
+        Err(reason) => {
+            BootScreen::log(LogType::Failed, reason);
+            BootScreen::log(LogType::Info, "Falling back to the 8259 PICs");
+            false
+        }
+    }
+}
+
+/// Sets up the APICs, returning the reason if they cannot be used.
+fn try_init() -> Result<(), &'static str> {
+    if ENABLED.load(Ordering::Relaxed) {
+        return Err("APIC already initialized");
+    }
+    #[allow(unused_unsafe)]
+    let has_apic = unsafe { __cpuid(1).edx & (1 << 9) != 0 };
+    if !has_apic {
+        return Err("CPU has no local APIC");
+    }
+    let madt = acpi::find_madt().ok_or("no ACPI MADT found")?;
+    let io_apic_info = madt
+        .io_apics
+        .iter()
+        .flatten()
+        .find(|info| info.gsi_base == 0)
+        .ok_or("no I/O APIC for the ISA IRQs")?;
+
+    let local_apic = unsafe { LocalApic::init(madt.local_apic_address, SPURIOUS_VECTOR) }
+        .map_err(|_| "failed to map the local APIC")?;
+    // Until the PICs are masked their interrupts arrive through LINT0, which must keep working
+    // if the switch is abandoned.
+    let abandon = |reason| {
+        local_apic.restore_virtual_wire();
+        reason
+    };
+    let mut io_apic = unsafe { IoApic::init(io_apic_info.address, io_apic_info.gsi_base) }
+        .map_err(|_| abandon("failed to map the I/O APIC"))?;
+
+    let timer_hz = calibrate(&local_apic);
+    if timer_hz == 0 {
+        return Err(abandon("local APIC timer calibration failed"));
+    }
+    TIMER_HZ.store(timer_hz, Ordering::Relaxed);
+
+    // Every line starts masked, so that no interrupt arrives through the I/O APIC while the end
+    // of interrupts is still signalled to the PICs.
+    let destination = local_apic.id();
+    for irq in 0..ISA_IRQS {
+        // IRQ 2 is the cascade of the PICs and never raised.
+        if irq == 2 {
+            continue;
+        }
+        let (gsi, active_low, level_triggered) = madt.isa_irq(irq);
+        if io_apic.handles(gsi) {
+            io_apic.route(
+                gsi,
+                PIC_1_OFFSET + irq,
+                destination,
+                active_low,
+                level_triggered,
+                true,
+            );
+        }
+    }
+
+    MADT.try_init_once(|| madt)
+        .map_err(|_| abandon("APIC already initialized"))?;
+    IO_APIC
+        .try_init_once(|| Mutex::new(io_apic))
+        .map_err(|_| abandon("APIC already initialized"))?;
+    LOCAL_APIC
+        .try_init_once(|| local_apic)
+        .map_err(|_| "APIC already initialized")?;
+    let local_apic = LOCAL_APIC.try_get().unwrap();
+
+    interrupts::without_interrupts(|| {
+        unsafe { PICS.lock().disable() };
+        local_apic.mask_lint0();
+        ENABLED.store(true, Ordering::SeqCst);
+        let frequency = time::frequency();
+        let initial_count = timer_count_for(frequency);
+        local_apic.start_periodic_timer(InterruptIndex::Timer.as_u8(), initial_count);
+        time::set_tick_rate(frequency_for(initial_count), tick_nanos(initial_count));
+        // The timer line stays masked, the local APIC timer takes over its vector.
+        for irq in 1..ISA_IRQS {
+            if irq != 2 && crate::interrupts::irq::has_handlers(irq) {
+                set_isa_irq_masked(irq, false);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Measures the local APIC timer against `CALIBRATION_TICKS` PIT ticks.
+///
+/// # Returns
+/// The timer rate in cycles per second, or 0 if the timer did not count.
+fn calibrate(local_apic: &LocalApic) -> u64 {
+    // Start right after a tick, so the measurement spans whole ticks.
+    let start = time::ticks();
+    while time::ticks() == start {
+        hlt();
+    }
+    let uptime = time::uptime();
+    local_apic.start_calibration(u32::MAX);
+    let start = time::ticks();
+    while time::ticks() < start + CALIBRATION_TICKS {
+        hlt();
+    }
+    let cycles = (u32::MAX - local_apic.timer_count()) as u64;
+    let nanos = (time::uptime() - uptime).as_nanos() as u64;
+    local_apic.start_calibration(0);
+    if nanos == 0 {
+        return 0;
+    }
+    cycles * 1_000_000_000 / nanos
+}
+
+/// Returns the timer count that comes closest to interrupts at `hz`.
+fn timer_count_for(hz: u32) -> u32 {
+    let hz = hz.max(1) as u64;
+    ((TIMER_HZ.load(Ordering::Relaxed) + hz / 2) / hz).clamp(1, u32::MAX as u64) as u32
+}
+
+/// Returns the interrupt frequency of a timer period of `count` cycles, rounded to whole Hz.
+fn frequency_for(count: u32) -> u64 {
+    let count = count as u64;
+    (TIMER_HZ.load(Ordering::Relaxed) + count / 2) / count
+}
+
+/// Returns the length of a timer period of `count` cycles in nanoseconds.
+fn tick_nanos(count: u32) -> u64 {
+    count as u64 * 1_000_000_000 / TIMER_HZ.load(Ordering::Relaxed)
+}
+
+/// Returns `true` if interrupts are delivered through the APICs.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Returns the ID of the boot processor's local APIC, or `None` if the APIC is not in use.
+pub fn local_apic_id() -> Option<u8> {
+    LOCAL_APIC.try_get().ok().map(LocalApic::id)
+}
+
+/// Returns the local APIC timer's rate in cycles per second, or 0 if it is not in use.
+pub fn timer_frequency() -> u64 {
+    TIMER_HZ.load(Ordering::Relaxed)
+}
+
+/// Signals the end of an interrupt to the local APIC.
+///
+/// Called by the interrupt handlers instead of notifying the PICs once the APIC is enabled.
+pub fn end_of_interrupt() {
+    if let Ok(local_apic) = LOCAL_APIC.try_get() {
+        local_apic.end_of_interrupt();
+    }
+}
+
+/// Reprograms the local APIC timer to raise the timer interrupt at `hz`.
+///
+/// # Returns
+/// The programmed frequency, rounded to whole Hz.
+pub(crate) fn set_timer_frequency(hz: u32) -> u32 {
+    let local_apic = LOCAL_APIC.try_get().expect("local APIC not initialized");
+    let initial_count = timer_count_for(hz);
+    let actual = frequency_for(initial_count);
+    interrupts::without_interrupts(|| {
+        local_apic.start_periodic_timer(InterruptIndex::Timer.as_u8(), initial_count);
+        time::set_tick_rate(actual, tick_nanos(initial_count));
+    });
+    actual as u32
+}
+
+/// Masks or unmasks an ISA IRQ at the I/O APIC.
+///
+/// # Returns
+/// `false` if the APIC is not in use or the IRQ is not routed through the I/O APIC.
+pub fn set_isa_irq_masked(irq: u8, masked: bool) -> bool {
+    let (Ok(madt), Ok(io_apic)) = (MADT.try_get(), IO_APIC.try_get()) else {
+        return false;
+    };
+    let (gsi, _, _) = madt.isa_irq(irq);
+    interrupts::without_interrupts(|| {
+        let mut io_apic = io_apic.lock();
+        if !io_apic.handles(gsi) {
+            return false;
+        }
+        io_apic.set_masked(gsi, masked);
+        true
+    })
+}
+
+/// Returns whether an ISA IRQ is masked at the I/O APIC, or `None` if the APIC is not in use
+/// or the IRQ is not routed through the I/O APIC.
+pub fn is_isa_irq_masked(irq: u8) -> Option<bool> {
+    let (madt, io_apic) = (MADT.try_get().ok()?, IO_APIC.try_get().ok()?);
+    let (gsi, _, _) = madt.isa_irq(irq);
+    interrupts::without_interrupts(|| {
+        let mut io_apic = io_apic.lock();
+        io_apic.handles(gsi).then(|| io_apic.is_masked(gsi))
+    })
+}
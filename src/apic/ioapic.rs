@@ -0,0 +1,141 @@
+use crate::memory::{self, mmio::MmioRegion};
+use x86_64::structures::paging::{mapper::MapToError, Size4KiB};
+use x86_64::PhysAddr;
+
+/// The offset of the register select register.
+const REGISTER_SELECT: usize = 0x00;
+
+/// The offset of the register window.
+const REGISTER_WINDOW: usize = 0x10;
+
+/// The version register, holding the index of the last redirection entry in bits 16-23.
+const VERSION: u32 = 0x01;
+
+/// The register holding the low half of the first redirection entry.
+const REDIRECTION_TABLE: u32 = 0x10;
+
+/// The polarity bit of a redirection entry: set for active low.
+const ACTIVE_LOW: u32 = 1 << 13;
+
+/// The trigger mode bit of a redirection entry: set for level-triggered.
+const LEVEL_TRIGGERED: u32 = 1 << 14;
+
+/// The mask bit of a redirection entry.
+const MASKED: u32 = 1 << 16;
+
+/// An I/O APIC, routing global system interrupts to local APICs.
+#[derive(Debug)]
+pub struct IoApic {
+    registers: MmioRegion,
+    /// The first global system interrupt handled by this I/O APIC.
+    gsi_base: u32,
+    /// The number of redirection entries.
+    entries: u32,
+}
+
+impl IoApic {
+    /// Maps the I/O APIC registers and masks every redirection entry.
+    ///
+    /// # Arguments
+    /// * `address` - The physical address of the registers, as reported by the MADT.
+    /// * `gsi_base` - The first global system interrupt handled by this I/O APIC.
+    ///
+    /// # Safety
+    /// `address` must be the register page of an I/O APIC.
+    pub unsafe fn init(address: PhysAddr, gsi_base: u32) -> Result<Self, MapToError<Size4KiB>> {
+        let mut apic = IoApic {
+            registers: memory::map_mmio(address, 0x20)?,
+            gsi_base,
+            entries: 0,
+        };
+        apic.entries = ((apic.read(VERSION) >> 16) & 0xFF) + 1;
+        for index in 0..apic.entries {
+            apic.write(REDIRECTION_TABLE + 2 * index, MASKED);
+        }
+        Ok(apic)
+    }
+
+    /// Returns `true` if this I/O APIC handles the given global system interrupt.
+    pub fn handles(&self, gsi: u32) -> bool {
+        (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+    }
+
+    /// Routes a global system interrupt to `vector` on the local APIC `destination`.
+    ///
+    /// # Arguments
+    /// * `gsi` - The global system interrupt, which must be handled by this I/O APIC.
+    /// * `vector` - The vector to raise.
+    /// * `destination` - The ID of the local APIC to deliver to.
+    /// * `active_low` - `true` for active low polarity.
+    /// * `level_triggered` - `true` for level-triggered interrupts.
+    /// * `masked` - `true` to keep the interrupt masked for now.
+    pub fn route(
+        &mut self,
+        gsi: u32,
+        vector: u8,
+        destination: u8,
+        active_low: bool,
+        level_triggered: bool,
+        masked: bool,
+    ) {
+        let mut low = vector as u32;
+        if active_low {
+            low |= ACTIVE_LOW;
+        }
+        if level_triggered {
+            low |= LEVEL_TRIGGERED;
+        }
+        if masked {
+            low |= MASKED;
+        }
+        let register = self.register(gsi);
+        // Mask while the entry is half-written, so no interrupt goes to the wrong place.
+        self.write(register, MASKED);
+        self.write(register + 1, (destination as u32) << 24);
+        self.write(register, low);
+    }
+
+    /// Masks or unmasks a global system interrupt handled by this I/O APIC.
+    pub fn set_masked(&mut self, gsi: u32, masked: bool) {
+        let register = self.register(gsi);
+        let low = self.read(register);
+        self.write(register, if masked { low | MASKED } else { low & !MASKED });
+    }
+
+    /// Returns `true` if a global system interrupt handled by this I/O APIC is masked.
+    pub fn is_masked(&mut self, gsi: u32) -> bool {
+        let register = self.register(gsi);
+        self.read(register) & MASKED != 0
+    }
+
+    /// Returns the vector a global system interrupt handled by this I/O APIC is routed to.
+    pub fn vector(&mut self, gsi: u32) -> u8 {
+        let register = self.register(gsi);
+        self.read(register) as u8
+    }
+
+    /// Returns the register holding the low half of the redirection entry for `gsi`.
+    ///
+    /// # Panics
+    /// Panics if this I/O APIC does not handle `gsi`.
+    fn register(&self, gsi: u32) -> u32 {
+        assert!(
+            self.handles(gsi),
+            "GSI {} not handled by this I/O APIC",
+            gsi
+        );
+        REDIRECTION_TABLE + 2 * (gsi - self.gsi_base)
+    }
+
+    /// Reads an indirect register.
+    fn read(&mut self, register: u32) -> u32 {
+        self.registers.write_u32(REGISTER_SELECT, register);
+        self.registers.read_u32(REGISTER_WINDOW)
+    }
+
+    /// Writes an indirect register.
+    fn write(&mut self, register: u32, value: u32) {
+        self.registers.write_u32(REGISTER_SELECT, register);
+        self.registers.write_u32(REGISTER_WINDOW, value);
+    }
+}
@@ -0,0 +1,126 @@
+use crate::memory::{self, mmio::MmioRegion};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::{mapper::MapToError, Size4KiB};
+use x86_64::PhysAddr;
+
+/// The `IA32_APIC_BASE` model-specific register.
+const APIC_BASE_MSR: u32 = 0x1B;
+
+/// The global enable bit of `IA32_APIC_BASE`.
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+
+/// The size of the local APIC register page.
+const REGISTERS_SIZE: usize = 0x400;
+
+const ID: usize = 0x20;
+const TASK_PRIORITY: usize = 0x80;
+const EOI: usize = 0xB0;
+const SPURIOUS_VECTOR: usize = 0xF0;
+const LVT_TIMER: usize = 0x320;
+const LVT_LINT0: usize = 0x350;
+const LVT_LINT1: usize = 0x360;
+const LVT_ERROR: usize = 0x370;
+const TIMER_INITIAL_COUNT: usize = 0x380;
+const TIMER_CURRENT_COUNT: usize = 0x390;
+const TIMER_DIVIDE: usize = 0x3E0;
+
+/// The software enable bit of the spurious interrupt vector register.
+const SOFTWARE_ENABLE: u32 = 1 << 8;
+
+/// The mask bit of every local vector table entry.
+const LVT_MASKED: u32 = 1 << 16;
+
+/// The delivery mode of a local vector table entry passing on the 8259 PICs' interrupts.
+const DELIVERY_EXT_INT: u32 = 0b111 << 8;
+
+/// The delivery mode of a local vector table entry raising a non-maskable interrupt.
+const DELIVERY_NMI: u32 = 0b100 << 8;
+
+/// The periodic mode bit of the timer's local vector table entry.
+const TIMER_PERIODIC: u32 = 1 << 17;
+
+/// The timer divide configuration for dividing the bus clock by 16.
+const DIVIDE_BY_16: u32 = 0b0011;
+
+/// The local APIC of the current processor.
+#[derive(Debug)]
+pub struct LocalApic {
+    registers: MmioRegion,
+}
+
+impl LocalApic {
+    /// Maps the local APIC registers and enables the APIC with the timer, LINT1 and error
+    /// interrupts masked.
+    ///
+    /// LINT0 is left alone, since the 8259 PICs' interrupts arrive through it until
+    /// `mask_lint0` is called; calibrating the timer waits for them.
+    ///
+    /// # Arguments
+    /// * `address` - The physical address of the registers, as reported by the MADT.
+    /// * `spurious_vector` - The vector raised for spurious interrupts.
+    ///
+    /// # Safety
+    /// `address` must be the local APIC's register page, and an IDT handler must exist for
+    /// `spurious_vector`.
+    pub unsafe fn init(
+        address: PhysAddr,
+        spurious_vector: u8,
+    ) -> Result<Self, MapToError<Size4KiB>> {
+        let mut base = Msr::new(APIC_BASE_MSR);
+        base.write(base.read() | APIC_BASE_ENABLE);
+
+        let apic = LocalApic {
+            registers: memory::map_mmio(address, REGISTERS_SIZE)?,
+        };
+        for lvt in [LVT_TIMER, LVT_LINT1, LVT_ERROR] {
+            apic.registers.write_u32(lvt, LVT_MASKED);
+        }
+        apic.registers.write_u32(TASK_PRIORITY, 0);
+        apic.registers
+            .write_u32(SPURIOUS_VECTOR, SOFTWARE_ENABLE | spurious_vector as u32);
+        Ok(apic)
+    }
+
+    /// Masks LINT0, so that interrupts of the 8259 PICs no longer reach the processor.
+    pub fn mask_lint0(&self) {
+        self.registers.write_u32(LVT_LINT0, LVT_MASKED);
+    }
+
+    /// Restores virtual wire mode, with the 8259 PICs' interrupts passed on through LINT0 and
+    /// NMIs arriving through LINT1, for when the APICs are not taking over after all.
+    pub fn restore_virtual_wire(&self) {
+        self.registers.write_u32(LVT_LINT0, DELIVERY_EXT_INT);
+        self.registers.write_u32(LVT_LINT1, DELIVERY_NMI);
+    }
+
+    /// Returns the ID of this local APIC.
+    pub fn id(&self) -> u8 {
+        (self.registers.read_u32(ID) >> 24) as u8
+    }
+
+    /// Signals the end of the interrupt currently being handled.
+    pub fn end_of_interrupt(&self) {
+        self.registers.write_u32(EOI, 0);
+    }
+
+    /// Starts the timer counting down from `initial_count` with the interrupt masked, for
+    /// measuring its rate.
+    pub fn start_calibration(&self, initial_count: u32) {
+        self.registers.write_u32(TIMER_DIVIDE, DIVIDE_BY_16);
+        self.registers.write_u32(LVT_TIMER, LVT_MASKED);
+        self.registers.write_u32(TIMER_INITIAL_COUNT, initial_count);
+    }
+
+    /// Returns the current count of the timer.
+    pub fn timer_count(&self) -> u32 {
+        self.registers.read_u32(TIMER_CURRENT_COUNT)
+    }
+
+    /// Raises `vector` every `initial_count` timer cycles, at the bus clock divided by 16.
+    pub fn start_periodic_timer(&self, vector: u8, initial_count: u32) {
+        self.registers.write_u32(TIMER_DIVIDE, DIVIDE_BY_16);
+        self.registers
+            .write_u32(LVT_TIMER, TIMER_PERIODIC | vector as u32);
+        self.registers.write_u32(TIMER_INITIAL_COUNT, initial_count);
+    }
+}
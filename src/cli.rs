@@ -1,9 +1,15 @@
-use crate::{println, vga_buffer::WRITER};
+use crate::allocator::{self, fixed_size_block::BLOCK_SIZES, slab};
+use crate::memory::walk::MappedRange;
+use crate::{memory, print, println, time, vga_buffer::WRITER};
 use alloc::string::String;
+use alloc::vec;
 use conquer_once::spin::OnceCell;
+use core::fmt;
 use core::sync::atomic::AtomicBool;
 use spin::Mutex;
 use x86_64::instructions::port::Port;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
 
 /// A static once-initialized buffer for storing the inputted commands.
 pub static COMMAND_BUFFER: OnceCell<Mutex<String>> = OnceCell::uninit();
@@ -27,7 +33,6 @@ pub fn init_cli() {
 ///
 /// The CLI runs in a loop, continually waiting for and processing commands until a command is processed.
 pub async fn cli() {
-    use crate::print;
     use crate::task::keyboard::ScancodeStream;
     use futures_util::stream::StreamExt;
     use pc_keyboard::{layouts, DecodedKey, HandleControl, Keyboard, ScancodeSet1};
@@ -91,14 +96,29 @@ pub async fn cli() {
 /// - `help` shows the available commands.
 /// - `hello` prints "Hello, World!".
 /// - `clear` clears the screen.
+/// - `meminfo` shows heap, physical memory and swap usage.
+/// - `vmmap` lists the mapped virtual address ranges.
+/// - `translate <addr>` shows how a virtual address is translated.
+/// - `uptime` shows the time since boot.
+/// - `irqs` shows the interrupt counts and handlers of each IRQ line.
+/// - `membench` measures the memory copy and fill routines.
 /// - `shutdown` shuts down the system.
 fn parse(buffer: &str) {
-    match buffer.trim() {
+    let mut words = buffer.split_whitespace();
+    let command = words.next().unwrap_or("");
+
+    match command {
         "help" => {
             println!("Available commands:");
             println!("  help     - Show this help menu");
             println!("  hello    - Print 'Hello, World!'");
             println!("  clear    - Clear the screen");
+            println!("  meminfo  - Show heap and physical memory usage");
+            println!("  vmmap    - List mapped virtual address ranges");
+            println!("  translate <addr> - Show the page table walk for an address");
+            println!("  uptime   - Show the time since boot");
+            println!("  irqs     - Show interrupt counts per IRQ line");
+            println!("  membench - Benchmark memcpy, memmove and memset");
             println!("  shutdown - Power off the system");
         }
         "hello" => {
@@ -108,6 +128,12 @@ fn parse(buffer: &str) {
             let mut writer = WRITER.lock();
             writer.clear_screen();
         }
+        "meminfo" => meminfo(),
+        "vmmap" => vmmap(),
+        "translate" => translate(words.next()),
+        "uptime" => uptime(),
+        "irqs" => irqs(),
+        "membench" => membench(),
         "shutdown" => {
             println!("Shutting down...");
             unsafe {
@@ -122,3 +148,297 @@ fn parse(buffer: &str) {
         }
     }
 }
+
+/// Prints heap allocator counters and physical frame usage.
+fn meminfo() {
+    let heap = allocator::heap_info();
+    println!("Heap ({} allocator):", allocator::HEAP_ALLOCATOR_NAME);
+    println!(
+        "  size        {} KiB (max {} KiB)",
+        heap.heap_size / 1024,
+        allocator::HEAP_MAX_SIZE / 1024
+    );
+    println!(
+        "  allocated   {} bytes (peak {} bytes)",
+        heap.stats.allocated_bytes, heap.stats.peak_bytes
+    );
+    println!("  live        {} allocations", heap.stats.live_allocations);
+    println!("  free        {} bytes", heap.free_bytes);
+    if let Some(free_blocks) = heap.free_blocks {
+        print!("  free blocks");
+        for (size, count) in BLOCK_SIZES.iter().zip(free_blocks.iter()) {
+            print!(" {}:{}", size, count);
+        }
+        println!();
+    }
+    if let Some(err) = heap.last_grow_error {
+        println!("  last growth failure: {}", err);
+    }
+    println!(
+        "  reserve     {} / {} bytes{}",
+        allocator::reserve::remaining(),
+        allocator::reserve::RESERVE_SIZE,
+        if allocator::reserve::is_released() {
+            " (released)"
+        } else {
+            ""
+        }
+    );
+
+    println!("Slab caches:");
+    slab::for_each_cache(|name, stats| {
+        println!(
+            "  {:<12}{} x {} bytes in use, {} partial / {} full / {} empty slabs",
+            name,
+            stats.objects_in_use,
+            stats.object_size,
+            stats.partial_slabs,
+            stats.full_slabs,
+            stats.empty_slabs
+        );
+    });
+
+    let (total, used, free) = {
+        let frame_allocator = memory::frame_allocator();
+        (
+            frame_allocator.total_frames(),
+            frame_allocator.used_frames(),
+            frame_allocator.free_frames(),
+        )
+    };
+    println!("Physical memory:");
+    println!("  total       {} frames ({} KiB)", total, total * 4);
+    println!("  used        {} frames ({} KiB)", used, used * 4);
+    println!("  free        {} frames ({} KiB)", free, free * 4);
+
+    let swap = memory::swap::stats();
+    if swap.slots > 0 {
+        println!("Swap:");
+        println!("  slots       {} / {} in use", swap.used_slots, swap.slots);
+        println!(
+            "  pages       {} out, {} in, {} dropped",
+            swap.swapped_out, swap.swapped_in, swap.dropped
+        );
+    }
+}
+
+/// Prints every mapped range of the active page table.
+fn vmmap() {
+    println!(
+        "{:<18} {:<18} {:>4} {:>8} flags",
+        "start", "end", "page", "pages"
+    );
+    // Copy the ranges in batches, so that the mapper is not locked while printing.
+    let mut batch = [MappedRange {
+        start: VirtAddr::zero(),
+        end: VirtAddr::zero(),
+        page_size: 0,
+        flags: PageTableFlags::empty(),
+    }; 32];
+    let mut from = VirtAddr::zero();
+    loop {
+        let copied = memory::walk::copy_mapped_ranges(from, &mut batch);
+        for range in &batch[..copied] {
+            println!(
+                "{:#018x} {:#018x} {:>4} {:>8} {}",
+                range.start.as_u64(),
+                range.end.as_u64(),
+                page_size_name(range.page_size),
+                range.pages(),
+                FlagString(range.flags)
+            );
+        }
+        if copied < batch.len() {
+            break;
+        }
+        from = batch[copied - 1].end;
+    }
+}
+
+/// Prints the page table entries used to translate the given address.
+///
+/// # Arguments
+/// * `arg` - The address as hexadecimal (with `0x` prefix) or decimal number.
+fn translate(arg: Option<&str>) {
+    let Some(arg) = arg else {
+        println!("Usage: translate <addr>");
+        return;
+    };
+    let parsed = match arg.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+    let Some(addr) = parsed.ok().and_then(|addr| VirtAddr::try_new(addr).ok()) else {
+        println!("Invalid address: {}", arg);
+        return;
+    };
+
+    let translation = memory::walk::translate(addr);
+    for entry in translation.entries.iter().flatten() {
+        println!(
+            "  P{} [{:>3}] {:#014x} {}",
+            entry.level,
+            u16::from(entry.index),
+            entry.addr.as_u64(),
+            FlagString(entry.flags)
+        );
+    }
+    match (translation.phys, translation.page_size, translation.flags) {
+        (Some(phys), Some(size), Some(flags)) => println!(
+            "{:#x} -> {:#x} ({} page, effective {})",
+            addr.as_u64(),
+            phys.as_u64(),
+            page_size_name(size),
+            FlagString(flags)
+        ),
+        _ => println!("{:#x} is not mapped", addr.as_u64()),
+    }
+}
+
+/// Prints the time since boot and the timer tick count.
+fn uptime() {
+    let uptime = time::uptime();
+    let secs = uptime.as_secs();
+    print!("up ");
+    if secs >= 86_400 {
+        print!("{}d ", secs / 86_400);
+    }
+    println!(
+        "{:02}:{:02}:{:02}.{:03} ({} ticks at {} Hz)",
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        uptime.subsec_millis(),
+        time::ticks(),
+        time::frequency()
+    );
+}
+
+/// Prints the interrupt count, unclaimed interrupts and state of every IRQ line.
+fn irqs() {
+    use crate::interrupts::irq;
+
+    let controller = if crate::apic::is_enabled() {
+        "I/O APIC"
+    } else {
+        "8259 PIC"
+    };
+    println!("IRQ lines ({}):", controller);
+    for line in 0..irq::IRQ_LINES {
+        let count = irq::interrupt_count(line);
+        if count == 0 && !irq::has_handlers(line) {
+            continue;
+        }
+        println!(
+            "  {:>2}: {:>10} interrupts, {:>6} unclaimed, {}, {}",
+            line,
+            count,
+            irq::unhandled_count(line),
+            if irq::has_handlers(line) {
+                "handled"
+            } else {
+                "no handler"
+            },
+            if irq::is_masked(line) {
+                "masked"
+            } else {
+                "unmasked"
+            }
+        );
+    }
+    println!("  Timer: {} ticks", time::ticks());
+}
+
+/// Measures the memory routines against naive byte loops, in CPU cycles per KiB.
+fn membench() {
+    use core::arch::x86_64::_rdtsc;
+    use core::ptr;
+
+    const ROUNDS: u64 = 16;
+
+    /// Runs `f` `ROUNDS` times and returns the average cycles per KiB of `len` bytes.
+    fn measure(len: usize, mut f: impl FnMut()) -> u64 {
+        let start = unsafe { _rdtsc() };
+        for _ in 0..ROUNDS {
+            f();
+        }
+        let cycles = unsafe { _rdtsc() } - start;
+        cycles * 1024 / (ROUNDS * len as u64)
+    }
+
+    println!("ERMS: {}", if memory::has_erms() { "yes" } else { "no" });
+    println!(
+        "{:>8} {:>10} {:>10} {:>10} {:>10}",
+        "size", "memcpy", "memmove", "memset", "byte loop"
+    );
+    for len in [64, 4096, 65536] {
+        let src = vec![0x5Au8; len];
+        let mut dest = vec![0u8; len + 1];
+        let (src, dest) = (src.as_ptr(), dest.as_mut_ptr());
+
+        let copy = measure(len, || unsafe {
+            memory::memcpy(dest, src, len);
+        });
+        // Shifting by one byte forces the overlap-safe backward path.
+        let moved = measure(len, || unsafe {
+            memory::memmove(dest.wrapping_add(1), dest, len);
+        });
+        let fill = measure(len, || unsafe {
+            memory::memset(dest, 0xA5, len);
+        });
+        // Volatile accesses keep the compiler from turning the loop into a `memcpy` call.
+        let bytes = measure(len, || unsafe {
+            for i in 0..len {
+                ptr::write_volatile(dest.add(i), ptr::read_volatile(src.add(i)));
+            }
+        });
+
+        println!(
+            "{:>8} {:>10} {:>10} {:>10} {:>10}",
+            len, copy, moved, fill, bytes
+        );
+    }
+    println!("(cycles per KiB)");
+}
+
+/// Returns a short name for a page size.
+fn page_size_name(size: u64) -> &'static str {
+    match size {
+        0x1000 => "4K",
+        0x20_0000 => "2M",
+        0x4000_0000 => "1G",
+        _ => "?",
+    }
+}
+
+/// Formats page table flags as `p`/`w`/`u`/`x` columns plus cache attributes.
+struct FlagString(PageTableFlags);
+
+impl fmt::Display for FlagString {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flags = self.0;
+        let column = |flag, c| if flags.contains(flag) { c } else { '-' };
+        write!(
+            f,
+            "{}{}{}{}",
+            column(PageTableFlags::PRESENT, 'p'),
+            column(PageTableFlags::WRITABLE, 'w'),
+            column(PageTableFlags::USER_ACCESSIBLE, 'u'),
+            if flags.contains(PageTableFlags::NO_EXECUTE) {
+                '-'
+            } else {
+                'x'
+            }
+        )?;
+        if flags.contains(PageTableFlags::NO_CACHE) {
+            write!(f, " uc")?;
+        }
+        if flags.contains(PageTableFlags::WRITE_THROUGH) {
+            write!(f, " wt")?;
+        }
+        if flags.contains(PageTableFlags::GLOBAL) {
+            write!(f, " g")?;
+        }
+        Ok(())
+    }
+}
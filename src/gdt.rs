@@ -1,28 +1,45 @@
+use core::cell::UnsafeCell;
 use lazy_static::lazy_static;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
+use x86_64::structures::paging::{PageSize, Size4KiB};
 use x86_64::structures::tss::TaskStateSegment;
 use x86_64::VirtAddr;
 
 use crate::boot_splash::BootScreen;
 use crate::log::LogType;
+use crate::memory;
 
 /// The index of the Double Fault handler in the Interrupt Stack Table (IST).
 /// This is used to define the stack for the Double Fault interrupt.
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
+/// The size of the double fault stack, both the boot-time one and the guarded one set up by
+/// `init_guarded_stacks`.
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+
+/// The Task State Segment, wrapped so that its interrupt stacks can be replaced once it is loaded.
+///
+/// No reference to the inner TSS is ever created: the GDT descriptor and `init_guarded_stacks`
+/// only use the raw pointer returned by `UnsafeCell::get`.
+struct Tss(UnsafeCell<TaskStateSegment>);
+
+// The TSS is only written by `init_guarded_stacks`, before any interrupt stack switch can
+// observe it.
+unsafe impl Sync for Tss {}
+
 lazy_static! {
-    static ref TSS: TaskStateSegment = {
+    static ref TSS: Tss = {
         let mut tss = TaskStateSegment::new();
         tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+            static mut STACK: [u8; DOUBLE_FAULT_STACK_SIZE] = [0; DOUBLE_FAULT_STACK_SIZE];
 
-            // The stack pointer for the Double Fault interrupt, pointing to the top of the stack.
+            // The boot-time stack for the Double Fault interrupt, used until
+            // `init_guarded_stacks` replaces it. It points to the top of the stack.
             let stack_start = VirtAddr::from_ptr(&raw const STACK);
 
-            stack_start + STACK_SIZE
+            stack_start + DOUBLE_FAULT_STACK_SIZE
         };
-        tss
+        Tss(UnsafeCell::new(tss))
     };
 }
 
@@ -31,8 +48,10 @@ lazy_static! {
         let mut gdt = GlobalDescriptorTable::new();
         // Add a code segment descriptor to the GDT
         let code_selector = gdt.add_entry(Descriptor::kernel_code_segment());
-        // Add a TSS descriptor to the GDT, linking it with the TSS instance
-        let tss_selector = gdt.add_entry(Descriptor::tss_segment(&TSS));
+        // Add a TSS descriptor to the GDT, linking it with the TSS instance. The TSS lives in a
+        // static and is never moved, so the pointer stays valid.
+        let tss_selector =
+            gdt.add_entry(unsafe { Descriptor::tss_segment_unchecked(TSS.0.get()) });
         (
             gdt,
             Selectors {
@@ -75,3 +94,31 @@ pub fn init() {
         BootScreen::log(LogType::Success, "Task State Segment loaded successfully");
     }
 }
+
+/// Moves the double fault handler onto a stack protected by a guard page and registers the
+/// guard page of the current kernel stack.
+///
+/// Both stacks are then recognised by the fault handlers, which report an overflow as
+/// "stack overflow in <stack name>". The memory globals must be initialized before.
+///
+/// # Panics
+/// Panics if the double fault stack cannot be allocated.
+pub fn init_guarded_stacks() {
+    BootScreen::log(LogType::Info, "Allocating guarded kernel stacks");
+    let pages = DOUBLE_FAULT_STACK_SIZE as u64 / Size4KiB::SIZE;
+    let stack = memory::stack::alloc("double fault", pages)
+        .expect("failed to allocate the double fault stack");
+    // Write through the raw pointer; no reference to the TSS exists that this could alias.
+    let tss = TSS.0.get();
+    unsafe {
+        (*tss).interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = stack.top();
+    }
+
+    if memory::stack::register_current("kernel").is_none() {
+        BootScreen::log(
+            LogType::Failed,
+            "No guard page found below the kernel stack",
+        );
+    }
+    BootScreen::log(LogType::Success, "Guarded kernel stacks installed");
+}
@@ -0,0 +1,490 @@
+use crate::gdt::DOUBLE_FAULT_IST_INDEX;
+use crate::memory;
+use crate::serial::SERIAL1;
+use crate::vga_buffer::{Writer, WRITER};
+use core::arch::global_asm;
+use core::fmt::{self, Write};
+use spin::MutexGuard;
+use uart_16550::SerialPort;
+use x86_64::registers::control::{Cr0, Cr2, Cr3, Cr4};
+use x86_64::structures::idt::{InterruptDescriptorTable, PageFaultErrorCode, SelectorErrorCode};
+use x86_64::VirtAddr;
+
+/// The number of exception vectors reserved by the architecture.
+pub const EXCEPTION_VECTORS: usize = 32;
+
+/// The vector of the debug exception (#DB).
+const DEBUG: u8 = 1;
+/// The vector of the non-maskable interrupt.
+const NON_MASKABLE_INTERRUPT: u8 = 2;
+/// The vector of the breakpoint exception (#BP).
+const BREAKPOINT: u8 = 3;
+/// The vector of the double fault (#DF).
+const DOUBLE_FAULT: u8 = 8;
+/// The vector of the page fault (#PF).
+const PAGE_FAULT: u8 = 14;
+/// The vector of the control protection exception (#CP).
+const CONTROL_PROTECTION: u8 = 21;
+
+/// The mnemonic and name of each exception vector, or `None` for reserved vectors.
+const EXCEPTIONS: [Option<(&str, &str)>; EXCEPTION_VECTORS] = [
+    Some(("#DE", "DIVIDE ERROR")),
+    Some(("#DB", "DEBUG")),
+    Some(("NMI", "NON-MASKABLE INTERRUPT")),
+    Some(("#BP", "BREAKPOINT")),
+    Some(("#OF", "OVERFLOW")),
+    Some(("#BR", "BOUND RANGE EXCEEDED")),
+    Some(("#UD", "INVALID OPCODE")),
+    Some(("#NM", "DEVICE NOT AVAILABLE")),
+    Some(("#DF", "DOUBLE FAULT")),
+    Some(("CSO", "COPROCESSOR SEGMENT OVERRUN")),
+    Some(("#TS", "INVALID TSS")),
+    Some(("#NP", "SEGMENT NOT PRESENT")),
+    Some(("#SS", "STACK-SEGMENT FAULT")),
+    Some(("#GP", "GENERAL PROTECTION FAULT")),
+    Some(("#PF", "PAGE FAULT")),
+    None,
+    Some(("#MF", "X87 FLOATING-POINT EXCEPTION")),
+    Some(("#AC", "ALIGNMENT CHECK")),
+    Some(("#MC", "MACHINE CHECK")),
+    Some(("#XM", "SIMD FLOATING-POINT EXCEPTION")),
+    Some(("#VE", "VIRTUALIZATION EXCEPTION")),
+    Some(("#CP", "CONTROL PROTECTION EXCEPTION")),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    Some(("#HV", "HYPERVISOR INJECTION EXCEPTION")),
+    Some(("#VC", "VMM COMMUNICATION EXCEPTION")),
+    Some(("#SX", "SECURITY EXCEPTION")),
+    None,
+];
+
+/// The state of the CPU when an exception was raised, as saved by the exception stubs.
+///
+/// The general-purpose registers are pushed by the stubs, the vector and error code (or 0 for
+/// exceptions without one) just before them, and the rest by the CPU when delivering the
+/// exception. The kernel is built without SSE, so no vector registers need saving.
+///
+/// The fields are laid out from the lowest address up, so their order must be the reverse of
+/// the push order in the `global_asm!` entry stubs below.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct ExceptionFrame {
+    /// The general-purpose registers, saved by the common entry, `r15` last.
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    /// The exception vector.
+    pub vector: u64,
+    /// The error code pushed by the CPU, or 0 for exceptions without one.
+    pub error_code: u64,
+    /// The interrupted instruction, code segment and flags, pushed by the CPU.
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    /// The interrupted stack, pushed by the CPU.
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+// One stub per exception vector. Each pushes a zero error code if the CPU does not push one,
+// then the vector, and jumps to the common entry, which saves the general-purpose registers and
+// calls `exception_dispatch` with a pointer to the resulting `ExceptionFrame`. If the dispatcher
+// returns, the registers are restored and the interrupted code resumes. The CPU aligns the stack
+// before pushing its frame, but the entry realigns it anyway so it can be reached from anywhere.
+global_asm!(
+    r#"
+.macro exception_stub vector, error_code
+exception_stub_\vector:
+.if \error_code == 0
+    push 0
+.endif
+    push \vector
+    jmp exception_entry
+.endm
+
+.pushsection .text.exception_stubs, "ax"
+.irp vector, 0,1,2,3,4,5,6,7,9,15,16,18,19,20,22,23,24,25,26,27,28,31
+    exception_stub \vector, 0
+.endr
+.irp vector, 8,10,11,12,13,14,17,21,29,30
+    exception_stub \vector, 1
+.endr
+
+exception_entry:
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+    cld
+    mov rdi, rsp
+    mov rbx, rsp
+    and rsp, -16
+    call {dispatch}
+    mov rsp, rbx
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+    add rsp, 16
+    iretq
+.popsection
+
+.pushsection .rodata.exception_stubs, "a"
+.balign 8
+.global marcel_os_exception_stubs
+marcel_os_exception_stubs:
+.irp vector, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31
+    .quad exception_stub_\vector
+.endr
+.popsection
+"#,
+    dispatch = sym exception_dispatch,
+);
+
+extern "C" {
+    /// The addresses of the exception stubs, indexed by vector.
+    #[link_name = "marcel_os_exception_stubs"]
+    static EXCEPTION_STUBS: [u64; EXCEPTION_VECTORS];
+}
+
+/// Points every architecturally defined exception vector of `idt` at its stub.
+///
+/// The double fault runs on its own interrupt stack, so that overflowing the kernel stack is
+/// reported instead of causing a triple fault.
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    let stub = |vector: usize| VirtAddr::new(unsafe { EXCEPTION_STUBS[vector] });
+    unsafe {
+        idt.divide_error.set_handler_addr(stub(0));
+        idt.debug.set_handler_addr(stub(1));
+        idt.non_maskable_interrupt.set_handler_addr(stub(2));
+        idt.breakpoint.set_handler_addr(stub(3));
+        idt.overflow.set_handler_addr(stub(4));
+        idt.bound_range_exceeded.set_handler_addr(stub(5));
+        idt.invalid_opcode.set_handler_addr(stub(6));
+        idt.device_not_available.set_handler_addr(stub(7));
+        idt.double_fault
+            .set_handler_addr(stub(8))
+            .set_stack_index(DOUBLE_FAULT_IST_INDEX);
+        idt[9].set_handler_addr(stub(9));
+        idt.invalid_tss.set_handler_addr(stub(10));
+        idt.segment_not_present.set_handler_addr(stub(11));
+        idt.stack_segment_fault.set_handler_addr(stub(12));
+        idt.general_protection_fault.set_handler_addr(stub(13));
+        idt.page_fault.set_handler_addr(stub(14));
+        idt.x87_floating_point.set_handler_addr(stub(16));
+        idt.alignment_check.set_handler_addr(stub(17));
+        idt.machine_check.set_handler_addr(stub(18));
+        idt.simd_floating_point.set_handler_addr(stub(19));
+        idt.virtualization.set_handler_addr(stub(20));
+        idt.cp_protection_exception.set_handler_addr(stub(21));
+        idt.hv_injection_exception.set_handler_addr(stub(28));
+        idt.vmm_communication_exception.set_handler_addr(stub(29));
+        idt.security_exception.set_handler_addr(stub(30));
+    }
+}
+
+/// Returns the mnemonic and name of an exception vector, such as `("#GP", "GENERAL PROTECTION
+/// FAULT")`, or `None` if the vector is reserved or not an exception.
+pub fn exception_name(vector: u8) -> Option<(&'static str, &'static str)> {
+    EXCEPTIONS.get(vector as usize).copied().flatten()
+}
+
+/// Returns `true` if the CPU pushes an error code for the exception vector.
+pub fn has_error_code(vector: u8) -> bool {
+    matches!(vector, 8 | 10..=14 | 17 | 21 | 29 | 30)
+}
+
+/// Returns `true` if the error code of the exception vector references a segment selector.
+pub fn has_selector_error_code(vector: u8) -> bool {
+    matches!(vector, 10..=13)
+}
+
+/// A human-readable decoding of an exception's error code, created by `decode_error_code`.
+pub struct DecodedErrorCode {
+    vector: u8,
+    error_code: u64,
+}
+
+/// Decodes the error code of an exception for a crash report.
+///
+/// Selector error codes are shown as the table and index of the faulting selector, page fault
+/// error codes as their flags and control protection error codes by the kind of violation.
+pub fn decode_error_code(vector: u8, error_code: u64) -> DecodedErrorCode {
+    DecodedErrorCode { vector, error_code }
+}
+
+impl fmt::Display for DecodedErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let code = self.error_code;
+        if has_selector_error_code(self.vector) {
+            let selector = SelectorErrorCode::new_truncate(code);
+            if selector.is_null() {
+                return f.write_str("no selector");
+            }
+            write!(
+                f,
+                "{:?} selector index {}",
+                selector.descriptor_table(),
+                selector.index()
+            )?;
+            if selector.external() {
+                f.write_str(", external event")?;
+            }
+            return Ok(());
+        }
+        match self.vector {
+            PAGE_FAULT => write!(f, "{:?}", PageFaultErrorCode::from_bits_truncate(code)),
+            CONTROL_PROTECTION => {
+                let kind = match code & 0x7FFF {
+                    1 => "near RET",
+                    2 => "far RET or IRET",
+                    3 => "missing ENDBRANCH",
+                    4 => "RSTORSSP",
+                    5 => "SETSSBSY",
+                    _ => "unknown violation",
+                };
+                f.write_str(kind)?;
+                if code & (1 << 15) != 0 {
+                    f.write_str(", in enclave")?;
+                }
+                Ok(())
+            }
+            _ if has_error_code(self.vector) => write!(f, "{:#x}", code),
+            _ => f.write_str("none"),
+        }
+    }
+}
+
+/// Handles an exception raised by the CPU. Called by the common exception entry.
+///
+/// Debug exceptions, non-maskable interrupts and breakpoints are reported if the output is not
+/// locked, and execution resumes. Page faults resolved by swapping, demand paging or
+/// copy-on-write resume silently. Every other exception is fatal and ends in `crash`.
+extern "C" fn exception_dispatch(frame: &mut ExceptionFrame) {
+    // Read CR2 first, before a nested page fault can overwrite it.
+    let fault_address = VirtAddr::new_truncate(Cr2::read_raw());
+    let vector = frame.vector as u8;
+    match vector {
+        DEBUG | NON_MASKABLE_INTERRUPT | BREAKPOINT => {
+            report_resumable(frame, fault_address);
+            return;
+        }
+        PAGE_FAULT if resolve_page_fault(frame.error_code, fault_address) => return,
+        _ => {}
+    }
+
+    if matches!(vector, PAGE_FAULT | DOUBLE_FAULT) {
+        if let Some(name) = memory::stack::guard_page_owner(fault_address) {
+            crash(
+                frame,
+                fault_address,
+                Some(format_args!("stack overflow in {}", name)),
+            )
+        }
+    }
+    crash(frame, fault_address, None)
+}
+
+/// Tries to resolve a page fault without involving the faulting code.
+///
+/// Not-present faults on kernel addresses whose level 4 entry is missing from the active address
+/// space are resolved by copying the entry, those on swapped-out pages by reading the page back
+/// in, those inside a registered virtual memory area by mapping a fresh frame, and write faults
+/// on copy-on-write pages by giving the page a private copy. If that fails while no frame is
+/// left, cold pages are swapped out and the fault is resolved again.
+///
+/// # Returns
+/// `true` if the faulting instruction can be retried.
+fn resolve_page_fault(error_code: u64, address: VirtAddr) -> bool {
+    let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
+    try_resolve_page_fault(error_code, address)
+        || (memory::swap::relieve_pressure() && try_resolve_page_fault(error_code, address))
+}
+
+/// Tries each way of resolving a page fault once, for `resolve_page_fault`.
+fn try_resolve_page_fault(error_code: PageFaultErrorCode, address: VirtAddr) -> bool {
+    if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && (memory::address_space::sync_kernel_entry(address)
+            || memory::swap::handle_page_fault(address)
+            || memory::vma::handle_page_fault(address))
+    {
+        return true;
+    }
+    error_code
+        .contains(PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE)
+        && memory::cow::handle_page_fault(address)
+}
+
+/// Reports a fatal exception on the screen and the serial port, then panics.
+///
+/// The panic message is `EXCEPTION: <name>`, followed by `detail` on a new line if given, so
+/// that panic handlers can tell exceptions apart.
+fn crash(frame: &ExceptionFrame, fault_address: VirtAddr, detail: Option<fmt::Arguments>) -> ! {
+    report(frame, fault_address, detail);
+    let (_, name) = exception_name(frame.vector as u8).unwrap_or(("", "RESERVED"));
+    match detail {
+        Some(detail) => panic!("EXCEPTION: {}\n{}", name, detail),
+        None => panic!("EXCEPTION: {}", name),
+    }
+}
+
+/// Writes the report of an exception that execution resumes from to the screen and the serial
+/// port.
+///
+/// The output locks are only tried and the report is skipped if the interrupted code holds one
+/// of them: forcing them open would interleave the report with output that the interrupted code
+/// carries on writing.
+fn report_resumable(frame: &ExceptionFrame, fault_address: VirtAddr) {
+    let (Some(vga), Some(serial)) = (WRITER.try_lock(), SERIAL1.try_lock()) else {
+        return;
+    };
+    let _ = write_report(&mut HeldWriter { vga, serial }, frame, fault_address, None);
+}
+
+/// Writes the crash report of an exception to the screen and the serial port.
+///
+/// The report holds the exception's name and vector, the decoded error code, `detail` if any,
+/// and the saved registers along with the control registers.
+fn report(frame: &ExceptionFrame, fault_address: VirtAddr, detail: Option<fmt::Arguments>) {
+    // The exception may have interrupted code holding the output locks, which would never be
+    // released. Interrupts are disabled in exception handlers, so nothing else can hold them.
+    unsafe {
+        if WRITER.try_lock().is_none() {
+            WRITER.force_unlock();
+        }
+        if SERIAL1.try_lock().is_none() {
+            SERIAL1.force_unlock();
+        }
+    }
+    let _ = write_report(&mut CrashWriter, frame, fault_address, detail);
+}
+
+/// Formats a crash report.
+fn write_report(
+    out: &mut impl Write,
+    frame: &ExceptionFrame,
+    fault_address: VirtAddr,
+    detail: Option<fmt::Arguments>,
+) -> fmt::Result {
+    let vector = frame.vector as u8;
+    let (mnemonic, name) = exception_name(vector).unwrap_or(("", "RESERVED"));
+    writeln!(out, "EXCEPTION: {} ({} vector {})", name, mnemonic, vector)?;
+    if has_error_code(vector) {
+        writeln!(
+            out,
+            "Error code: {:#x} ({})",
+            frame.error_code,
+            decode_error_code(vector, frame.error_code)
+        )?;
+    }
+    if vector == PAGE_FAULT {
+        writeln!(out, "Accessed address: {:#x}", fault_address.as_u64())?;
+    }
+    if let Some(detail) = detail {
+        writeln!(out, "{}", detail)?;
+    }
+
+    writeln!(
+        out,
+        "RIP={:#018x} CS={:#06x} RFLAGS={:#010x}",
+        frame.rip, frame.cs, frame.rflags
+    )?;
+    writeln!(out, "RSP={:#018x} SS={:#06x}", frame.rsp, frame.ss)?;
+    let registers = [
+        ("RAX", frame.rax),
+        ("RBX", frame.rbx),
+        ("RCX", frame.rcx),
+        ("RDX", frame.rdx),
+        ("RSI", frame.rsi),
+        ("RDI", frame.rdi),
+        ("RBP", frame.rbp),
+        ("R8 ", frame.r8),
+        ("R9 ", frame.r9),
+        ("R10", frame.r10),
+        ("R11", frame.r11),
+        ("R12", frame.r12),
+        ("R13", frame.r13),
+        ("R14", frame.r14),
+        ("R15", frame.r15),
+    ];
+    for line in registers.chunks(3) {
+        for (i, (name, value)) in line.iter().enumerate() {
+            let separator = if i + 1 == line.len() { "\n" } else { " " };
+            write!(out, "{}={:#018x}{}", name, value, separator)?;
+        }
+    }
+    let (level_4_frame, _) = Cr3::read_raw();
+    writeln!(
+        out,
+        "CR0={:#010x} CR2={:#018x}",
+        Cr0::read_raw(),
+        fault_address.as_u64()
+    )?;
+    writeln!(
+        out,
+        "CR3={:#018x} CR4={:#010x}",
+        level_4_frame.start_address().as_u64(),
+        Cr4::read_raw()
+    )
+}
+
+/// Writes to both the VGA buffer and the first serial port through locks that are already held.
+struct HeldWriter<'a> {
+    vga: MutexGuard<'a, Writer>,
+    serial: MutexGuard<'a, SerialPort>,
+}
+
+impl Write for HeldWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.vga.write_string(s);
+        self.serial.write_str(s)
+    }
+}
+
+/// Writes to both the VGA buffer and the first serial port.
+struct CrashWriter;
+
+impl Write for CrashWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        WRITER.lock().write_string(s);
+        SERIAL1.lock().write_str(s)
+    }
+}
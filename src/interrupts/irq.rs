@@ -0,0 +1,324 @@
+use super::{end_of_interrupt, PICS, PIC_1_OFFSET};
+use crate::apic;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::{HandlerFunc, InterruptDescriptorTable, InterruptStackFrame};
+
+/// The number of IRQ lines of the legacy interrupt controllers.
+pub const IRQ_LINES: u8 = 16;
+
+/// The maximum number of handlers that can share one IRQ line.
+pub const MAX_SHARED_HANDLERS: usize = 4;
+
+/// The IRQ line of the timer, whose vector is driven by the PIT or the local APIC timer and
+/// handled by `time`.
+const TIMER_IRQ: u8 = 0;
+
+/// The IRQ line the slave PIC is cascaded on, which is never raised.
+const CASCADE_IRQ: u8 = 2;
+
+/// The command port of the master PIC.
+const PIC_1_COMMAND: u16 = 0x20;
+
+/// The command port of the slave PIC.
+const PIC_2_COMMAND: u16 = 0xA0;
+
+/// The operation command word selecting the in-service register for reads of a command port.
+const READ_ISR: u8 = 0x0B;
+
+/// The non-specific end-of-interrupt command of the PICs.
+const PIC_EOI: u8 = 0x20;
+
+/// A driver's interrupt handler.
+///
+/// Called in interrupt context with interrupts disabled and the IRQ line as argument, so it
+/// must not block or allocate. Handlers on a shared line are all called for every interrupt and
+/// must check their device, returning `true` only if it raised the interrupt.
+pub type IrqHandler = fn(irq: u8) -> bool;
+
+/// The errors that can occur when registering or unregistering an IRQ handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrqError {
+    /// The IRQ line does not exist.
+    InvalidIrq,
+    /// The IRQ line is used by the kernel itself.
+    Reserved,
+    /// The IRQ line already has `MAX_SHARED_HANDLERS` handlers.
+    ChainFull,
+    /// The handler is not registered.
+    NotRegistered,
+}
+
+/// Identifies a registered handler, returned by `register_irq`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqHandlerId {
+    irq: u8,
+    id: u64,
+}
+
+impl IrqHandlerId {
+    /// Returns the IRQ line the handler is registered on.
+    pub fn irq(&self) -> u8 {
+        self.irq
+    }
+}
+
+/// A handler in the chain of an IRQ line.
+#[derive(Clone, Copy)]
+struct Registration {
+    id: u64,
+    handler: IrqHandler,
+}
+
+/// The handler chains, indexed by IRQ line.
+///
+/// Task code only locks this with interrupts disabled, so the trampoline never finds it locked
+/// on the same core.
+static HANDLERS: Mutex<[[Option<Registration>; MAX_SHARED_HANDLERS]; IRQ_LINES as usize]> =
+    Mutex::new([[None; MAX_SHARED_HANDLERS]; IRQ_LINES as usize]);
+
+/// The number of interrupts raised on each IRQ line.
+static COUNTS: [AtomicU64; IRQ_LINES as usize] = [const { AtomicU64::new(0) }; IRQ_LINES as usize];
+
+/// The number of interrupts on each IRQ line that no handler claimed.
+static UNHANDLED: [AtomicU64; IRQ_LINES as usize] =
+    [const { AtomicU64::new(0) }; IRQ_LINES as usize];
+
+/// The number of spurious interrupts raised on each IRQ line, only ever IRQ 7 and 15.
+static SPURIOUS: [AtomicU64; IRQ_LINES as usize] =
+    [const { AtomicU64::new(0) }; IRQ_LINES as usize];
+
+/// Points the vectors of every IRQ line except the timer's at the generic trampoline.
+pub(super) fn install(idt: &mut InterruptDescriptorTable) {
+    let trampolines: [HandlerFunc; IRQ_LINES as usize] = [
+        trampoline::<0>,
+        trampoline::<1>,
+        trampoline::<2>,
+        trampoline::<3>,
+        trampoline::<4>,
+        trampoline::<5>,
+        trampoline::<6>,
+        trampoline::<7>,
+        trampoline::<8>,
+        trampoline::<9>,
+        trampoline::<10>,
+        trampoline::<11>,
+        trampoline::<12>,
+        trampoline::<13>,
+        trampoline::<14>,
+        trampoline::<15>,
+    ];
+    for irq in 0..IRQ_LINES {
+        if irq != TIMER_IRQ {
+            idt[usize::from(PIC_1_OFFSET + irq)].set_handler_fn(trampolines[irq as usize]);
+        }
+    }
+}
+
+/// Masks every IRQ line at the 8259 PICs except the timer and the cascade.
+///
+/// Lines are unmasked again as handlers are registered for them. Must be called right after the
+/// PICs are initialized.
+pub(crate) fn init() {
+    let masks = !((1u16 << TIMER_IRQ) | (1u16 << CASCADE_IRQ));
+    interrupts::without_interrupts(|| unsafe {
+        PICS.lock().write_masks(masks as u8, (masks >> 8) as u8);
+    });
+}
+
+/// The entry point of every IRQ line, dispatching to the line's handlers.
+extern "x86-interrupt" fn trampoline<const IRQ: u8>(_stack_frame: InterruptStackFrame) {
+    dispatch(IRQ);
+}
+
+/// Calls every handler registered on `irq` and signals the end of the interrupt.
+///
+/// Spurious interrupts of the PICs reach no handler and get no end of interrupt, since nothing
+/// is in service, except at the master PIC for IRQ 15: it forwarded the slave's interrupt on
+/// the cascade line and did set that line in service.
+fn dispatch(irq: u8) {
+    if is_spurious(irq) {
+        SPURIOUS[irq as usize].fetch_add(1, Ordering::Relaxed);
+        if irq == 15 {
+            unsafe { Port::new(PIC_1_COMMAND).write(PIC_EOI) };
+        }
+        return;
+    }
+
+    COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+    // Copy the chain, so that handlers may unregister themselves.
+    let chain = HANDLERS.try_lock().map(|handlers| handlers[irq as usize]);
+    let mut handled = false;
+    for registration in chain.iter().flatten().flatten() {
+        handled |= (registration.handler)(irq);
+    }
+    if !handled {
+        UNHANDLED[irq as usize].fetch_add(1, Ordering::Relaxed);
+    }
+    end_of_interrupt(PIC_1_OFFSET + irq);
+}
+
+/// Returns `true` if `irq` is a spurious IRQ 7 or 15 of the PICs.
+///
+/// A PIC raises its lowest-priority line when an interrupt goes away before the processor
+/// acknowledges it, without setting the line in service, so reading the in-service register
+/// tells the two apart.
+fn is_spurious(irq: u8) -> bool {
+    if apic::is_enabled() || irq & 7 != 7 {
+        return false;
+    }
+    let mut command = Port::<u8>::new(if irq < 8 {
+        PIC_1_COMMAND
+    } else {
+        PIC_2_COMMAND
+    });
+    let in_service = unsafe {
+        command.write(READ_ISR);
+        command.read()
+    };
+    in_service & (1 << 7) == 0
+}
+
+/// Checks that drivers may use `irq`.
+fn check_irq(irq: u8) -> Result<(), IrqError> {
+    match irq {
+        TIMER_IRQ | CASCADE_IRQ => Err(IrqError::Reserved),
+        irq if irq >= IRQ_LINES => Err(IrqError::InvalidIrq),
+        _ => Ok(()),
+    }
+}
+
+/// Registers a handler for an IRQ line.
+///
+/// A line can be shared by up to `MAX_SHARED_HANDLERS` handlers, which are called in the order
+/// of their slots. The end of the interrupt is signalled once all of them have run. Registering
+/// the first handler of a line unmasks it.
+///
+/// # Arguments
+/// * `irq` - The ISA IRQ line, between 1 and 15 except the cascade line 2.
+/// * `handler` - The handler to call on every interrupt of the line.
+///
+/// # Returns
+/// An identifier to pass to `unregister_irq`.
+pub fn register_irq(irq: u8, handler: IrqHandler) -> Result<IrqHandlerId, IrqError> {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    check_irq(irq)?;
+
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    interrupts::without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let chain = &mut handlers[irq as usize];
+        let was_empty = chain.iter().all(Option::is_none);
+        let slot = chain
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(IrqError::ChainFull)?;
+        *slot = Some(Registration { id, handler });
+        if was_empty {
+            set_masked(irq, false);
+        }
+        Ok(IrqHandlerId { irq, id })
+    })
+}
+
+/// Unregisters a handler. Unregistering the last handler of a line masks it.
+///
+/// # Arguments
+/// * `handler` - The identifier returned by `register_irq`.
+pub fn unregister_irq(handler: IrqHandlerId) -> Result<(), IrqError> {
+    let irq = handler.irq;
+    check_irq(irq)?;
+
+    interrupts::without_interrupts(|| {
+        let mut handlers = HANDLERS.lock();
+        let chain = &mut handlers[irq as usize];
+        let slot = chain
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|registration| registration.id == handler.id))
+            .ok_or(IrqError::NotRegistered)?;
+        *slot = None;
+        if chain.iter().all(Option::is_none) {
+            set_masked(irq, true);
+        }
+        Ok(())
+    })
+}
+
+/// Returns `true` if at least one handler is registered for `irq`.
+pub fn has_handlers(irq: u8) -> bool {
+    irq < IRQ_LINES
+        && interrupts::without_interrupts(|| {
+            HANDLERS.lock()[irq as usize].iter().any(Option::is_some)
+        })
+}
+
+/// Masks an IRQ line at the interrupt controller in use, so that its interrupts are held back
+/// until it is unmasked.
+pub fn mask_irq(irq: u8) -> Result<(), IrqError> {
+    check_irq(irq)?;
+    set_masked(irq, true);
+    Ok(())
+}
+
+/// Unmasks an IRQ line at the interrupt controller in use.
+pub fn unmask_irq(irq: u8) -> Result<(), IrqError> {
+    check_irq(irq)?;
+    set_masked(irq, false);
+    Ok(())
+}
+
+/// Returns `true` if an IRQ line is masked at the interrupt controller in use.
+pub fn is_masked(irq: u8) -> bool {
+    if irq >= IRQ_LINES {
+        return true;
+    }
+    if apic::is_enabled() {
+        return apic::is_isa_irq_masked(irq).unwrap_or(true);
+    }
+    let masks = interrupts::without_interrupts(|| unsafe { PICS.lock().read_masks() });
+    masks[(irq >> 3) as usize] & (1 << (irq & 7)) != 0
+}
+
+/// Returns the number of interrupts raised on an IRQ line since boot.
+pub fn interrupt_count(irq: u8) -> u64 {
+    COUNTS
+        .get(irq as usize)
+        .map_or(0, |count| count.load(Ordering::Relaxed))
+}
+
+/// Returns the number of interrupts on an IRQ line that no handler claimed.
+pub fn unhandled_count(irq: u8) -> u64 {
+    UNHANDLED
+        .get(irq as usize)
+        .map_or(0, |count| count.load(Ordering::Relaxed))
+}
+
+/// Returns the number of spurious interrupts of the PICs on an IRQ line since boot.
+///
+/// They are not part of `interrupt_count`.
+pub fn spurious_count(irq: u8) -> u64 {
+    SPURIOUS
+        .get(irq as usize)
+        .map_or(0, |count| count.load(Ordering::Relaxed))
+}
+
+/// Masks or unmasks an IRQ line at the I/O APIC once it is in use, at the PICs otherwise.
+fn set_masked(irq: u8, masked: bool) {
+    if apic::is_enabled() {
+        apic::set_isa_irq_masked(irq, masked);
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        let mut masks = unsafe { pics.read_masks() };
+        let (chip, bit) = ((irq >> 3) as usize, 1 << (irq & 7));
+        if masked {
+            masks[chip] |= bit;
+        } else {
+            masks[chip] &= !bit;
+        }
+        unsafe { pics.write_masks(masks[0], masks[1]) };
+    });
+}
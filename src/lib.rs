@@ -4,16 +4,21 @@
 #![feature(custom_test_frameworks)]
 #![test_runner(crate::test_runner)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 
 extern crate alloc;
+use core::fmt::{self, Write};
 use core::panic::PanicInfo;
 
 use boot_splash::BootScreen;
 #[cfg(test)]
-use bootloader::{entry_point, BootInfo};
+use bootloader::entry_point;
+use bootloader::BootInfo;
 use log::LogType;
 
+pub mod acpi;
 pub mod allocator;
+pub mod apic;
 pub mod boot_splash;
 pub mod cli;
 pub mod gdt;
@@ -23,16 +28,23 @@ pub mod memory;
 pub mod serial;
 pub mod settings;
 pub mod task;
+pub mod time;
 pub mod vga_buffer;
 
 /// Initializes various kernel components, including:
+/// - Memory protections (NX, write protection, SMEP and SMAP)
 /// - The Global Descriptor Table (GDT)
 /// - The Interrupt Descriptor Table (IDT)
-/// - Programmable Interrupt Controllers (PICs)
+/// - Programmable Interrupt Controllers (PICs), with every IRQ line but the timer masked
+/// - The keyboard driver
+/// - The Programmable Interval Timer (PIT)
 /// - Enables CPU interrupts
 ///
 /// This function is called at the start of the kernel's execution.
 pub fn init() {
+    // Enable NX, write protection, SMEP and SMAP where supported
+    memory::protect::init();
+
     // Initialize GDT and IDT
     gdt::init();
     interrupts::init_idt();
@@ -44,18 +56,45 @@ pub fn init() {
             "Initializing Programmable Interrupt Controllers",
         );
         interrupts::PICS.lock().initialize();
+        interrupts::irq::init();
         BootScreen::log(
             LogType::Success,
             "Programmable Interrupt Controllers initialized successfully",
         );
     }
 
+    // Register the keyboard driver
+    task::keyboard::init();
+
+    // Program the timer interrupt rate
+    time::init();
+
     // Enable CPU interrupts
     BootScreen::log(LogType::Info, "Enabling interrupts");
     x86_64::instructions::interrupts::enable();
     BootScreen::log(LogType::Success, "Interrupts enabled");
 }
 
+/// Initializes the kernel for an integration test that needs memory management.
+///
+/// Runs `init`, then sets up the kernel's page table mapper and a buddy frame allocator over
+/// the usable memory described by the bootloader, and installs both globally through
+/// `memory::init_globals`. The heap is not initialized.
+///
+/// # Arguments
+/// * `boot_info` - The boot information passed to the test's entry point.
+pub fn test_init(boot_info: &'static BootInfo) {
+    use memory::buddy::BuddyFrameAllocator;
+    use x86_64::VirtAddr;
+
+    init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+    memory::init_globals(mapper, frame_allocator);
+}
+
 /// Trait for marking types that can be tested in the kernel test suite.
 pub trait Testable {
     /// Runs the test function.
@@ -94,6 +133,59 @@ pub fn test_panic_handler(info: &PanicInfo) -> ! {
     hlt_loop();
 }
 
+/// Handles the panic of a test that is expected to panic, exiting the QEMU emulator with a
+/// success code if the panic message contains `expected` and with a failure code otherwise.
+pub fn test_expect_panic(info: &PanicInfo, expected: &str) -> ! {
+    let mut buffer = MessageBuffer::new();
+    let _ = write!(buffer, "{}", info.message());
+
+    if buffer.as_str().contains(expected) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]\n{}", buffer.as_str());
+        exit_qemu(QemuExitCode::Failed);
+    }
+    hlt_loop();
+}
+
+/// A fixed-size buffer collecting formatted text, so that tests can inspect messages without
+/// relying on the heap. Text beyond its capacity is dropped.
+pub struct MessageBuffer {
+    bytes: [u8; 256],
+    len: usize,
+}
+
+impl MessageBuffer {
+    /// Creates an empty buffer.
+    pub fn new() -> Self {
+        MessageBuffer {
+            bytes: [0; 256],
+            len: 0,
+        }
+    }
+
+    /// Returns the collected text.
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl Default for MessageBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Write for MessageBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.bytes.len() - self.len);
+        self.bytes[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
 /// Enum representing the QEMU exit codes used to signal the result of test execution.
 /// `Success` signals that tests passed, while `Failed` indicates that at least one test failed.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
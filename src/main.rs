@@ -9,16 +9,21 @@ extern crate alloc;
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
 use marcel_os::allocator;
+use marcel_os::apic;
 use marcel_os::boot_splash::BootScreen;
 use marcel_os::cli::{cli, init_cli};
+use marcel_os::gdt;
 use marcel_os::log::LogType;
-use marcel_os::memory::{self, BootInfoFrameAllocator};
+use marcel_os::memory::{self, buddy::BuddyFrameAllocator};
 use marcel_os::task::executor::Executor;
 use marcel_os::task::Task;
 use x86_64::VirtAddr;
 
 entry_point!(kmain);
 
+/// The number of pages the swap store can hold.
+const SWAP_SLOTS: usize = 256;
+
 /// Kernel main function, responsible for initializing the system and entering the main loop.
 ///
 /// # Arguments
@@ -29,14 +34,25 @@ fn kmain(boot_info: &'static BootInfo) -> ! {
 
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     BootScreen::log(LogType::Info, "Initializing memory mapper");
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mapper = unsafe { memory::init(phys_mem_offset) };
     BootScreen::log(LogType::Success, "Memory mapper initialized successfully");
 
     BootScreen::log(LogType::Info, "Initializing frame allocator");
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
     BootScreen::log(LogType::Success, "Frame allocator initialized successfully");
 
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_globals(mapper, frame_allocator);
+    memory::protect::remap_kernel();
+    gdt::init_guarded_stacks();
+    allocator::init_heap().expect("heap initialization failed");
+
+    BootScreen::log(LogType::Info, "Initializing swap store");
+    match memory::swap::init(SWAP_SLOTS) {
+        Ok(()) => BootScreen::log(LogType::Success, "Swap store initialized successfully"),
+        Err(_) => BootScreen::log(LogType::Failed, "Swap store initialization failed"),
+    }
+    apic::init();
 
     BootScreen::log(LogType::Info, "Initializing Command Line Interface");
     init_cli();
@@ -1,9 +1,40 @@
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use conquer_once::spin::OnceCell;
+use core::arch::asm;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::{Mutex, MutexGuard};
 use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB};
 use x86_64::{registers::control::Cr3, PhysAddr, VirtAddr};
 
 use crate::boot_splash::BootScreen;
 use crate::log::LogType;
+use crate::memory::buddy::BuddyFrameAllocator;
+
+pub mod address_space;
+pub mod buddy;
+pub mod cow;
+pub mod mmio;
+pub mod protect;
+pub mod region;
+pub mod stack;
+pub mod swap;
+pub mod vma;
+pub mod walk;
+
+pub use address_space::AddressSpace;
+pub use mmio::map_mmio;
+
+/// The kernel's page table mapper, shared by every subsystem that needs to map memory.
+///
+/// When both this and `FRAME_ALLOCATOR` are needed, the mapper must be locked first.
+static MAPPER: OnceCell<Mutex<OffsetPageTable<'static>>> = OnceCell::uninit();
+
+/// The kernel's physical frame allocator, shared by every subsystem that needs frames.
+static FRAME_ALLOCATOR: OnceCell<Mutex<BuddyFrameAllocator>> = OnceCell::uninit();
+
+/// The virtual address at which the bootloader mapped the complete physical memory.
+static PHYSICAL_MEMORY_OFFSET: OnceCell<VirtAddr> = OnceCell::uninit();
 
 /// Initializes the page table using the physical memory offset.
 ///
@@ -33,6 +64,132 @@ pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static>
     offset_page_table
 }
 
+/// Makes the page table mapper and the frame allocator available to the rest of the kernel.
+///
+/// After this call, subsystems that allocate memory on their own (such as the growable heap)
+/// reach them through `mapper` and `frame_allocator` instead of taking them as arguments.
+///
+/// # Arguments
+/// * `mapper` - The page table mapper returned by `init`.
+/// * `frame_allocator` - The frame allocator built from the bootloader's memory map.
+///
+/// # Panics
+/// Panics if called more than once.
+pub fn init_globals(mapper: OffsetPageTable<'static>, frame_allocator: BuddyFrameAllocator) {
+    let physical_memory_offset = mapper.phys_offset();
+    PHYSICAL_MEMORY_OFFSET
+        .try_init_once(|| physical_memory_offset)
+        .expect("memory::init_globals should only be called once");
+    MAPPER
+        .try_init_once(|| Mutex::new(mapper))
+        .expect("memory::init_globals should only be called once");
+    FRAME_ALLOCATOR
+        .try_init_once(|| Mutex::new(frame_allocator))
+        .expect("memory::init_globals should only be called once");
+}
+
+/// Returns the virtual address through which the given physical address can be accessed.
+///
+/// # Panics
+/// Panics if `init_globals` has not been called yet.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    let offset = PHYSICAL_MEMORY_OFFSET
+        .try_get()
+        .expect("physical memory offset not initialized");
+    *offset + addr.as_u64()
+}
+
+/// Locks and returns the kernel's page table mapper.
+///
+/// # Panics
+/// Panics if `init_globals` has not been called yet.
+pub fn mapper() -> MutexGuard<'static, OffsetPageTable<'static>> {
+    MAPPER.try_get().expect("mapper not initialized").lock()
+}
+
+/// Locks and returns the kernel's frame allocator.
+///
+/// # Panics
+/// Panics if `init_globals` has not been called yet.
+pub fn frame_allocator() -> MutexGuard<'static, BuddyFrameAllocator> {
+    FRAME_ALLOCATOR
+        .try_get()
+        .expect("frame allocator not initialized")
+        .lock()
+}
+
+/// Returns the kernel's page table mapper if it is initialized and not currently locked.
+///
+/// This is meant for paths that may run while the mapper is already held, such as the global
+/// allocator growing the heap, where blocking on the lock would deadlock.
+pub fn try_mapper() -> Option<MutexGuard<'static, OffsetPageTable<'static>>> {
+    MAPPER.try_get().ok()?.try_lock()
+}
+
+/// Returns the kernel's frame allocator if it is initialized and not currently locked.
+///
+/// See `try_mapper` for when this should be preferred over `frame_allocator`.
+pub fn try_frame_allocator() -> Option<MutexGuard<'static, BuddyFrameAllocator>> {
+    FRAME_ALLOCATOR.try_get().ok()?.try_lock()
+}
+
+/// A mapper for the page tables loaded in `Cr3`, returned by `try_active_mapper`.
+///
+/// It holds the lock of the kernel's mapper, so that no two paths edit page tables at once.
+/// Level 4 entries of the kernel part created through it are copied to the kernel's own page
+/// table when it is dropped.
+pub struct ActiveMapper {
+    kernel: MutexGuard<'static, OffsetPageTable<'static>>,
+    active: OffsetPageTable<'static>,
+}
+
+impl Deref for ActiveMapper {
+    type Target = OffsetPageTable<'static>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.active
+    }
+}
+
+impl DerefMut for ActiveMapper {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.active
+    }
+}
+
+impl Drop for ActiveMapper {
+    fn drop(&mut self) {
+        let active: *const PageTable = self.active.level_4_table();
+        let kernel = self.kernel.level_4_table();
+        if !core::ptr::eq(active, kernel) {
+            address_space::copy_kernel_entries(unsafe { &*active }, kernel);
+        }
+    }
+}
+
+/// Returns a mapper for the page tables loaded in `Cr3` if the kernel's mapper is initialized
+/// and not currently locked.
+///
+/// The page fault handler resolves faults through this instead of `try_mapper`, since the
+/// faulting access may have gone through the page tables of an `AddressSpace` rather than the
+/// kernel's own.
+pub fn try_active_mapper() -> Option<ActiveMapper> {
+    let kernel = try_mapper()?;
+    let offset = kernel.phys_offset();
+    let active = unsafe { OffsetPageTable::new(active_level_4_table(offset), offset) };
+    Some(ActiveMapper { kernel, active })
+}
+
+/// Returns `true` if the CPU supports 1 GiB pages, as reported by the `Page1GB` CPUID flag.
+pub fn supports_1gib_pages() -> bool {
+    use core::arch::x86_64::__cpuid;
+
+    #[allow(unused_unsafe)]
+    unsafe {
+        __cpuid(0x8000_0000).eax >= 0x8000_0001 && __cpuid(0x8000_0001).edx & (1 << 26) != 0
+    }
+}
+
 /// Retrieves the currently active Level 4 page table from the CPU's page table register (Cr3).
 ///
 /// This function reads the `Cr3` control register to obtain the physical address of the
@@ -121,9 +278,32 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     }
 }
 
+/// Whether the CPU supports enhanced `rep movsb`/`rep stosb` (ERMS): 0 if not yet checked,
+/// 1 if unsupported and 2 if supported.
+static ERMS: AtomicU8 = AtomicU8::new(0);
+
+/// Returns `true` if `rep movsb` and `rep stosb` are fast on this CPU.
+///
+/// The CPUID result is cached, since `cpuid` is expensive and may even trap to a hypervisor.
+pub fn has_erms() -> bool {
+    match ERMS.load(Ordering::Relaxed) {
+        0 => {
+            use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+            #[allow(unused_unsafe)]
+            let erms = unsafe { __cpuid(0).eax >= 7 && __cpuid_count(7, 0).ebx & (1 << 9) != 0 };
+            ERMS.store(if erms { 2 } else { 1 }, Ordering::Relaxed);
+            erms
+        }
+        state => state == 2,
+    }
+}
+
 /// Sets a block of memory to a specific value.
 ///
-/// This function writes the `value` byte to `num` consecutive bytes starting from `ptr`.
+/// This function writes the `value` byte to `num` consecutive bytes starting from `ptr`. With
+/// ERMS, the whole block is filled by a single `rep stosb`; otherwise the destination is aligned
+/// to 8 bytes and filled with `rep stosq`, leaving only the tail for byte stores.
 ///
 /// # Arguments
 /// * `ptr` - A pointer to the start of the memory block to be filled.
@@ -136,12 +316,33 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
 /// # Safety
 /// This function is unsafe because it operates directly on raw pointers.
 #[no_mangle]
-pub extern "C" fn memset(ptr: *mut u8, value: u8, num: usize) -> *mut u8 {
+pub unsafe extern "C" fn memset(ptr: *mut u8, value: u8, num: usize) -> *mut u8 {
     unsafe {
-        let mut p = ptr;
-        for _ in 0..num {
-            *p = value;
-            p = p.add(1);
+        if has_erms() || num < 16 {
+            asm!(
+                "rep stosb",
+                inout("rcx") num => _,
+                inout("rdi") ptr => _,
+                in("al") value,
+                options(nostack, preserves_flags)
+            );
+        } else {
+            let head = ptr.align_offset(8);
+            let words = (num - head) / 8;
+            let tail = (num - head) % 8;
+            asm!(
+                "rep stosb",
+                "mov rcx, {words}",
+                "rep stosq",
+                "mov rcx, {tail}",
+                "rep stosb",
+                words = in(reg) words,
+                tail = in(reg) tail,
+                inout("rcx") head => _,
+                inout("rdi") ptr => _,
+                in("rax") u64::from(value) * 0x0101_0101_0101_0101,
+                options(nostack, preserves_flags)
+            );
         }
     }
 
@@ -151,6 +352,8 @@ pub extern "C" fn memset(ptr: *mut u8, value: u8, num: usize) -> *mut u8 {
 /// Copies a block of memory from one location to another.
 ///
 /// This function copies `num` bytes from the source pointer `src` to the destination pointer `dest`.
+/// With ERMS, a single `rep movsb` is used; otherwise the destination is aligned to 8 bytes and
+/// the bulk is copied with `rep movsq`.
 ///
 /// # Arguments
 /// * `dest` - A pointer to the destination memory block.
@@ -163,18 +366,8 @@ pub extern "C" fn memset(ptr: *mut u8, value: u8, num: usize) -> *mut u8 {
 /// # Safety
 /// This function is unsafe because it operates directly on raw pointers.
 #[no_mangle]
-pub extern "C" fn memcpy(dest: *mut u8, src: *const u8, num: usize) -> *mut u8 {
-    unsafe {
-        let mut dest_ptr = dest;
-        let mut src_ptr = src;
-
-        for _ in 0..num {
-            *dest_ptr = *src_ptr;
-            dest_ptr = dest_ptr.add(1);
-            src_ptr = src_ptr.add(1);
-        }
-    }
-
+pub unsafe extern "C" fn memcpy(dest: *mut u8, src: *const u8, num: usize) -> *mut u8 {
+    unsafe { copy_forward(dest, src, num) };
     dest
 }
 
@@ -182,7 +375,8 @@ pub extern "C" fn memcpy(dest: *mut u8, src: *const u8, num: usize) -> *mut u8 {
 ///
 /// This function compares the first `num` bytes of the memory blocks pointed to by `ptr1` and
 /// `ptr2`. If the memory blocks are identical, it returns 0. Otherwise, it returns a positive or
-/// negative value based on the first differing byte.
+/// negative value based on the first differing byte. Equal prefixes are skipped eight bytes at
+/// a time.
 ///
 /// # Arguments
 /// * `ptr1` - A pointer to the first memory block.
@@ -198,12 +392,22 @@ pub extern "C" fn memcpy(dest: *mut u8, src: *const u8, num: usize) -> *mut u8 {
 #[no_mangle]
 pub unsafe extern "C" fn memcmp(ptr1: *const u8, ptr2: *const u8, num: usize) -> i32 {
     unsafe {
-        for i in 0..num {
+        let mut i = 0;
+        while i + 8 <= num {
+            let word1 = ptr1.add(i).cast::<u64>().read_unaligned();
+            let word2 = ptr2.add(i).cast::<u64>().read_unaligned();
+            if word1 != word2 {
+                break;
+            }
+            i += 8;
+        }
+        while i < num {
             let byte1 = *ptr1.add(i);
             let byte2 = *ptr2.add(i);
             if byte1 != byte2 {
                 return (byte1 as i32) - (byte2 as i32);
             }
+            i += 1;
         }
     }
     0
@@ -212,7 +416,9 @@ pub unsafe extern "C" fn memcmp(ptr1: *const u8, ptr2: *const u8, num: usize) ->
 /// Moves a block of memory from one location to another, handling overlapping regions.
 ///
 /// This function safely moves `num` bytes from the source pointer `src` to the destination pointer
-/// `dest`, taking care to handle cases where the memory regions may overlap.
+/// `dest`, taking care to handle cases where the memory regions may overlap. Unless the
+/// destination starts inside the source, the block is copied forwards like `memcpy`; otherwise
+/// it is copied backwards with the direction flag set.
 ///
 /// # Arguments
 /// * `dest` - A pointer to the destination memory block.
@@ -227,24 +433,86 @@ pub unsafe extern "C" fn memcmp(ptr1: *const u8, ptr2: *const u8, num: usize) ->
 #[no_mangle]
 pub unsafe extern "C" fn memmove(dest: *mut u8, src: *const u8, num: usize) -> *mut u8 {
     unsafe {
-        if (dest as *const u8) < src {
-            let mut dest_ptr = dest;
-            let mut src_ptr = src;
-            for _ in 0..num {
-                *dest_ptr = *src_ptr;
-                dest_ptr = dest_ptr.add(1);
-                src_ptr = src_ptr.add(1);
-            }
+        let distance = (dest as usize).wrapping_sub(src as usize);
+        if distance >= num {
+            // The destination does not start inside the source, so a forward copy never
+            // overwrites bytes before they are read.
+            copy_forward(dest, src, num);
         } else {
-            let mut dest_ptr = dest.add(num);
-            let mut src_ptr = src.add(num);
-            for _ in 0..num {
-                dest_ptr = dest_ptr.sub(1);
-                src_ptr = src_ptr.sub(1);
-                *dest_ptr = *src_ptr;
-            }
+            copy_backward(dest, src, num);
         }
     }
 
     dest
 }
+
+/// Copies `num` bytes in ascending address order.
+///
+/// # Safety
+/// Both ranges must be valid, and `dest` must not start inside `src`.
+unsafe fn copy_forward(dest: *mut u8, src: *const u8, num: usize) {
+    if has_erms() || num < 16 {
+        asm!(
+            "rep movsb",
+            inout("rcx") num => _,
+            inout("rdi") dest => _,
+            inout("rsi") src => _,
+            options(nostack, preserves_flags)
+        );
+    } else {
+        let head = dest.align_offset(8);
+        let words = (num - head) / 8;
+        let tail = (num - head) % 8;
+        asm!(
+            "rep movsb",
+            "mov rcx, {words}",
+            "rep movsq",
+            "mov rcx, {tail}",
+            "rep movsb",
+            words = in(reg) words,
+            tail = in(reg) tail,
+            inout("rcx") head => _,
+            inout("rdi") dest => _,
+            inout("rsi") src => _,
+            options(nostack, preserves_flags)
+        );
+    }
+}
+
+/// Copies `num` bytes in descending address order, for destinations that overlap the end of
+/// the source.
+///
+/// The tail is copied byte by byte until the end of the destination is 8-byte aligned, then the
+/// bulk is copied with `rep movsq` and the remaining head byte by byte, all with the direction
+/// flag set.
+///
+/// # Safety
+/// Both ranges must be valid.
+unsafe fn copy_backward(dest: *mut u8, src: *const u8, num: usize) {
+    let tail = (dest as usize + num) % 8;
+    let (tail, words, head) = if num < 16 {
+        (num, 0, 0)
+    } else {
+        (tail, (num - tail) / 8, (num - tail) % 8)
+    };
+    asm!(
+        "std",
+        "rep movsb",
+        // Step back from the last byte to the start of the last word.
+        "sub rsi, 7",
+        "sub rdi, 7",
+        "mov rcx, {words}",
+        "rep movsq",
+        "add rsi, 7",
+        "add rdi, 7",
+        "mov rcx, {head}",
+        "rep movsb",
+        "cld",
+        words = in(reg) words,
+        head = in(reg) head,
+        inout("rcx") tail => _,
+        inout("rdi") dest.add(num).wrapping_sub(1) => _,
+        inout("rsi") src.add(num).wrapping_sub(1) => _,
+        options(nostack)
+    );
+}
@@ -0,0 +1,394 @@
+use super::{frame_allocator, mapper, phys_to_virt, swap, try_mapper};
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::{AtomicU16, Ordering};
+use spin::Mutex;
+use x86_64::instructions::tlb::Pcid;
+use x86_64::registers::control::{Cr3, Cr3Flags, Cr4, Cr4Flags};
+use x86_64::structures::paging::{
+    mapper::{MapToError, TranslateResult},
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTable,
+    PageTableFlags, PhysFrame, Size2MiB, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// The first address of the user part of every address space, right above the range managed by
+/// the kernel's region allocator.
+pub const USER_SPACE_START: u64 = 0x7000_0000_0000;
+
+/// The address directly after the user part of every address space, the end of the lower half.
+pub const USER_SPACE_END: u64 = 0x8000_0000_0000;
+
+/// The level 4 entries covering the user part; all other entries belong to the kernel.
+pub(crate) const USER_ENTRIES: core::ops::Range<usize> =
+    (USER_SPACE_START >> 39) as usize..(USER_SPACE_END >> 39) as usize;
+
+/// The number of process-context identifiers; PCID 0 is kept for the kernel's own page table.
+const PCID_COUNT: u16 = 4096;
+
+/// The PCID handed to the next address space.
+static NEXT_PCID: AtomicU16 = AtomicU16::new(1);
+
+/// The maximum number of address spaces whose user pages can be reclaimed.
+const MAX_SPACES: usize = 64;
+
+/// The level 4 frames of the live address spaces, whose user pages `swap::reclaim` evicts.
+static SPACES: Mutex<[Option<PhysFrame>; MAX_SPACES]> = Mutex::new([None; MAX_SPACES]);
+
+/// A set of page tables with a private user part and the kernel part shared with every other
+/// address space.
+///
+/// The level 4 table is a copy of the kernel's, except for the entries covering `USER_SPACE_START`
+/// to `USER_SPACE_END`. Since the copied entries point to the same lower-level tables, kernel
+/// mappings made later below an existing level 4 entry are visible everywhere. Level 4 entries
+/// the kernel creates later are copied in when the address space is activated, or by the page
+/// fault handler if it is already active. Dropping the address space frees every user frame,
+/// swap slot and page table it owns.
+///
+/// The user pages of up to `MAX_SPACES` live address spaces are evicted by `swap::reclaim`
+/// under memory pressure, like those of the kernel's reclaimable ranges.
+#[derive(Debug)]
+pub struct AddressSpace {
+    /// The frame holding the level 4 table.
+    level_4_frame: PhysFrame,
+    /// The PCID tagging this address space's TLB entries, if PCIDs are enabled.
+    pcid: Option<Pcid>,
+}
+
+impl AddressSpace {
+    /// Creates an address space with an empty user part.
+    ///
+    /// # Returns
+    /// The new address space, or `MapToError::FrameAllocationFailed` if no frame is left for
+    /// the level 4 table.
+    pub fn new() -> Result<Self, MapToError<Size4KiB>> {
+        let mut mapper = mapper();
+        let level_4_frame: PhysFrame = frame_allocator()
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+
+        let table = unsafe { &mut *table_ptr(level_4_frame) };
+        table.zero();
+        for (index, entry) in mapper.level_4_table().iter().enumerate() {
+            if !USER_ENTRIES.contains(&index) {
+                table[index] = entry.clone();
+            }
+        }
+        drop(mapper);
+
+        if let Some(slot) = SPACES.lock().iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(level_4_frame);
+        }
+
+        let pcid = enable_pcid().then(|| {
+            let pcid = NEXT_PCID.fetch_add(1, Ordering::Relaxed) % (PCID_COUNT - 1) + 1;
+            Pcid::new(pcid).unwrap()
+        });
+
+        Ok(AddressSpace {
+            level_4_frame,
+            pcid,
+        })
+    }
+
+    /// Returns the frame holding the level 4 table.
+    pub fn level_4_frame(&self) -> PhysFrame {
+        self.level_4_frame
+    }
+
+    /// Returns the PCID of this address space, or `None` if PCIDs are unavailable.
+    pub fn pcid(&self) -> Option<Pcid> {
+        self.pcid
+    }
+
+    /// Returns `true` if this address space is loaded in `Cr3`.
+    pub fn is_active(&self) -> bool {
+        Cr3::read().0 == self.level_4_frame
+    }
+
+    /// Maps a zeroed frame at a user page.
+    ///
+    /// # Arguments
+    /// * `page` - The page to map, inside the user part.
+    /// * `flags` - The flags to map the page with. `PRESENT` and `USER_ACCESSIBLE` are added
+    ///   automatically.
+    ///
+    /// # Returns
+    /// An error if frame allocation fails or the page is already mapped.
+    ///
+    /// # Panics
+    /// Panics if the page is outside the user part.
+    pub fn map_user(
+        &mut self,
+        page: Page,
+        flags: PageTableFlags,
+    ) -> Result<(), MapToError<Size4KiB>> {
+        assert_user_page(page);
+        let flags = flags | PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+        let mut frame_allocator = frame_allocator();
+        let frame: PhysFrame = frame_allocator
+            .allocate_frame()
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        unsafe {
+            let frame_ptr: *mut u8 = phys_to_virt(frame.start_address()).as_mut_ptr();
+            frame_ptr.write_bytes(0, Size4KiB::SIZE as usize);
+
+            let mut mapper = self.mapper();
+            match mapper.map_to(page, frame, flags, &mut *frame_allocator) {
+                // Flushing only matters if this address space is active; it is cheap otherwise.
+                Ok(flush) => flush.flush(),
+                Err(error) => {
+                    frame_allocator.deallocate_frame(frame);
+                    return Err(error);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Unmaps a user page and drops its reference to the mapped frame, or releases its swap slot
+    /// if it was swapped out.
+    ///
+    /// # Returns
+    /// `true` if the page was mapped.
+    ///
+    /// # Safety
+    /// No references into the page may be used afterwards.
+    ///
+    /// # Panics
+    /// Panics if the page is outside the user part.
+    pub unsafe fn unmap_user(&mut self, page: Page) -> bool {
+        assert_user_page(page);
+        let mut frame_allocator = frame_allocator();
+        let mut mapper = self.mapper();
+        let start = page.start_address();
+        if swap::discard_in(&mut mapper, start, start + Size4KiB::SIZE) > 0 {
+            return true;
+        }
+        match mapper.unmap(page) {
+            Ok((frame, flush)) => {
+                flush.flush();
+                frame_allocator.release_frame(frame);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Translates an address through this address space's page tables.
+    ///
+    /// # Returns
+    /// The physical address `addr` is mapped to, or `None` if it is not mapped.
+    pub fn translate(&mut self, addr: VirtAddr) -> Option<PhysAddr> {
+        match self.mapper().translate(addr) {
+            TranslateResult::Mapped { frame, offset, .. } => Some(frame.start_address() + offset),
+            _ => None,
+        }
+    }
+
+    /// Loads this address space into `Cr3`, tagged with its PCID if PCIDs are enabled.
+    ///
+    /// Writing `Cr3` invalidates the TLB entries of the new PCID, so entries left over from an
+    /// earlier address space with the same PCID are never used.
+    ///
+    /// The kernel's level 4 entries created since this address space was last active are copied
+    /// in first, so the kernel part matches the kernel's own page table.
+    ///
+    /// # Safety
+    /// The caller must ensure that the kernel's stack and code remain mapped, which holds for
+    /// every mapping of the kernel's own page table.
+    pub unsafe fn activate(&self) {
+        {
+            let mut kernel = mapper();
+            copy_kernel_entries(kernel.level_4_table(), &mut *table_ptr(self.level_4_frame));
+        }
+        match self.pcid {
+            Some(pcid) => Cr3::write_pcid(self.level_4_frame, pcid),
+            None => Cr3::write(self.level_4_frame, Cr3Flags::empty()),
+        }
+    }
+
+    /// Returns a mapper for this address space's page tables.
+    fn mapper(&mut self) -> OffsetPageTable<'_> {
+        let offset = phys_to_virt(PhysAddr::new(0));
+        unsafe { OffsetPageTable::new(&mut *table_ptr(self.level_4_frame), offset) }
+    }
+}
+
+impl Drop for AddressSpace {
+    /// Frees every user frame, swap slot and page table of this address space, and the level 4
+    /// table.
+    ///
+    /// # Panics
+    /// Panics if the address space is still active.
+    fn drop(&mut self) {
+        assert!(!self.is_active(), "dropping the active address space");
+        if let Some(slot) = SPACES
+            .lock()
+            .iter_mut()
+            .find(|slot| **slot == Some(self.level_4_frame))
+        {
+            *slot = None;
+        }
+
+        let mut frame_allocator = frame_allocator();
+        let table = unsafe { &mut *table_ptr(self.level_4_frame) };
+        for index in USER_ENTRIES {
+            let entry = &mut table[index];
+            if let Ok(frame) = entry.frame() {
+                unsafe {
+                    free_table(frame, 3, &mut frame_allocator);
+                    frame_allocator.deallocate_frame(frame);
+                }
+            }
+            entry.set_unused();
+        }
+        unsafe { frame_allocator.deallocate_frame(self.level_4_frame) };
+    }
+}
+
+/// Loads the kernel's own page table into `Cr3`.
+///
+/// # Safety
+/// No references into the user part of the previously active address space may be used
+/// afterwards.
+pub unsafe fn activate_kernel() {
+    let frame = kernel_level_4_frame(&mut mapper());
+    Cr3::write(frame, Cr3Flags::empty());
+}
+
+/// Copies the kernel's level 4 entry covering `addr` into the active address space if it is
+/// missing there.
+///
+/// Called by the page fault handler, so that kernel mappings below level 4 entries created
+/// while an address space is active become visible in it. The kernel's mapper is only tried.
+///
+/// # Returns
+/// `true` if the entry was copied and the faulting access can be retried.
+pub fn sync_kernel_entry(addr: VirtAddr) -> bool {
+    let index = usize::from(addr.p4_index());
+    if USER_ENTRIES.contains(&index) {
+        return false;
+    }
+    let Some(mut kernel) = try_mapper() else {
+        return false;
+    };
+    let active = Cr3::read().0;
+    if active == kernel_level_4_frame(&mut kernel) {
+        return false;
+    }
+
+    let source = &kernel.level_4_table()[index];
+    let table = unsafe { &mut *table_ptr(active) };
+    if source.is_unused() || !table[index].is_unused() {
+        return false;
+    }
+    table[index] = source.clone();
+    true
+}
+
+/// Copies the kernel entries that are present in the level 4 table `from` but missing from `to`.
+///
+/// Level 4 entries of the kernel part are never removed, so entries present in both tables
+/// always match.
+pub(crate) fn copy_kernel_entries(from: &PageTable, to: &mut PageTable) {
+    for (index, entry) in from.iter().enumerate() {
+        if !USER_ENTRIES.contains(&index) && !entry.is_unused() && to[index].is_unused() {
+            to[index] = entry.clone();
+        }
+    }
+}
+
+/// Returns the frame holding the kernel's level 4 table.
+fn kernel_level_4_frame(mapper: &mut OffsetPageTable) -> PhysFrame {
+    let table = VirtAddr::from_ptr(mapper.level_4_table() as *const PageTable);
+    PhysFrame::containing_address(PhysAddr::new(table - mapper.phys_offset()))
+}
+
+/// Returns the level 4 frames of the live address spaces, for `swap::reclaim`.
+///
+/// The registry is only tried, since reclaim may interrupt an address space being created or
+/// dropped; no address space is returned then.
+pub(crate) fn registered_spaces() -> [Option<PhysFrame>; MAX_SPACES] {
+    SPACES
+        .try_lock()
+        .map_or([None; MAX_SPACES], |spaces| *spaces)
+}
+
+/// Returns a pointer to the page table stored in `frame`.
+fn table_ptr(frame: PhysFrame) -> *mut PageTable {
+    phys_to_virt(frame.start_address()).as_mut_ptr()
+}
+
+/// Panics if `page` is outside the user part.
+fn assert_user_page(page: Page) {
+    let addr = page.start_address().as_u64();
+    assert!(
+        (USER_SPACE_START..USER_SPACE_END).contains(&addr),
+        "{:?} is outside the user part of the address space",
+        page
+    );
+}
+
+/// Frees the frames mapped by the level `level` table in `frame` and its lower-level tables.
+///
+/// 4 KiB frames only lose a reference, since they may be shared copy-on-write. Swapped-out pages
+/// release their swap slots, and frames the frame allocator does not manage are skipped.
+///
+/// # Safety
+/// The table must belong to an inactive address space and must not be used afterwards.
+unsafe fn free_table(
+    frame: PhysFrame,
+    level: u8,
+    frame_allocator: &mut super::buddy::BuddyFrameAllocator,
+) {
+    let table = &*table_ptr(frame);
+    for entry in table.iter() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            if level == 1 && flags.contains(swap::SWAPPED) {
+                swap::release_slot(entry);
+            }
+            continue;
+        }
+        let addr = entry.addr();
+        match level {
+            1 => {
+                frame_allocator.release_frame(PhysFrame::containing_address(addr));
+            }
+            2 if flags.contains(PageTableFlags::HUGE_PAGE) => {
+                // Device memory may be mapped with huge pages too; it is owned elsewhere.
+                if frame_allocator.manages(PhysFrame::containing_address(addr)) {
+                    let frame = PhysFrame::<Size2MiB>::containing_address(addr);
+                    frame_allocator.deallocate_frame(frame);
+                }
+            }
+            // 1 GiB frames are never handed out, so such a mapping is a window onto memory
+            // that is owned elsewhere.
+            3 if flags.contains(PageTableFlags::HUGE_PAGE) => {}
+            _ => {
+                let child = PhysFrame::containing_address(addr);
+                free_table(child, level - 1, frame_allocator);
+                frame_allocator.deallocate_frame(child);
+            }
+        }
+    }
+}
+
+/// Enables PCIDs if the CPU supports them and they are not enabled yet.
+///
+/// # Returns
+/// `true` if PCIDs are enabled.
+fn enable_pcid() -> bool {
+    if Cr4::read().contains(Cr4Flags::PCID) {
+        return true;
+    }
+    #[allow(unused_unsafe)]
+    let supported = unsafe { __cpuid(1).ecx & (1 << 17) != 0 };
+    // `CR4.PCIDE` can only be set while the current PCID is 0.
+    if !supported || Cr3::read_raw().1 & 0xFFF != 0 {
+        return false;
+    }
+    unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::PCID)) };
+    true
+}
@@ -0,0 +1,426 @@
+use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// The size of a single physical frame in bytes.
+const FRAME_SIZE: u64 = Size4KiB::SIZE;
+
+/// The largest block order served by the allocator. A block of order `n` spans `2^n` frames,
+/// so the allocator hands out blocks from 4 KiB (order 0) up to 4 MiB (order 10).
+pub const MAX_ORDER: usize = 10;
+
+/// The block order matching a 2 MiB huge frame.
+pub const HUGE_2MIB_ORDER: usize = (Size2MiB::SIZE / FRAME_SIZE).trailing_zeros() as usize;
+
+/// Marker stored in `block_orders` for frames that are not the head of a free block.
+const NOT_FREE: u8 = u8::MAX;
+
+/// Marker stored in `block_orders` for frames that are not usable RAM, including the frames
+/// holding the tables themselves. Such frames never coalesce and are never accepted back.
+const UNUSABLE: u8 = u8::MAX - 1;
+
+/// Marker used in the intrusive free lists for "no frame".
+const NIL: usize = usize::MAX;
+
+/// A node of a free list, stored in the first frame of every free block.
+struct FreeBlock {
+    /// The frame index of the previous free block of the same order.
+    prev: usize,
+    /// The frame index of the next free block of the same order.
+    next: usize,
+}
+
+/// A buddy-system frame allocator serving naturally aligned power-of-two runs of frames.
+///
+/// Free blocks are kept in one doubly linked list per order. The list nodes live inside the
+/// free frames themselves, reached through the physical memory mapping, and a table with one
+/// byte per frame records which frames start a free block of which order. When a block is
+/// freed and its buddy is free as well, both are merged into a block of the next order.
+///
+/// Single frames can additionally be shared between several mappings, for example by
+/// copy-on-write. A second table counts the extra references to each frame; `release_frame`
+/// only returns a frame to the free lists once its last reference is dropped.
+pub struct BuddyFrameAllocator {
+    /// The virtual address at which physical memory is mapped.
+    physical_memory_offset: VirtAddr,
+    /// The first frame of the free list for each order, or `NIL` if the list is empty.
+    free_lists: [usize; MAX_ORDER + 1],
+    /// The order of the free block starting at each frame, `NOT_FREE` or `UNUSABLE`.
+    block_orders: &'static mut [u8],
+    /// The number of references to each frame beyond the first, for frames mapped more than once.
+    shared_refs: &'static mut [u16],
+    /// The number of frames that were marked as usable by the bootloader.
+    total_frames: usize,
+    /// The number of usable frames that are currently allocated.
+    used_frames: usize,
+}
+
+impl BuddyFrameAllocator {
+    /// Builds the free lists from the bootloader-provided memory map.
+    ///
+    /// The per-frame order and reference count tables are stored at the start of the first
+    /// usable region large enough to hold them; the frames they occupy are counted as used.
+    ///
+    /// # Arguments
+    /// * `memory_map` - The memory map provided by the bootloader, detailing the memory regions.
+    /// * `physical_memory_offset` - The virtual address at which physical memory is mapped.
+    ///
+    /// # Safety
+    /// The caller must guarantee that the memory map is valid, that all physical memory is
+    /// mapped at `physical_memory_offset` and that this function is only called once.
+    ///
+    /// # Panics
+    /// Panics if no usable region is large enough to hold the tables.
+    pub unsafe fn init(memory_map: &'static MemoryMap, physical_memory_offset: VirtAddr) -> Self {
+        let usable_regions = || {
+            memory_map
+                .iter()
+                .filter(|r| r.region_type == MemoryRegionType::Usable)
+        };
+
+        // The order table has to cover every frame up to the end of the highest usable region.
+        let max_addr = usable_regions()
+            .map(|r| r.range.end_addr())
+            .max()
+            .unwrap_or(0);
+        let frame_count = (max_addr / FRAME_SIZE) as usize;
+        // The reference counts follow the order table, aligned for `u16`.
+        let refs_offset = (frame_count + 1) & !1;
+        let table_size = (refs_offset + frame_count * 2) as u64;
+
+        let table_region = usable_regions()
+            .find(|r| r.range.end_addr() - r.range.start_addr() >= table_size)
+            .expect("no usable memory region large enough for the buddy tables");
+        let table_start = table_region.range.start_addr();
+        let table_ptr: *mut u8 = (physical_memory_offset + table_start).as_mut_ptr();
+        let block_orders = core::slice::from_raw_parts_mut(table_ptr, frame_count);
+        block_orders.fill(UNUSABLE);
+        let refs_ptr = table_ptr.add(refs_offset).cast::<u16>();
+        let shared_refs = core::slice::from_raw_parts_mut(refs_ptr, frame_count);
+        shared_refs.fill(0);
+
+        let mut allocator = BuddyFrameAllocator {
+            physical_memory_offset,
+            free_lists: [NIL; MAX_ORDER + 1],
+            block_orders,
+            shared_refs,
+            total_frames: 0,
+            used_frames: 0,
+        };
+
+        let table_start_frame = (table_start / FRAME_SIZE) as usize;
+        let table_frames = table_size.div_ceil(FRAME_SIZE) as usize;
+
+        for region in usable_regions() {
+            let mut start = (region.range.start_addr() / FRAME_SIZE) as usize;
+            let end = (region.range.end_addr() / FRAME_SIZE) as usize;
+            allocator.total_frames += end - start;
+
+            // Skip the frames backing the tables themselves.
+            if start == table_start_frame {
+                start += table_frames;
+                allocator.used_frames += table_frames;
+            }
+            allocator.block_orders[start..end].fill(NOT_FREE);
+
+            // Split the region into the largest naturally aligned blocks that fit.
+            while start < end {
+                let mut order = (start.trailing_zeros() as usize).min(MAX_ORDER);
+                while start + (1 << order) > end {
+                    order -= 1;
+                }
+                allocator.free_block(start, order);
+                start += 1 << order;
+            }
+        }
+
+        allocator
+    }
+
+    /// Returns the smallest order whose blocks hold at least `frames` frames.
+    pub fn order_for_frames(frames: usize) -> usize {
+        frames.max(1).next_power_of_two().trailing_zeros() as usize
+    }
+
+    /// Allocates a naturally aligned block of `2^order` physically contiguous frames.
+    ///
+    /// # Arguments
+    /// * `order` - The order of the requested block, at most `MAX_ORDER`.
+    ///
+    /// # Returns
+    /// The first frame of the block, or `None` if no block of that order is available.
+    pub fn allocate_contiguous(&mut self, order: usize) -> Option<PhysFrame> {
+        if order > MAX_ORDER {
+            return None;
+        }
+
+        // Take a block from the smallest non-empty list that is large enough.
+        let mut current = (order..=MAX_ORDER).find(|&o| self.free_lists[o] != NIL)?;
+        let index = self.free_lists[current];
+        self.remove(index, current);
+
+        // Split it, returning the upper halves to the free lists.
+        while current > order {
+            current -= 1;
+            self.push(index + (1 << current), current);
+        }
+
+        self.used_frames += 1 << order;
+
+        let addr = PhysAddr::new(index as u64 * FRAME_SIZE);
+        Some(PhysFrame::containing_address(addr))
+    }
+
+    /// Returns a block previously obtained from `allocate_contiguous` to the allocator.
+    ///
+    /// # Arguments
+    /// * `frame` - The first frame of the block.
+    /// * `order` - The order the block was allocated with.
+    ///
+    /// # Safety
+    /// The caller must ensure that the block is no longer in use and that `order` matches the
+    /// order used for the allocation.
+    ///
+    /// # Panics
+    /// Panics if the block is outside the managed frames, not usable memory, misaligned for its
+    /// order, overlaps a free block or is still shared.
+    pub unsafe fn deallocate_contiguous(&mut self, frame: PhysFrame, order: usize) {
+        assert!(order <= MAX_ORDER, "invalid block order {}", order);
+        let index = self
+            .frame_index(frame)
+            .filter(|&index| index + (1 << order) <= self.block_orders.len())
+            .unwrap_or_else(|| {
+                panic!(
+                    "deallocating block {:?} of order {} beyond the managed frames",
+                    frame, order
+                )
+            });
+        assert!(
+            self.is_usable(index, order),
+            "deallocating block {:?} of order {} that is not usable memory",
+            frame,
+            order
+        );
+        assert_eq!(
+            index & ((1 << order) - 1),
+            0,
+            "block {:?} is misaligned",
+            frame
+        );
+        assert!(
+            !self.overlaps_free_block(index, order),
+            "deallocating block {:?} that is not allocated",
+            frame
+        );
+        assert_eq!(
+            self.shared_refs[index], 0,
+            "deallocating frame {:?} that is still shared",
+            frame
+        );
+
+        self.free_block(index, order);
+        self.used_frames -= 1 << order;
+    }
+
+    /// Adds a reference to an allocated frame that is about to be mapped a second time.
+    ///
+    /// # Arguments
+    /// * `frame` - The shared frame.
+    ///
+    /// # Panics
+    /// Panics if the frame is not usable memory, is free or its reference count overflows.
+    pub fn share_frame(&mut self, frame: PhysFrame) {
+        let index = self
+            .frame_index(frame)
+            .filter(|&index| self.is_usable(index, 0))
+            .unwrap_or_else(|| panic!("sharing frame {:?} that is not usable memory", frame));
+        assert!(
+            !self.overlaps_free_block(index, 0),
+            "sharing frame {:?} that is not allocated",
+            frame
+        );
+        self.shared_refs[index] = self.shared_refs[index]
+            .checked_add(1)
+            .expect("frame reference count overflow");
+    }
+
+    /// Returns the number of references to an allocated frame.
+    ///
+    /// Frames that were never shared have a single reference, held by whoever allocated them.
+    /// The same holds for frames the allocator does not manage.
+    pub fn ref_count(&self, frame: PhysFrame) -> usize {
+        self.frame_index(frame)
+            .map_or(1, |index| self.shared_refs[index] as usize + 1)
+    }
+
+    /// Returns `true` if the frame is usable RAM handed out by this allocator, as opposed to
+    /// reserved memory, a device window or a frame holding the allocator's own tables.
+    pub fn manages(&self, frame: PhysFrame) -> bool {
+        self.frame_index(frame)
+            .is_some_and(|index| self.is_usable(index, 0))
+    }
+
+    /// Drops a reference to a frame, deallocating it once no references are left.
+    ///
+    /// Frames the allocator does not manage, such as device memory mapped into an address
+    /// space, are left alone.
+    ///
+    /// # Arguments
+    /// * `frame` - The frame whose mapping went away.
+    ///
+    /// # Returns
+    /// `true` if this was the last reference and the frame was deallocated.
+    ///
+    /// # Safety
+    /// The caller must no longer use the frame through the dropped reference.
+    pub unsafe fn release_frame(&mut self, frame: PhysFrame) -> bool {
+        let Some(index) = self.frame_index(frame).filter(|&i| self.is_usable(i, 0)) else {
+            return false;
+        };
+        if self.shared_refs[index] > 0 {
+            self.shared_refs[index] -= 1;
+            return false;
+        }
+        self.deallocate_contiguous(frame, 0);
+        true
+    }
+
+    /// Returns the number of usable physical frames managed by this allocator.
+    pub fn total_frames(&self) -> usize {
+        self.total_frames
+    }
+
+    /// Returns the number of usable frames that are currently allocated.
+    pub fn used_frames(&self) -> usize {
+        self.used_frames
+    }
+
+    /// Returns the number of usable frames that are still available.
+    pub fn free_frames(&self) -> usize {
+        self.total_frames - self.used_frames
+    }
+
+    /// Returns the number of free blocks of the given order.
+    pub fn free_blocks(&self, order: usize) -> usize {
+        let mut count = 0;
+        let mut index = self.free_lists[order];
+        while index != NIL {
+            count += 1;
+            index = unsafe { (*self.node(index)).next };
+        }
+        count
+    }
+
+    /// Returns the index of a frame in the per-frame tables, or `None` if the frame lies beyond
+    /// the highest usable frame.
+    fn frame_index(&self, frame: PhysFrame) -> Option<usize> {
+        let index = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        (index < self.block_orders.len()).then_some(index)
+    }
+
+    /// Returns `true` if every frame of the block of `2^order` frames at `index` is usable RAM.
+    fn is_usable(&self, index: usize, order: usize) -> bool {
+        self.block_orders[index..index + (1 << order)]
+            .iter()
+            .all(|&o| o != UNUSABLE)
+    }
+
+    /// Returns `true` if any frame of the block of `2^order` frames at `index` is on a free list.
+    ///
+    /// The block overlaps a free block either if a free block of the same or a higher order
+    /// contains it, which can only start at the block's aligned-down index for that order, or
+    /// if a smaller free block starts inside it.
+    fn overlaps_free_block(&self, index: usize, order: usize) -> bool {
+        let contained = (order..=MAX_ORDER).any(|o| {
+            let start = index & !((1 << o) - 1);
+            self.block_orders[start] == o as u8
+        });
+        contained
+            || self.block_orders[index..index + (1 << order)]
+                .iter()
+                .any(|&o| o <= MAX_ORDER as u8)
+    }
+
+    /// Inserts a free block, merging it with its buddy as long as the buddy is free too.
+    fn free_block(&mut self, mut index: usize, mut order: usize) {
+        while order < MAX_ORDER {
+            let buddy = index ^ (1 << order);
+            if buddy >= self.block_orders.len() || self.block_orders[buddy] != order as u8 {
+                break;
+            }
+            self.remove(buddy, order);
+            index = index.min(buddy);
+            order += 1;
+        }
+        self.push(index, order);
+    }
+
+    /// Returns a pointer to the free list node stored in the given frame.
+    fn node(&self, index: usize) -> *mut FreeBlock {
+        (self.physical_memory_offset + index as u64 * FRAME_SIZE).as_mut_ptr()
+    }
+
+    /// Pushes the block starting at `index` onto the free list of the given order.
+    fn push(&mut self, index: usize, order: usize) {
+        let head = self.free_lists[order];
+        unsafe {
+            self.node(index).write(FreeBlock {
+                prev: NIL,
+                next: head,
+            });
+            if head != NIL {
+                (*self.node(head)).prev = index;
+            }
+        }
+        self.free_lists[order] = index;
+        self.block_orders[index] = order as u8;
+    }
+
+    /// Unlinks the block starting at `index` from the free list of the given order.
+    fn remove(&mut self, index: usize, order: usize) {
+        unsafe {
+            let FreeBlock { prev, next } = self.node(index).read();
+            if prev == NIL {
+                self.free_lists[order] = next;
+            } else {
+                (*self.node(prev)).next = next;
+            }
+            if next != NIL {
+                (*self.node(next)).prev = prev;
+            }
+        }
+        self.block_orders[index] = NOT_FREE;
+    }
+}
+
+/// Allocates a single frame as a block of order 0.
+unsafe impl FrameAllocator<Size4KiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        self.allocate_contiguous(0)
+    }
+}
+
+/// Returns a single frame as a block of order 0, merging it with free buddies.
+impl FrameDeallocator<Size4KiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.deallocate_contiguous(frame, 0);
+    }
+}
+
+/// Allocates a 2 MiB huge frame as a naturally aligned block of `HUGE_2MIB_ORDER`.
+unsafe impl FrameAllocator<Size2MiB> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let frame = self.allocate_contiguous(HUGE_2MIB_ORDER)?;
+        Some(PhysFrame::containing_address(frame.start_address()))
+    }
+}
+
+/// Returns a 2 MiB huge frame as a block of `HUGE_2MIB_ORDER`.
+impl FrameDeallocator<Size2MiB> for BuddyFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let frame = PhysFrame::containing_address(frame.start_address());
+        self.deallocate_contiguous(frame, HUGE_2MIB_ORDER);
+    }
+}
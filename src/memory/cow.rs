@@ -0,0 +1,205 @@
+use super::buddy::BuddyFrameAllocator;
+use super::{phys_to_virt, try_active_mapper, try_frame_allocator};
+use x86_64::structures::paging::{
+    mapper::{MapToError, MappedFrame, TranslateResult},
+    FrameAllocator, Mapper, OffsetPageTable, Page, PageSize, PageTableFlags, PhysFrame, Size4KiB,
+    Translate,
+};
+use x86_64::VirtAddr;
+
+/// The page table flag marking a read-only mapping of a shared frame that becomes private on
+/// the first write. It uses one of the bits the CPU leaves to the operating system.
+pub const COW: PageTableFlags = PageTableFlags::BIT_9;
+
+/// The reasons a page could not be shared copy-on-write.
+#[derive(Debug)]
+pub enum CowError {
+    /// The page is not mapped.
+    NotMapped,
+    /// The page is part of a huge page; only 4 KiB pages can be shared.
+    HugePage,
+    /// The shared frame could not be mapped at the destination.
+    Map(MapToError<Size4KiB>),
+}
+
+/// Returns the flags for a copy-on-write mapping of a page mapped with `flags`.
+///
+/// Writable pages lose `WRITABLE` and gain `COW`; read-only pages can be shared as they are.
+pub fn cow_flags(flags: PageTableFlags) -> PageTableFlags {
+    if flags.contains(PageTableFlags::WRITABLE) {
+        (flags - PageTableFlags::WRITABLE) | COW
+    } else {
+        flags
+    }
+}
+
+/// Turns the mapping of `page` into a copy-on-write mapping in place.
+///
+/// # Arguments
+/// * `mapper` - The page table containing the mapping.
+/// * `page` - The page to protect.
+///
+/// # Returns
+/// The mapped frame and its new flags, ready to be passed to `map_shared`.
+pub fn protect(
+    mapper: &mut OffsetPageTable,
+    page: Page,
+) -> Result<(PhysFrame, PageTableFlags), CowError> {
+    let (frame, flags) = match mapper.translate(page.start_address()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags,
+            ..
+        } => (frame, flags),
+        TranslateResult::Mapped { .. } => return Err(CowError::HugePage),
+        _ => return Err(CowError::NotMapped),
+    };
+
+    let flags = cow_flags(flags);
+    unsafe {
+        mapper
+            .update_flags(page, flags)
+            .map_err(|_| CowError::NotMapped)?
+            .flush();
+    }
+    Ok((frame, flags))
+}
+
+/// Maps a frame that is already mapped elsewhere at `page`, adding a reference to it.
+///
+/// # Arguments
+/// * `mapper` - The page table to map into, which may belong to another address space.
+/// * `page` - The unmapped destination page.
+/// * `frame` - The shared frame, as returned by `protect`.
+/// * `flags` - The copy-on-write flags, as returned by `protect`.
+/// * `frame_allocator` - The frame allocator, used for the reference count and page tables.
+///
+/// # Safety
+/// All other mappings of the frame must be read-only, which `protect` guarantees.
+pub unsafe fn map_shared(
+    mapper: &mut OffsetPageTable,
+    page: Page,
+    frame: PhysFrame,
+    flags: PageTableFlags,
+    frame_allocator: &mut BuddyFrameAllocator,
+) -> Result<(), CowError> {
+    let flush = mapper
+        .map_to(page, frame, flags, frame_allocator)
+        .map_err(CowError::Map)?;
+    flush.flush();
+    frame_allocator.share_frame(frame);
+    Ok(())
+}
+
+/// Maps the frame behind `source` at `dest` as well, making both mappings copy-on-write.
+///
+/// # Arguments
+/// * `mapper` - The page table containing both pages.
+/// * `source` - The mapped page to share.
+/// * `dest` - The unmapped page to map the shared frame at.
+/// * `frame_allocator` - The frame allocator, used for the reference count and page tables.
+///
+/// # Returns
+/// An error if `source` is not a mapped 4 KiB page or `dest` cannot be mapped. `source` stays
+/// copy-on-write in the latter case, which is harmless: the first write simply reclaims it.
+pub fn share_page(
+    mapper: &mut OffsetPageTable,
+    source: Page,
+    dest: Page,
+    frame_allocator: &mut BuddyFrameAllocator,
+) -> Result<(), CowError> {
+    let (frame, flags) = protect(mapper, source)?;
+    unsafe { map_shared(mapper, dest, frame, flags, frame_allocator) }
+}
+
+/// Unmaps `page` and drops its reference to the mapped frame, freeing the frame if no other
+/// mapping uses it.
+///
+/// # Returns
+/// `true` if the frame was freed, or `false` if it is still shared or the page was not mapped.
+///
+/// # Safety
+/// No references into the page may be used afterwards.
+pub unsafe fn unmap_page(
+    mapper: &mut OffsetPageTable,
+    page: Page,
+    frame_allocator: &mut BuddyFrameAllocator,
+) -> bool {
+    match mapper.unmap(page) {
+        Ok((frame, flush)) => {
+            flush.flush();
+            frame_allocator.release_frame(frame)
+        }
+        Err(_) => false,
+    }
+}
+
+/// Resolves a write fault on a copy-on-write page at `addr`.
+///
+/// If other mappings still share the frame, its contents are copied into a fresh frame that
+/// replaces the faulting mapping; the last mapping of a frame simply becomes writable again.
+/// Called by the page fault handler. Every lock is only tried, so a fault raised while the
+/// mapper or the frame allocator is held is reported instead of deadlocking.
+///
+/// # Arguments
+/// * `addr` - The faulting address, as read from `Cr2`.
+///
+/// # Returns
+/// `true` if the page is now writable and the faulting instruction can be retried, `false` if
+/// the fault has to be treated as an invalid access.
+pub fn handle_page_fault(addr: VirtAddr) -> bool {
+    let (Some(mut mapper), Some(mut frame_allocator)) =
+        (try_active_mapper(), try_frame_allocator())
+    else {
+        return false;
+    };
+
+    let page = Page::<Size4KiB>::containing_address(addr);
+    let TranslateResult::Mapped {
+        frame: MappedFrame::Size4KiB(frame),
+        flags,
+        ..
+    } = mapper.translate(page.start_address())
+    else {
+        return false;
+    };
+    if !flags.contains(COW) {
+        return false;
+    }
+    let flags = (flags - COW) | PageTableFlags::WRITABLE;
+
+    unsafe {
+        if frame_allocator.ref_count(frame) == 1 {
+            // Every other mapping is gone already, so the frame can be reused as it is.
+            return match mapper.update_flags(page, flags) {
+                Ok(flush) => {
+                    flush.flush();
+                    true
+                }
+                Err(_) => false,
+            };
+        }
+
+        let Some(copy): Option<PhysFrame> = frame_allocator.allocate_frame() else {
+            return false;
+        };
+        let src: *const u8 = phys_to_virt(frame.start_address()).as_ptr();
+        let dest: *mut u8 = phys_to_virt(copy.start_address()).as_mut_ptr();
+        core::ptr::copy_nonoverlapping(src, dest, Size4KiB::SIZE as usize);
+
+        // The page tables of the old mapping stay in place, so remapping cannot fail.
+        match mapper.unmap(page) {
+            Ok((_, flush)) => flush.ignore(),
+            Err(_) => {
+                frame_allocator.release_frame(copy);
+                return false;
+            }
+        }
+        mapper
+            .map_to(page, copy, flags, &mut *frame_allocator)
+            .expect("remapping a copy-on-write page failed")
+            .flush();
+        frame_allocator.release_frame(frame);
+    }
+    true
+}
@@ -0,0 +1,157 @@
+use super::{region, region::VirtRegion};
+use core::mem;
+use x86_64::structures::paging::{mapper::MapToError, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// The flags used for every MMIO page: uncached and write-through, so that every access reaches
+/// the device in program order.
+const MMIO_FLAGS: PageTableFlags = PageTableFlags::PRESENT
+    .union(PageTableFlags::WRITABLE)
+    .union(PageTableFlags::NO_CACHE)
+    .union(PageTableFlags::WRITE_THROUGH);
+
+/// A physical device range mapped into the kernel's address space.
+///
+/// The mapping is removed when the handle is dropped. The physical frames are not returned to
+/// the frame allocator, since they belong to the device and not to RAM.
+#[derive(Debug)]
+pub struct MmioRegion {
+    /// The virtual region holding the mapping, taken when the handle is dropped.
+    region: Option<VirtRegion>,
+    /// The physical address of the first byte of the range.
+    phys: PhysAddr,
+    /// The virtual address of the first byte of the range.
+    base: VirtAddr,
+    /// The length of the range in bytes.
+    len: usize,
+}
+
+/// Maps the physical range `phys..phys + len` for memory-mapped I/O.
+///
+/// The range may start and end anywhere inside a page; the covering pages are mapped with
+/// `NO_CACHE` and `WRITE_THROUGH` into a fresh virtual region through `region::map_physical`.
+///
+/// # Arguments
+/// * `phys` - The physical address of the device registers.
+/// * `len` - The length of the range in bytes.
+///
+/// # Returns
+/// A handle to the mapped range, or an error if no virtual region is available or a page table
+/// could not be allocated.
+///
+/// # Safety
+/// The range must belong to a device (or otherwise be safe to access uncached) and must not be
+/// handed out by the frame allocator.
+pub unsafe fn map_mmio(phys: PhysAddr, len: usize) -> Result<MmioRegion, MapToError<Size4KiB>> {
+    let first_frame = PhysFrame::<Size4KiB>::containing_address(phys);
+    let offset = phys - first_frame.start_address();
+    let size = offset + len.max(1) as u64;
+
+    let region = region::alloc(size).ok_or(MapToError::FrameAllocationFailed)?;
+    if let Err(error) = region::map_physical(&region, first_frame.start_address(), MMIO_FLAGS) {
+        region::free(region);
+        return Err(error);
+    }
+
+    let base = region.start() + offset;
+    Ok(MmioRegion {
+        region: Some(region),
+        phys,
+        base,
+        len,
+    })
+}
+
+impl MmioRegion {
+    /// Returns the physical address of the first byte of the range.
+    pub fn phys(&self) -> PhysAddr {
+        self.phys
+    }
+
+    /// Returns the virtual address of the first byte of the range.
+    pub fn base(&self) -> VirtAddr {
+        self.base
+    }
+
+    /// Returns the length of the range in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the range is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Reads a value with a single volatile access.
+    ///
+    /// # Arguments
+    /// * `offset` - The offset of the register from the start of the range.
+    ///
+    /// # Panics
+    /// Panics if the access is out of bounds or `offset` is not aligned for `T`.
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { self.register::<T>(offset).read_volatile() }
+    }
+
+    /// Writes a value with a single volatile access.
+    ///
+    /// # Arguments
+    /// * `offset` - The offset of the register from the start of the range.
+    /// * `value` - The value to write.
+    ///
+    /// # Panics
+    /// Panics if the access is out of bounds or `offset` is not aligned for `T`.
+    pub fn write<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { self.register::<T>(offset).write_volatile(value) }
+    }
+
+    /// Reads a 32-bit register.
+    pub fn read_u32(&self, offset: usize) -> u32 {
+        self.read(offset)
+    }
+
+    /// Writes a 32-bit register.
+    pub fn write_u32(&self, offset: usize, value: u32) {
+        self.write(offset, value)
+    }
+
+    /// Reads a 64-bit register.
+    pub fn read_u64(&self, offset: usize) -> u64 {
+        self.read(offset)
+    }
+
+    /// Writes a 64-bit register.
+    pub fn write_u64(&self, offset: usize, value: u64) {
+        self.write(offset, value)
+    }
+
+    /// Returns a pointer to the register of type `T` at `offset`, checking bounds and alignment.
+    fn register<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset
+                .checked_add(mem::size_of::<T>())
+                .is_some_and(|end| end <= self.len),
+            "MMIO access at offset {:#x} out of bounds (length {:#x})",
+            offset,
+            self.len
+        );
+        let ptr: *mut T = (self.base + offset as u64).as_mut_ptr();
+        assert!(
+            ptr.is_aligned(),
+            "misaligned MMIO access at offset {:#x}",
+            offset
+        );
+        ptr
+    }
+}
+
+impl Drop for MmioRegion {
+    /// Unmaps the range and returns its virtual region to the region allocator.
+    fn drop(&mut self) {
+        if let Some(region) = self.region.take() {
+            unsafe { region::unmap_physical(&region) };
+            region::free(region);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use super::mapper;
+use crate::boot_splash::BootScreen;
+use crate::log::LogType;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use core::{mem, slice};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::{
+    mapper::{MappedFrame, TranslateResult},
+    page::PageRange,
+    Mapper, OffsetPageTable, Page, PageSize, PageTableFlags, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+extern "C" {
+    /// The start of the kernel image, placed by the linker on the ELF header.
+    static __ehdr_start: u8;
+}
+
+/// The type of the ELF program headers that describe loadable segments.
+const PT_LOAD: u32 = 1;
+/// The ELF segment flag of executable segments.
+const PF_X: u32 = 1;
+/// The ELF segment flag of writable segments.
+const PF_W: u32 = 2;
+
+/// An ELF64 program header.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct ProgramHeader {
+    p_type: u32,
+    p_flags: u32,
+    p_offset: u64,
+    p_vaddr: u64,
+    p_paddr: u64,
+    p_filesz: u64,
+    p_memsz: u64,
+    p_align: u64,
+}
+
+/// The memory protections supported or enabled on this CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Protections {
+    /// `EFER.NXE`: pages can be marked `NO_EXECUTE`.
+    pub no_execute: bool,
+    /// `CR0.WP`: read-only pages are also read-only for the kernel.
+    pub write_protect: bool,
+    /// `CR4.SMEP`: the kernel cannot execute user pages.
+    pub smep: bool,
+    /// `CR4.SMAP`: the kernel cannot access user pages outside `with_user_access`.
+    pub smap: bool,
+}
+
+/// Returns the protections supported by the CPU, as reported by CPUID.
+pub fn supported() -> Protections {
+    #[allow(unused_unsafe)]
+    let (extended, structured) = unsafe {
+        let extended_edx = if __cpuid(0x8000_0000).eax >= 0x8000_0001 {
+            __cpuid(0x8000_0001).edx
+        } else {
+            0
+        };
+        let structured_ebx = if __cpuid(0).eax >= 7 {
+            __cpuid_count(7, 0).ebx
+        } else {
+            0
+        };
+        (extended_edx, structured_ebx)
+    };
+
+    Protections {
+        no_execute: extended & (1 << 20) != 0,
+        write_protect: true,
+        smep: structured & (1 << 7) != 0,
+        smap: structured & (1 << 20) != 0,
+    }
+}
+
+/// Returns the protections that are currently enabled.
+pub fn enabled() -> Protections {
+    let cr4 = Cr4::read();
+    Protections {
+        no_execute: Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE),
+        write_protect: Cr0::read().contains(Cr0Flags::WRITE_PROTECT),
+        smep: cr4.contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION),
+        smap: cr4.contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION),
+    }
+}
+
+/// Enables every protection the CPU supports.
+///
+/// Called from `crate::init`. Kernel sections are only remapped W^X by `remap_kernel`, which
+/// needs the memory globals.
+pub fn init() {
+    BootScreen::log(LogType::Info, "Enabling memory protections");
+    let supported = supported();
+
+    unsafe {
+        if supported.no_execute {
+            Efer::update(|flags| flags.insert(EferFlags::NO_EXECUTE_ENABLE));
+        }
+        Cr0::update(|flags| flags.insert(Cr0Flags::WRITE_PROTECT));
+
+        let mut cr4 = Cr4Flags::empty();
+        if supported.smep {
+            cr4 |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+        }
+        if supported.smap {
+            cr4 |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+        }
+        Cr4::update(|flags| flags.insert(cr4));
+    }
+
+    if !supported.no_execute {
+        BootScreen::log(LogType::Failed, "No-execute pages are not supported");
+    }
+    BootScreen::log(LogType::Success, "Memory protections enabled");
+}
+
+/// Returns `NO_EXECUTE` if no-execute pages are enabled, or no flags otherwise.
+///
+/// Setting `NO_EXECUTE` without `EFER.NXE` makes the entry invalid, so data mappings should
+/// add this instead of the flag itself.
+pub fn no_execute() -> PageTableFlags {
+    if Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE) {
+        PageTableFlags::NO_EXECUTE
+    } else {
+        PageTableFlags::empty()
+    }
+}
+
+/// Remaps the kernel image W^X.
+///
+/// Every loadable segment of the image is remapped with the permissions of its ELF program
+/// header: segments that are not writable, holding the headers, `.rodata` and `.text`, lose
+/// `WRITABLE`, and segments that are not executable, holding the headers, `.rodata`, `.data`
+/// and `.bss`, gain `NO_EXECUTE`. The linker starts every segment on a new page, so no page
+/// holds both code and writable data.
+pub fn remap_kernel() {
+    BootScreen::log(LogType::Info, "Remapping kernel sections W^X");
+    let nx = no_execute();
+
+    let mut mapper = mapper();
+    for segment in program_headers()
+        .iter()
+        .filter(|phdr| phdr.p_type == PT_LOAD)
+    {
+        let start = VirtAddr::new(segment.p_vaddr);
+        let writable = segment.p_flags & PF_W != 0;
+        let executable = segment.p_flags & PF_X != 0;
+        for page in pages_between(start, start + segment.p_memsz) {
+            update_page(&mut mapper, page, |mut flags| {
+                if !writable {
+                    flags.remove(PageTableFlags::WRITABLE);
+                }
+                if !executable {
+                    flags.insert(nx);
+                }
+                flags
+            });
+        }
+    }
+
+    BootScreen::log(LogType::Success, "Kernel sections remapped");
+}
+
+/// Returns the program headers of the kernel image, read from its ELF header in memory.
+///
+/// The kernel is linked at a fixed address, so the virtual addresses of the headers are the
+/// addresses the segments are mapped at.
+fn program_headers() -> &'static [ProgramHeader] {
+    let ehdr = &raw const __ehdr_start;
+    unsafe {
+        let phoff = ehdr.add(0x20).cast::<u64>().read_unaligned();
+        let phentsize = ehdr.add(0x36).cast::<u16>().read_unaligned();
+        let phnum = ehdr.add(0x38).cast::<u16>().read_unaligned();
+        if usize::from(phentsize) != mem::size_of::<ProgramHeader>() {
+            return &[];
+        }
+        slice::from_raw_parts(ehdr.add(phoff as usize).cast(), usize::from(phnum))
+    }
+}
+
+/// Returns the pages overlapping `start..end`.
+fn pages_between(start: VirtAddr, end: VirtAddr) -> PageRange {
+    Page::range(
+        Page::containing_address(start),
+        Page::containing_address(end.align_up(Size4KiB::SIZE)),
+    )
+}
+
+/// Replaces the flags of a page mapped with 4 KiB pages by `f(flags)`; other pages are skipped.
+fn update_page(
+    mapper: &mut OffsetPageTable,
+    page: Page,
+    f: impl Fn(PageTableFlags) -> PageTableFlags,
+) {
+    let TranslateResult::Mapped {
+        frame: MappedFrame::Size4KiB(_),
+        flags,
+        ..
+    } = mapper.translate(page.start_address())
+    else {
+        return;
+    };
+    if let Ok(flush) = unsafe { mapper.update_flags(page, f(flags)) } {
+        flush.flush();
+    }
+}
+
+/// Runs `f` with SMAP temporarily lifted, so that the kernel can access user pages.
+pub fn with_user_access<R>(f: impl FnOnce() -> R) -> R {
+    let smap = enabled().smap;
+    if smap {
+        unsafe { core::arch::asm!("stac", options(nomem, nostack)) };
+    }
+    let result = f();
+    if smap {
+        unsafe { core::arch::asm!("clac", options(nomem, nostack)) };
+    }
+    result
+}
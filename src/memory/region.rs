@@ -0,0 +1,502 @@
+use super::{buddy::BuddyFrameAllocator, frame_allocator, mapper};
+use spin::Mutex;
+use x86_64::structures::paging::{
+    mapper::{MapToError, MappedFrame, TranslateResult},
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageSize, PageTableFlags,
+    PhysFrame, Size1GiB, Size2MiB, Size4KiB, Translate,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// The start of the virtual address range managed by the region allocator.
+pub const KERNEL_SPACE_START: u64 = 0x4000_0000_0000;
+/// The end of the virtual address range managed by the region allocator.
+pub const KERNEL_SPACE_END: u64 = 0x7000_0000_0000;
+
+/// The maximum number of disjoint free ranges that can be tracked.
+const MAX_FREE_RANGES: usize = 128;
+
+/// The size of a page in bytes.
+const PAGE_SIZE: u64 = Size4KiB::SIZE;
+
+/// The free parts of the managed range as `(start, end)` pairs, kept in a fixed-size table so
+/// that the heap itself can be placed with this allocator.
+static FREE_RANGES: Mutex<[Option<(u64, u64)>; MAX_FREE_RANGES]> = {
+    let mut ranges = [None; MAX_FREE_RANGES];
+    ranges[0] = Some((KERNEL_SPACE_START, KERNEL_SPACE_END));
+    Mutex::new(ranges)
+};
+
+/// A page-aligned range of kernel virtual addresses handed out by the region allocator.
+///
+/// Owning a `VirtRegion` means that no other region overlaps it. It does not imply that
+/// anything is mapped; use `map_region` and `unmap_region` for that.
+#[derive(Debug, PartialEq, Eq)]
+pub struct VirtRegion {
+    /// The first address of the region.
+    start: VirtAddr,
+    /// The size of the region in bytes, a multiple of the page size.
+    size: u64,
+}
+
+impl VirtRegion {
+    /// Returns the first address of the region.
+    pub fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    /// Returns the address directly after the region.
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    /// Returns the size of the region in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the pages covered by the region.
+    pub fn pages(&self) -> impl Iterator<Item = Page> {
+        Page::range(
+            Page::containing_address(self.start),
+            Page::containing_address(self.end()),
+        )
+    }
+
+    /// Returns `true` if the region contains the given address.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end()
+    }
+
+    /// Returns a view of part of this region, for mapping only some of its pages.
+    ///
+    /// The returned value must not be passed to `free`; only the original region is released.
+    ///
+    /// # Panics
+    /// Panics if the part is not page-aligned or not contained in this region.
+    pub fn sub_region(&self, offset: u64, size: u64) -> VirtRegion {
+        assert!(
+            offset & (PAGE_SIZE - 1) == 0 && size & (PAGE_SIZE - 1) == 0,
+            "sub-region must be page-aligned"
+        );
+        assert!(offset + size <= self.size, "sub-region out of bounds");
+        VirtRegion {
+            start: self.start + offset,
+            size,
+        }
+    }
+}
+
+/// Allocates a free region of at least `size` bytes.
+///
+/// # Arguments
+/// * `size` - The requested size, rounded up to whole pages.
+///
+/// # Returns
+/// The region, or `None` if no free range is large enough.
+pub fn alloc(size: u64) -> Option<VirtRegion> {
+    alloc_aligned(size, PAGE_SIZE)
+}
+
+/// Allocates a free region of at least `size` bytes whose start is aligned to `align`.
+///
+/// # Arguments
+/// * `size` - The requested size, rounded up to whole pages.
+/// * `align` - The alignment of the start address, a power of two of at least the page size.
+///
+/// # Returns
+/// The region, or `None` if no free range can hold it.
+pub fn alloc_aligned(size: u64, align: u64) -> Option<VirtRegion> {
+    assert!(align.is_power_of_two() && align >= PAGE_SIZE);
+    let size = align_up(size.max(1), PAGE_SIZE);
+
+    let mut ranges = FREE_RANGES.lock();
+    let (index, start) = ranges.iter().enumerate().find_map(|(index, range)| {
+        let (range_start, range_end) = (*range)?;
+        let start = align_up(range_start, align);
+        (start.checked_add(size)? <= range_end).then_some((index, start))
+    })?;
+
+    take_from(&mut ranges, index, start, start + size).then(|| VirtRegion {
+        start: VirtAddr::new(start),
+        size,
+    })
+}
+
+/// Claims the fixed range `start..start + size`, for mappings that have to live at a known
+/// address such as the kernel heap.
+///
+/// # Arguments
+/// * `start` - The page-aligned first address of the range.
+/// * `size` - The size of the range, rounded up to whole pages.
+///
+/// # Returns
+/// The region, or `None` if part of the range is already allocated or outside the managed range.
+pub fn reserve(start: VirtAddr, size: u64) -> Option<VirtRegion> {
+    let start = start.as_u64();
+    assert!(
+        start & (PAGE_SIZE - 1) == 0,
+        "reserved region must be page-aligned"
+    );
+    let size = align_up(size.max(1), PAGE_SIZE);
+    let end = start.checked_add(size)?;
+
+    let mut ranges = FREE_RANGES.lock();
+    let index = ranges.iter().position(|range| {
+        range.is_some_and(|(range_start, range_end)| range_start <= start && end <= range_end)
+    })?;
+
+    take_from(&mut ranges, index, start, end).then(|| VirtRegion {
+        start: VirtAddr::new(start),
+        size,
+    })
+}
+
+/// Returns a region to the allocator, merging it with adjacent free ranges.
+///
+/// # Arguments
+/// * `region` - The region to free. Its pages should be unmapped first.
+///
+/// # Panics
+/// Panics if the free range table is full.
+pub fn free(region: VirtRegion) {
+    let (mut start, mut end) = (region.start.as_u64(), region.end().as_u64());
+
+    let mut ranges = FREE_RANGES.lock();
+    for slot in ranges.iter_mut() {
+        match *slot {
+            Some((range_start, range_end)) if range_end == start => {
+                start = range_start;
+                *slot = None;
+            }
+            Some((range_start, range_end)) if range_start == end => {
+                end = range_end;
+                *slot = None;
+            }
+            _ => {}
+        }
+    }
+    insert(&mut ranges, start, end);
+}
+
+/// Returns the number of bytes of the managed range that are not allocated.
+pub fn free_bytes() -> u64 {
+    FREE_RANGES
+        .lock()
+        .iter()
+        .flatten()
+        .map(|(start, end)| end - start)
+        .sum()
+}
+
+/// Backs every page of `region` with a fresh frame.
+///
+/// Parts of the region that are 2 MiB aligned are mapped with 2 MiB pages as long as the frame
+/// allocator can provide huge frames; everything else uses 4 KiB pages.
+///
+/// # Arguments
+/// * `region` - The region to map. None of its pages may be mapped yet.
+/// * `flags` - The flags to map the pages with. `PRESENT` is added automatically.
+///
+/// # Returns
+/// An error if frame allocation or mapping fails, in which case every page mapped by this call
+/// is unmapped again and its frame released.
+pub fn map_region(region: &VirtRegion, flags: PageTableFlags) -> Result<(), MapToError<Size4KiB>> {
+    map_fresh(
+        &mut mapper(),
+        &mut frame_allocator(),
+        region.start(),
+        region.end(),
+        flags,
+    )
+}
+
+/// Backs every page in `start..end` with a fresh frame like `map_region`, for callers that
+/// already hold the mapper and frame allocator, such as the heap.
+///
+/// # Arguments
+/// * `mapper` - The kernel's mapper.
+/// * `frame_allocator` - The kernel's frame allocator.
+/// * `start` - The page-aligned start of the range. None of its pages may be mapped yet.
+/// * `end` - The page-aligned end of the range.
+/// * `flags` - The flags to map the pages with. `PRESENT` is added automatically.
+///
+/// # Returns
+/// An error if frame allocation or mapping fails, in which case every page mapped by this call
+/// is unmapped again and its frame released.
+pub(crate) fn map_fresh(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BuddyFrameAllocator,
+    start: VirtAddr,
+    end: VirtAddr,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    let flags = flags | PageTableFlags::PRESENT;
+    let mut addr = start;
+    while addr < end {
+        let result = if addr.is_aligned(Size2MiB::SIZE) && end - addr >= Size2MiB::SIZE {
+            match FrameAllocator::<Size2MiB>::allocate_frame(frame_allocator) {
+                Some(frame) => map_page(mapper, frame_allocator, addr, frame, flags)
+                    .inspect_err(|_| unsafe { frame_allocator.deallocate_frame(frame) }),
+                None => map_small_page(mapper, frame_allocator, addr, flags),
+            }
+        } else {
+            map_small_page(mapper, frame_allocator, addr, flags)
+        };
+
+        match result {
+            Ok(size) => addr += size,
+            Err(error) => {
+                unmap_range(mapper, start, addr, |frame| unsafe {
+                    release_frame(frame_allocator, frame)
+                });
+                return Err(error);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Unmaps every mapped page of `region` and returns the frames to the frame allocator.
+///
+/// Pages that are not mapped are skipped, so this also works for partially populated regions.
+/// Both 4 KiB and 2 MiB mappings are handled, and swapped-out pages release their swap slots.
+///
+/// # Arguments
+/// * `region` - The region to unmap.
+///
+/// # Safety
+/// No references into the region may be used afterwards, and the frames must not be mapped
+/// anywhere else.
+pub unsafe fn unmap_region(region: &VirtRegion) {
+    let mut mapper = mapper();
+    let mut frame_allocator = frame_allocator();
+    super::swap::discard_in(&mut mapper, region.start(), region.end());
+    unmap_range(&mut mapper, region.start(), region.end(), |frame| {
+        release_frame(&mut frame_allocator, frame)
+    });
+}
+
+/// Maps `region` onto the physical range starting at `phys`, such as a window onto RAM or a
+/// device.
+///
+/// The largest page size that alignment permits is used for each part of the range: 1 GiB
+/// pages if the CPU supports them, then 2 MiB pages, then 4 KiB pages. Regions meant for huge
+/// pages should come from `alloc_aligned`.
+///
+/// # Arguments
+/// * `region` - The region to map. None of its pages may be mapped yet.
+/// * `phys` - The page-aligned physical address mapped at the start of the region.
+/// * `flags` - The flags to map the pages with. `PRESENT` is added automatically.
+///
+/// # Returns
+/// An error if a page table could not be allocated, in which case every page mapped by this call
+/// is unmapped again.
+///
+/// # Safety
+/// Accessing the physical range with the given flags must be safe, and it must stay valid
+/// until the region is unmapped with `unmap_physical`.
+pub unsafe fn map_physical(
+    region: &VirtRegion,
+    phys: PhysAddr,
+    flags: PageTableFlags,
+) -> Result<(), MapToError<Size4KiB>> {
+    assert!(
+        phys.is_aligned(PAGE_SIZE),
+        "physical range must be page-aligned"
+    );
+    let flags = flags | PageTableFlags::PRESENT;
+    let huge_1gib = super::supports_1gib_pages();
+    let mut mapper = mapper();
+    let mut frame_allocator = frame_allocator();
+
+    let mut addr = region.start();
+    while addr < region.end() {
+        let frame_addr = phys + (addr - region.start());
+        let fits = |size: u64| {
+            addr.is_aligned(size) && frame_addr.is_aligned(size) && region.end() - addr >= size
+        };
+
+        let result = if huge_1gib && fits(Size1GiB::SIZE) {
+            let frame = PhysFrame::<Size1GiB>::containing_address(frame_addr);
+            map_page(&mut mapper, &mut frame_allocator, addr, frame, flags)
+        } else if fits(Size2MiB::SIZE) {
+            let frame = PhysFrame::<Size2MiB>::containing_address(frame_addr);
+            map_page(&mut mapper, &mut frame_allocator, addr, frame, flags)
+        } else {
+            let frame = PhysFrame::<Size4KiB>::containing_address(frame_addr);
+            map_page(&mut mapper, &mut frame_allocator, addr, frame, flags)
+        };
+
+        match result {
+            Ok(size) => addr += size,
+            Err(error) => {
+                unmap_range(&mut mapper, region.start(), addr, |_| {});
+                return Err(error);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Unmaps a region mapped with `map_physical` without releasing the underlying frames.
+///
+/// # Arguments
+/// * `region` - The region to unmap.
+///
+/// # Safety
+/// No references into the region may be used afterwards.
+pub unsafe fn unmap_physical(region: &VirtRegion) {
+    unmap_range(&mut mapper(), region.start(), region.end(), |_| {});
+}
+
+/// Maps a fresh 4 KiB frame at `addr`.
+///
+/// # Returns
+/// The size of the mapped page, or an error if frame allocation or mapping fails.
+fn map_small_page(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BuddyFrameAllocator,
+    addr: VirtAddr,
+    flags: PageTableFlags,
+) -> Result<u64, MapToError<Size4KiB>> {
+    let frame: PhysFrame<Size4KiB> = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    map_page(mapper, frame_allocator, addr, frame, flags)
+        .inspect_err(|_| unsafe { frame_allocator.deallocate_frame(frame) })
+}
+
+/// Maps the page of size `S` starting at `addr` to `frame`.
+///
+/// # Returns
+/// The size of the mapped page, or the mapping error converted to its 4 KiB equivalent.
+fn map_page<S: PageSize>(
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut BuddyFrameAllocator,
+    addr: VirtAddr,
+    frame: PhysFrame<S>,
+    flags: PageTableFlags,
+) -> Result<u64, MapToError<Size4KiB>>
+where
+    for<'a> OffsetPageTable<'a>: Mapper<S>,
+{
+    let page = Page::<S>::containing_address(addr);
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            Ok(S::SIZE)
+        }
+        Err(MapToError::FrameAllocationFailed) => Err(MapToError::FrameAllocationFailed),
+        Err(MapToError::ParentEntryHugePage) => Err(MapToError::ParentEntryHugePage),
+        Err(MapToError::PageAlreadyMapped(frame)) => Err(MapToError::PageAlreadyMapped(
+            PhysFrame::containing_address(frame.start_address()),
+        )),
+    }
+}
+
+/// Unmaps every mapping in `start..end`, whatever its page size, and passes the frames that were
+/// mapped to `release`.
+fn unmap_range(
+    mapper: &mut OffsetPageTable,
+    start: VirtAddr,
+    end: VirtAddr,
+    mut release: impl FnMut(MappedFrame),
+) {
+    let mut addr = start;
+    while addr < end {
+        let TranslateResult::Mapped { frame, .. } = mapper.translate(addr) else {
+            addr = addr.align_down(PAGE_SIZE) + PAGE_SIZE;
+            continue;
+        };
+
+        let unmapped = match frame {
+            MappedFrame::Size4KiB(_) => {
+                Mapper::<Size4KiB>::unmap(mapper, Page::containing_address(addr))
+                    .map(|(_, flush)| flush.flush())
+            }
+            MappedFrame::Size2MiB(_) => {
+                Mapper::<Size2MiB>::unmap(mapper, Page::containing_address(addr))
+                    .map(|(_, flush)| flush.flush())
+            }
+            MappedFrame::Size1GiB(_) => {
+                Mapper::<Size1GiB>::unmap(mapper, Page::containing_address(addr))
+                    .map(|(_, flush)| flush.flush())
+            }
+        };
+        let size = frame.size();
+        if unmapped.is_ok() {
+            release(frame);
+        }
+        addr = addr.align_down(size) + size;
+    }
+}
+
+/// Returns a frame taken from the frame allocator by `map_region`.
+///
+/// Frames the allocator does not manage, such as device memory, are skipped.
+///
+/// # Safety
+/// The frame must not be mapped anywhere anymore.
+///
+/// # Panics
+/// Panics on 1 GiB frames, which the frame allocator never hands out.
+unsafe fn release_frame(frame_allocator: &mut BuddyFrameAllocator, frame: MappedFrame) {
+    let managed = |addr| frame_allocator.manages(PhysFrame::containing_address(addr));
+    match frame {
+        MappedFrame::Size4KiB(frame) if managed(frame.start_address()) => {
+            frame_allocator.deallocate_frame(frame)
+        }
+        MappedFrame::Size2MiB(frame) if managed(frame.start_address()) => {
+            frame_allocator.deallocate_frame(frame)
+        }
+        MappedFrame::Size4KiB(_) | MappedFrame::Size2MiB(_) => {}
+        MappedFrame::Size1GiB(frame) => {
+            panic!("{:?} was not allocated by the frame allocator", frame)
+        }
+    }
+}
+
+/// Removes `start..end` from the free range at `index`, keeping the parts before and after it.
+///
+/// # Returns
+/// `false` if the table has no room for the remaining parts, in which case nothing is changed.
+fn take_from(
+    ranges: &mut [Option<(u64, u64)>; MAX_FREE_RANGES],
+    index: usize,
+    start: u64,
+    end: u64,
+) -> bool {
+    let (range_start, range_end) = ranges[index].expect("free range disappeared");
+    let needs_slot = range_start < start && end < range_end;
+    if needs_slot && ranges.iter().all(|slot| slot.is_some()) {
+        return false;
+    }
+
+    ranges[index] = None;
+    if range_start < start {
+        insert(ranges, range_start, start);
+    }
+    if end < range_end {
+        insert(ranges, end, range_end);
+    }
+    true
+}
+
+/// Stores a free range in the first empty slot.
+///
+/// # Panics
+/// Panics if the table is full.
+fn insert(ranges: &mut [Option<(u64, u64)>; MAX_FREE_RANGES], start: u64, end: u64) {
+    let slot = ranges
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("virtual region table full");
+    *slot = Some((start, end));
+}
+
+/// Aligns `value` upwards to the given power-of-two alignment.
+fn align_up(value: u64, align: u64) -> u64 {
+    (value + align - 1) & !(align - 1)
+}
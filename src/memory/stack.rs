@@ -0,0 +1,148 @@
+use super::{mapper, region};
+use spin::Mutex;
+use x86_64::structures::paging::{
+    mapper::MapToError, Page, PageSize, PageTableFlags, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+/// The maximum number of stacks whose guard pages can be registered.
+const MAX_STACKS: usize = 32;
+
+/// The maximum number of pages searched below the current stack pointer by `register_current`.
+const MAX_BOOT_STACK_PAGES: u64 = 1024;
+
+/// The guard pages of every known stack, together with the name of the stack.
+///
+/// The registry is a fixed-size table because it is consulted by the fault handlers, which must
+/// not allocate.
+static GUARDS: Mutex<[Option<(Page, &'static str)>; MAX_STACKS]> = Mutex::new([None; MAX_STACKS]);
+
+/// A kernel stack mapped in its own virtual region, with an unmapped guard page below it.
+#[derive(Debug, Clone, Copy)]
+pub struct KernelStack {
+    /// The name of the stack, used in overflow reports.
+    name: &'static str,
+    /// The unmapped page directly below the stack.
+    guard: Page,
+    /// The lowest usable address of the stack.
+    bottom: VirtAddr,
+    /// The address directly above the stack, which is the initial stack pointer.
+    top: VirtAddr,
+}
+
+impl KernelStack {
+    /// Returns the name of the stack.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the unmapped guard page below the stack.
+    pub fn guard_page(&self) -> Page {
+        self.guard
+    }
+
+    /// Returns the lowest usable address of the stack.
+    pub fn bottom(&self) -> VirtAddr {
+        self.bottom
+    }
+
+    /// Returns the initial stack pointer, directly above the highest usable address.
+    pub fn top(&self) -> VirtAddr {
+        self.top
+    }
+}
+
+/// Allocates and maps a new kernel stack.
+///
+/// The stack gets its own region from the virtual region allocator, consisting of an unmapped
+/// guard page followed by `pages` mapped pages. The guard page is registered so that
+/// overflowing the stack is reported with its name.
+///
+/// # Arguments
+/// * `name` - The name of the stack, used in overflow reports.
+/// * `pages` - The number of usable 4 KiB pages.
+///
+/// # Returns
+/// The new stack, or an error if no virtual region is available or mapping fails.
+///
+/// # Panics
+/// Panics if `MAX_STACKS` guard pages are already registered.
+pub fn alloc(name: &'static str, pages: u64) -> Result<KernelStack, MapToError<Size4KiB>> {
+    let region =
+        region::alloc((pages + 1) * Size4KiB::SIZE).ok_or(MapToError::FrameAllocationFailed)?;
+    let stack_region = region.sub_region(Size4KiB::SIZE, pages * Size4KiB::SIZE);
+    if let Err(error) = region::map_region(
+        &stack_region,
+        PageTableFlags::WRITABLE | super::protect::no_execute(),
+    ) {
+        region::free(region);
+        return Err(error);
+    }
+
+    let guard = Page::containing_address(region.start());
+    let bottom = stack_region.start();
+    let top = stack_region.end();
+
+    register_guard(guard, name);
+    Ok(KernelStack {
+        name,
+        guard,
+        bottom,
+        top,
+    })
+}
+
+/// Registers the guard page of the stack that is currently in use.
+///
+/// Meant for the stack set up by the bootloader: starting at the current stack pointer, the
+/// first unmapped page below it is taken as its guard page.
+///
+/// # Arguments
+/// * `name` - The name of the stack, used in overflow reports.
+///
+/// # Returns
+/// The guard page, or `None` if no unmapped page was found within `MAX_BOOT_STACK_PAGES`.
+pub fn register_current(name: &'static str) -> Option<Page> {
+    let rsp: u64;
+    unsafe {
+        core::arch::asm!("mov {}, rsp", out(reg) rsp, options(nomem, nostack));
+    }
+
+    let guard = {
+        let mapper = mapper();
+        let current = Page::<Size4KiB>::containing_address(VirtAddr::new(rsp));
+        (1..=MAX_BOOT_STACK_PAGES)
+            .map(|offset| current - offset)
+            .find(|page| mapper.translate_addr(page.start_address()).is_none())?
+    };
+
+    register_guard(guard, name);
+    Some(guard)
+}
+
+/// Returns the name of the stack whose guard page contains `addr`, if any.
+///
+/// This is called from the fault handlers, so the registry lock is only tried. A fault raised
+/// while the registry is locked is not recognised as a stack overflow.
+pub fn guard_page_owner(addr: VirtAddr) -> Option<&'static str> {
+    let page = Page::<Size4KiB>::containing_address(addr);
+    GUARDS
+        .try_lock()?
+        .iter()
+        .flatten()
+        .find(|(guard, _)| *guard == page)
+        .map(|(_, name)| *name)
+}
+
+/// Adds a guard page to the registry.
+///
+/// # Panics
+/// Panics if `MAX_STACKS` guard pages are already registered.
+fn register_guard(guard: Page, name: &'static str) {
+    let mut guards = GUARDS.lock();
+    let slot = guards
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .expect("too many kernel stacks registered");
+    *slot = Some((guard, name));
+}
@@ -0,0 +1,650 @@
+use super::address_space::{self, USER_ENTRIES};
+use super::region::{self, VirtRegion};
+use super::{cow, vma};
+use super::{
+    frame_allocator, mapper, phys_to_virt, protect, try_active_mapper, try_frame_allocator,
+    try_mapper,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Mutex;
+use x86_64::instructions::tlb;
+use x86_64::structures::paging::{
+    mapper::MapToError, page_table::PageTableEntry, FrameAllocator, OffsetPageTable, Page,
+    PageSize, PageTable, PageTableFlags, PageTableIndex, PhysFrame, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// The page table flag marking a not-present entry whose page lives in the swap store. The
+/// address bits of such an entry hold the swap slot instead of a frame.
+pub const SWAPPED: PageTableFlags = PageTableFlags::BIT_10;
+
+/// The size of a swap slot, one page.
+const SLOT_SIZE: u64 = Size4KiB::SIZE;
+
+/// The maximum number of slots a swap store can have.
+pub const MAX_SLOTS: usize = 4096;
+
+/// The number of pages evicted at once when frames run out.
+pub const RECLAIM_BATCH: usize = 32;
+
+/// The maximum number of reclaimable ranges that can be registered at the same time.
+const MAX_RANGES: usize = 16;
+
+/// The swap store, created by `init`.
+static STORE: Mutex<Option<SwapStore>> = Mutex::new(None);
+
+/// The ranges whose pages may be evicted, as `(start, end)` pairs.
+static RANGES: Mutex<[Option<(VirtAddr, VirtAddr)>; MAX_RANGES]> = Mutex::new([None; MAX_RANGES]);
+
+/// The clock hand of `reclaim`: the index of the next reclaimable page to look at.
+static HAND: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of pages written to the swap store.
+static SWAPPED_OUT: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of pages read back from the swap store.
+static SWAPPED_IN: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of clean demand-zero pages that were dropped instead of being written out.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// The reasons a page could not be swapped out or a range not registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapError {
+    /// `init` has not been called.
+    NoStore,
+    /// Every slot of the swap store is in use.
+    StoreFull,
+    /// No frame was available to read a page back in.
+    OutOfFrames,
+    /// The page is not inside a reclaimable range.
+    NotReclaimable,
+    /// The page is not mapped.
+    NotMapped,
+    /// The page is part of a huge page; only 4 KiB pages are swapped.
+    HugePage,
+    /// The page's frame is shared copy-on-write.
+    Shared,
+    /// The range is misaligned, empty or overlaps a registered range.
+    InvalidRange,
+    /// `MAX_RANGES` ranges are already registered.
+    TableFull,
+    /// The area registry was locked, so the page could not be classified.
+    Busy,
+}
+
+/// Usage counters of the swap subsystem.
+#[derive(Debug, Clone, Copy)]
+pub struct SwapStats {
+    /// The number of slots in the swap store.
+    pub slots: usize,
+    /// The number of slots holding a page.
+    pub used_slots: usize,
+    /// The number of pages written to the swap store.
+    pub swapped_out: usize,
+    /// The number of pages read back from the swap store.
+    pub swapped_in: usize,
+    /// The number of clean demand-zero pages that were dropped instead of being written out.
+    pub dropped: usize,
+}
+
+/// A swap store backed by a reserved range of kernel memory.
+struct SwapStore {
+    /// The memory holding the slots.
+    region: VirtRegion,
+    /// The number of slots.
+    slots: usize,
+    /// One bit per slot, set if the slot is in use.
+    used: [u64; MAX_SLOTS / 64],
+    /// The number of slots in use.
+    used_slots: usize,
+}
+
+impl SwapStore {
+    /// Returns a pointer to the page-sized slot `slot`.
+    fn slot_ptr(&self, slot: usize) -> *mut u8 {
+        (self.region.start() + slot as u64 * SLOT_SIZE).as_mut_ptr()
+    }
+
+    /// Claims a free slot.
+    fn alloc(&mut self) -> Option<usize> {
+        let slot = (0..self.slots).find(|&slot| self.used[slot / 64] & (1 << (slot & 63)) == 0)?;
+        self.used[slot / 64] |= 1 << (slot & 63);
+        self.used_slots += 1;
+        Some(slot)
+    }
+
+    /// Releases a slot claimed by `alloc`.
+    fn free(&mut self, slot: usize) {
+        assert!(
+            self.used[slot / 64] & (1 << (slot & 63)) != 0,
+            "freeing swap slot {} that is not in use",
+            slot
+        );
+        self.used[slot / 64] &= !(1 << (slot & 63));
+        self.used_slots -= 1;
+    }
+}
+
+/// Creates the swap store with room for `slots` pages of kernel memory.
+///
+/// # Returns
+/// An error if the store's memory could not be mapped.
+///
+/// # Panics
+/// Panics if `slots` exceeds `MAX_SLOTS` or the store already exists.
+pub fn init(slots: usize) -> Result<(), MapToError<Size4KiB>> {
+    assert!(
+        slots <= MAX_SLOTS,
+        "swap store larger than {} slots",
+        MAX_SLOTS
+    );
+    let region =
+        region::alloc(slots as u64 * SLOT_SIZE).ok_or(MapToError::FrameAllocationFailed)?;
+    if let Err(error) =
+        region::map_region(&region, PageTableFlags::WRITABLE | protect::no_execute())
+    {
+        region::free(region);
+        return Err(error);
+    }
+
+    let mut store = STORE.lock();
+    assert!(store.is_none(), "swap store already initialized");
+    *store = Some(SwapStore {
+        region,
+        slots,
+        used: [0; MAX_SLOTS / 64],
+        used_slots: 0,
+    });
+    Ok(())
+}
+
+/// Returns the usage counters of the swap subsystem.
+pub fn stats() -> SwapStats {
+    let (slots, used_slots) = match &*STORE.lock() {
+        Some(store) => (store.slots, store.used_slots),
+        None => (0, 0),
+    };
+    SwapStats {
+        slots,
+        used_slots,
+        swapped_out: SWAPPED_OUT.load(Ordering::Relaxed),
+        swapped_in: SWAPPED_IN.load(Ordering::Relaxed),
+        dropped: DROPPED.load(Ordering::Relaxed),
+    }
+}
+
+/// Allows the pages of a range to be evicted by `swap_out` and `reclaim`.
+///
+/// # Arguments
+/// * `start` - The page-aligned first address of the range.
+/// * `size` - The size of the range in bytes, a non-zero multiple of the page size.
+pub fn mark_reclaimable(start: VirtAddr, size: u64) -> Result<(), SwapError> {
+    if size == 0 || !start.is_aligned(SLOT_SIZE) || size & (SLOT_SIZE - 1) != 0 {
+        return Err(SwapError::InvalidRange);
+    }
+    let end = start + size;
+
+    let mut ranges = RANGES.lock();
+    if ranges
+        .iter()
+        .flatten()
+        .any(|&(other_start, other_end)| start < other_end && other_start < end)
+    {
+        return Err(SwapError::InvalidRange);
+    }
+    let slot = ranges
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(SwapError::TableFull)?;
+    *slot = Some((start, end));
+    Ok(())
+}
+
+/// Brings every swapped page of the range starting at `start` back in and stops evicting it.
+///
+/// # Returns
+/// An error if a page could not be swapped back in for lack of frames; the range stays
+/// reclaimable then.
+pub fn unmark_reclaimable(start: VirtAddr) -> Result<(), SwapError> {
+    let mut ranges = RANGES.lock();
+    let Some(slot) = ranges
+        .iter_mut()
+        .find(|slot| slot.is_some_and(|(range_start, _)| range_start == start))
+    else {
+        return Err(SwapError::NotReclaimable);
+    };
+    let (start, end) = slot.unwrap();
+
+    let mut mapper = mapper();
+    let mut frame_allocator = frame_allocator();
+    let mut store = STORE.lock();
+    if let Some(store) = store.as_mut() {
+        for page in Page::range(
+            Page::containing_address(start),
+            Page::containing_address(end),
+        ) {
+            if let Ok(entry) = p1_entry(&mut mapper, page) {
+                if entry.flags().contains(SWAPPED)
+                    && !swap_in(entry, page, store, &mut frame_allocator)
+                {
+                    return Err(SwapError::OutOfFrames);
+                }
+            }
+        }
+    }
+    *slot = None;
+    Ok(())
+}
+
+/// Releases the swap slots of every swapped page in `start..end` and clears their entries.
+///
+/// Called before a range is unmapped, since unmapping skips not-present entries.
+///
+/// # Safety
+/// The contents of the swapped pages are lost.
+pub unsafe fn discard(start: VirtAddr, end: VirtAddr) {
+    discard_in(&mut mapper(), start, end);
+}
+
+/// Releases the swap slots of every swapped page of `mapper` in `start..end` like `discard`,
+/// for callers that already hold a mapper, such as `region::unmap_region` or an address space.
+///
+/// # Returns
+/// The number of swapped pages that were discarded.
+///
+/// # Safety
+/// The contents of the swapped pages are lost.
+pub(crate) unsafe fn discard_in(
+    mapper: &mut OffsetPageTable,
+    start: VirtAddr,
+    end: VirtAddr,
+) -> usize {
+    let mut store = STORE.lock();
+    let Some(store) = store.as_mut() else {
+        return 0;
+    };
+    let mut discarded = 0;
+    for page in Page::range(
+        Page::containing_address(start),
+        Page::containing_address(end),
+    ) {
+        if let Ok(entry) = p1_entry(mapper, page) {
+            if entry.flags().contains(SWAPPED) {
+                store.free(slot_of(entry));
+                entry.set_unused();
+                discarded += 1;
+            }
+        }
+    }
+    discarded
+}
+
+/// Releases the swap slot of a `SWAPPED` level 1 entry of a page table that is being freed.
+pub(crate) fn release_slot(entry: &PageTableEntry) {
+    if let Some(store) = STORE.lock().as_mut() {
+        store.free(slot_of(entry));
+    }
+}
+
+/// Evicts the page containing `addr`.
+///
+/// Clean pages inside a virtual memory area have never been written since the area mapped them
+/// zeroed, so they are simply unmapped and come back zeroed on the next access. Every other
+/// page is copied into a swap slot and its entry marked `SWAPPED`.
+///
+/// # Returns
+/// An error if the page is not reclaimable, not a mapped private 4 KiB page, or the store is
+/// full.
+pub fn swap_out(addr: VirtAddr) -> Result<(), SwapError> {
+    if !is_reclaimable(addr) {
+        return Err(SwapError::NotReclaimable);
+    }
+    let mut mapper = mapper();
+    let mut frame_allocator = frame_allocator();
+    let mut store = STORE.lock();
+    let store = store.as_mut().ok_or(SwapError::NoStore)?;
+    evict(
+        &mut mapper,
+        Page::containing_address(addr),
+        store,
+        &mut frame_allocator,
+    )
+}
+
+/// Evicts up to `pages` cold pages from the reclaimable ranges and the user parts of the live
+/// address spaces.
+///
+/// Pages of the reclaimable ranges are visited in a circle, like the hands of a clock. A page
+/// accessed since the last visit gets a second chance: its `ACCESSED` bit is cleared and it is
+/// skipped. Every page is visited at most twice per call, so the scan ends even if nothing can
+/// be evicted. User pages are only scanned if the ranges did not yield enough pages, with the
+/// same second chance.
+///
+/// # Returns
+/// The number of pages that were evicted.
+pub fn reclaim(pages: usize) -> usize {
+    let ranges = *RANGES.lock();
+    let mut mapper = mapper();
+    let mut frame_allocator = frame_allocator();
+    let mut store = STORE.lock();
+    match store.as_mut() {
+        Some(store) => reclaim_locked(pages, &ranges, &mut mapper, &mut frame_allocator, store),
+        None => 0,
+    }
+}
+
+/// Evicts up to `pages` cold pages like `reclaim`, unless one of the locks involved is held.
+///
+/// This is meant for paths that run short of frames while they may interrupt a holder of those
+/// locks, such as the heap growing or the page fault handler.
+///
+/// # Returns
+/// The number of pages that were evicted, 0 if a lock was held.
+pub fn try_reclaim(pages: usize) -> usize {
+    let Some(ranges) = RANGES.try_lock().map(|ranges| *ranges) else {
+        return 0;
+    };
+    let (Some(mut mapper), Some(mut frame_allocator)) = (try_mapper(), try_frame_allocator())
+    else {
+        return 0;
+    };
+    let Some(mut store) = STORE.try_lock() else {
+        return 0;
+    };
+    match store.as_mut() {
+        Some(store) => reclaim_locked(pages, &ranges, &mut mapper, &mut frame_allocator, store),
+        None => 0,
+    }
+}
+
+/// Evicts `RECLAIM_BATCH` cold pages if no free frame is left.
+///
+/// Called by the page fault handler when it could not resolve a fault, which may have been for
+/// lack of a frame. Every lock is only tried.
+///
+/// # Returns
+/// `true` if pages were evicted, so that resolving the fault again may succeed.
+pub fn relieve_pressure() -> bool {
+    let exhausted = try_frame_allocator().is_some_and(|allocator| allocator.free_frames() == 0);
+    exhausted && try_reclaim(RECLAIM_BATCH) > 0
+}
+
+/// Evicts up to `pages` cold pages, for `reclaim` and `try_reclaim`.
+fn reclaim_locked(
+    pages: usize,
+    ranges: &[Option<(VirtAddr, VirtAddr)>],
+    mapper: &mut OffsetPageTable,
+    frame_allocator: &mut super::buddy::BuddyFrameAllocator,
+    store: &mut SwapStore,
+) -> usize {
+    let total: usize = ranges
+        .iter()
+        .flatten()
+        .map(|&(start, end)| ((end - start) / SLOT_SIZE) as usize)
+        .sum();
+
+    let mut evicted = 0;
+    for _ in 0..2 * total {
+        if evicted == pages {
+            return evicted;
+        }
+        let index = HAND.fetch_add(1, Ordering::Relaxed) % total;
+        match visit(mapper, nth_page(ranges, index), store, frame_allocator) {
+            Ok(true) => evicted += 1,
+            Err(SwapError::StoreFull) => return evicted,
+            _ => {}
+        }
+    }
+
+    let offset = mapper.phys_offset();
+    for _ in 0..2 {
+        for level_4_frame in address_space::registered_spaces().into_iter().flatten() {
+            let table = unsafe { &mut *phys_to_virt(level_4_frame.start_address()).as_mut_ptr() };
+            let mut space = unsafe { OffsetPageTable::new(table, offset) };
+            match reclaim_user_pages(pages - evicted, &mut space, store, frame_allocator) {
+                Ok(count) => evicted += count,
+                Err(count) => return evicted + count,
+            }
+            if evicted == pages {
+                return evicted;
+            }
+        }
+    }
+    evicted
+}
+
+/// Visits every mapped 4 KiB page of the user part of an address space once, giving each a
+/// second chance like `reclaim` does, until `pages` pages were evicted.
+///
+/// # Returns
+/// The number of pages that were evicted, as an error if the store filled up.
+fn reclaim_user_pages(
+    pages: usize,
+    space: &mut OffsetPageTable,
+    store: &mut SwapStore,
+    frame_allocator: &mut super::buddy::BuddyFrameAllocator,
+) -> Result<usize, usize> {
+    let mut evicted = 0;
+    for p4 in USER_ENTRIES {
+        let Some(level_3) = next_table(&space.level_4_table()[p4]) else {
+            continue;
+        };
+        for p3 in 0..512 {
+            let Some(level_2) = next_table(&unsafe { &*level_3 }[p3]) else {
+                continue;
+            };
+            for p2 in 0..512 {
+                if next_table(&unsafe { &*level_2 }[p2]).is_none() {
+                    continue;
+                }
+                for p1 in 0..512 {
+                    if evicted == pages {
+                        return Ok(evicted);
+                    }
+                    match visit(space, page_at(p4, p3, p2, p1), store, frame_allocator) {
+                        Ok(true) => evicted += 1,
+                        Err(SwapError::StoreFull) => return Err(evicted),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    Ok(evicted)
+}
+
+/// Gives a page a second chance if it was accessed since the last visit and evicts it
+/// otherwise.
+///
+/// # Returns
+/// `true` if the page was evicted, `false` if it was skipped.
+fn visit(
+    mapper: &mut OffsetPageTable,
+    page: Page,
+    store: &mut SwapStore,
+    frame_allocator: &mut super::buddy::BuddyFrameAllocator,
+) -> Result<bool, SwapError> {
+    let entry = p1_entry(mapper, page)?;
+    let flags = entry.flags();
+    if !flags.contains(PageTableFlags::PRESENT) {
+        return Ok(false);
+    }
+    if flags.contains(PageTableFlags::ACCESSED) {
+        entry.set_flags(flags - PageTableFlags::ACCESSED);
+        tlb::flush(page.start_address());
+        return Ok(false);
+    }
+    evict(mapper, page, store, frame_allocator).map(|()| true)
+}
+
+/// Resolves a not-present page fault at `addr` by reading the page back from the swap store.
+///
+/// Called by the page fault handler. Every lock is only tried, so a fault raised while the
+/// mapper, the frame allocator or the store is held is reported instead of deadlocking.
+///
+/// # Arguments
+/// * `addr` - The faulting address, as read from `Cr2`.
+///
+/// # Returns
+/// `true` if the page is mapped again and the faulting instruction can be retried, `false` if
+/// the page was not swapped out.
+pub fn handle_page_fault(addr: VirtAddr) -> bool {
+    let (Some(mut mapper), Some(mut frame_allocator)) =
+        (try_active_mapper(), try_frame_allocator())
+    else {
+        return false;
+    };
+    let Some(mut store) = STORE.try_lock() else {
+        return false;
+    };
+    let Some(store) = store.as_mut() else {
+        return false;
+    };
+
+    let page = Page::containing_address(addr);
+    match p1_entry(&mut mapper, page) {
+        Ok(entry) if entry.flags().contains(SWAPPED) => {
+            swap_in(entry, page, store, &mut frame_allocator)
+        }
+        _ => false,
+    }
+}
+
+/// Evicts a page, either dropping it if it is a clean demand-zero page or writing it to a slot.
+fn evict(
+    mapper: &mut OffsetPageTable,
+    page: Page,
+    store: &mut SwapStore,
+    frame_allocator: &mut super::buddy::BuddyFrameAllocator,
+) -> Result<(), SwapError> {
+    let entry = p1_entry(mapper, page)?;
+    let flags = entry.flags();
+    if !flags.contains(PageTableFlags::PRESENT) {
+        return Err(SwapError::NotMapped);
+    }
+    let frame = PhysFrame::containing_address(entry.addr());
+    if flags.contains(cow::COW) || frame_allocator.ref_count(frame) > 1 {
+        return Err(SwapError::Shared);
+    }
+
+    // Reclaim runs from the heap growth and fault paths, which may hold the area registry.
+    let demand_zero = vma::try_find(page.start_address())
+        .ok_or(SwapError::Busy)?
+        .is_some();
+    if !flags.contains(PageTableFlags::DIRTY) && demand_zero {
+        entry.set_unused();
+        tlb::flush(page.start_address());
+        unsafe { frame_allocator.release_frame(frame) };
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return Ok(());
+    }
+
+    let slot = store.alloc().ok_or(SwapError::StoreFull)?;
+    unsafe {
+        let src: *const u8 = phys_to_virt(frame.start_address()).as_ptr();
+        core::ptr::copy_nonoverlapping(src, store.slot_ptr(slot), SLOT_SIZE as usize);
+    }
+    let flags = flags - PageTableFlags::PRESENT - PageTableFlags::ACCESSED - PageTableFlags::DIRTY;
+    entry.set_addr(PhysAddr::new(slot as u64 * SLOT_SIZE), flags | SWAPPED);
+    tlb::flush(page.start_address());
+    unsafe { frame_allocator.release_frame(frame) };
+    SWAPPED_OUT.fetch_add(1, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Reads a swapped page back into a fresh frame and maps it again.
+///
+/// The slot is released, so the page is marked `DIRTY` to have it written out again on the
+/// next eviction.
+///
+/// # Returns
+/// `false` if no frame is available.
+fn swap_in(
+    entry: &mut PageTableEntry,
+    page: Page,
+    store: &mut SwapStore,
+    frame_allocator: &mut super::buddy::BuddyFrameAllocator,
+) -> bool {
+    let Some(frame): Option<PhysFrame> = frame_allocator.allocate_frame() else {
+        return false;
+    };
+    let slot = slot_of(entry);
+    unsafe {
+        let dest: *mut u8 = phys_to_virt(frame.start_address()).as_mut_ptr();
+        core::ptr::copy_nonoverlapping(store.slot_ptr(slot), dest, SLOT_SIZE as usize);
+    }
+    store.free(slot);
+
+    let flags = (entry.flags() - SWAPPED) | PageTableFlags::PRESENT | PageTableFlags::DIRTY;
+    entry.set_frame(frame, flags);
+    tlb::flush(page.start_address());
+    SWAPPED_IN.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+/// Returns the swap slot recorded in a `SWAPPED` entry.
+fn slot_of(entry: &PageTableEntry) -> usize {
+    (entry.addr().as_u64() / SLOT_SIZE) as usize
+}
+
+/// Returns `true` if `addr` lies in a reclaimable range.
+fn is_reclaimable(addr: VirtAddr) -> bool {
+    RANGES
+        .lock()
+        .iter()
+        .flatten()
+        .any(|&(start, end)| start <= addr && addr < end)
+}
+
+/// Returns the `index`th page of the reclaimable ranges, counted in table order.
+fn nth_page(ranges: &[Option<(VirtAddr, VirtAddr)>], mut index: usize) -> Page {
+    for &(start, end) in ranges.iter().flatten() {
+        let pages = ((end - start) / SLOT_SIZE) as usize;
+        if index < pages {
+            return Page::containing_address(start + index as u64 * SLOT_SIZE);
+        }
+        index -= pages;
+    }
+    unreachable!("page index beyond the reclaimable ranges")
+}
+
+/// Returns the table `entry` points to, or `None` if it is not present or maps a huge page.
+fn next_table(entry: &PageTableEntry) -> Option<*const PageTable> {
+    let flags = entry.flags();
+    (flags.contains(PageTableFlags::PRESENT) && !flags.contains(PageTableFlags::HUGE_PAGE))
+        .then(|| phys_to_virt(entry.addr()).as_ptr())
+}
+
+/// Returns the page with the given page table indices.
+fn page_at(p4: usize, p3: usize, p2: usize, p1: usize) -> Page {
+    Page::from_page_table_indices(
+        PageTableIndex::new(p4 as u16),
+        PageTableIndex::new(p3 as u16),
+        PageTableIndex::new(p2 as u16),
+        PageTableIndex::new(p1 as u16),
+    )
+}
+
+/// Returns the level 1 entry for `page`, which may be present, swapped or unused.
+///
+/// # Returns
+/// `SwapError::NotMapped` if a higher-level table is missing, or `SwapError::HugePage` if the
+/// page is part of a huge page.
+fn p1_entry<'a>(
+    mapper: &'a mut OffsetPageTable,
+    page: Page,
+) -> Result<&'a mut PageTableEntry, SwapError> {
+    let mut table: &'a mut PageTable = mapper.level_4_table();
+    for index in [page.p4_index(), page.p3_index(), page.p2_index()] {
+        let flags = table[index].flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return Err(SwapError::NotMapped);
+        }
+        if flags.contains(PageTableFlags::HUGE_PAGE) {
+            return Err(SwapError::HugePage);
+        }
+        table = unsafe { &mut *phys_to_virt(table[index].addr()).as_mut_ptr() };
+    }
+    Ok(&mut table[page.p1_index()])
+}
@@ -0,0 +1,219 @@
+use super::{frame_allocator, mapper, phys_to_virt, try_active_mapper, try_frame_allocator};
+use spin::Mutex;
+use x86_64::structures::paging::{
+    mapper::TranslateResult, FrameAllocator, FrameDeallocator, Mapper, Page, PageSize,
+    PageTableFlags, Size4KiB, Translate,
+};
+use x86_64::VirtAddr;
+
+/// The maximum number of areas that can be registered at the same time.
+///
+/// The registry is a fixed-size table so that the page fault handler never has to allocate.
+const MAX_AREAS: usize = 64;
+
+/// The registered areas, looked up by the page fault handler.
+static AREAS: Mutex<[Option<VirtualMemoryArea>; MAX_AREAS]> = Mutex::new([None; MAX_AREAS]);
+
+/// A range of virtual memory that is reserved up front and backed by frames on first access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualMemoryArea {
+    /// The page-aligned first address of the area.
+    pub start: VirtAddr,
+    /// The page-aligned address directly after the area.
+    pub end: VirtAddr,
+    /// The flags every page of the area is mapped with, in addition to `PRESENT`.
+    pub flags: PageTableFlags,
+    /// The name of the area, used for diagnostics.
+    pub name: &'static str,
+}
+
+impl VirtualMemoryArea {
+    /// Returns `true` if the area contains the given address.
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Returns the size of the area in bytes.
+    pub fn size(&self) -> u64 {
+        self.end - self.start
+    }
+}
+
+/// The reasons a virtual memory area could not be registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VmaError {
+    /// The start or size is not page-aligned, or the size is zero.
+    Misaligned,
+    /// The range overlaps an area that is already registered.
+    Overlap,
+    /// `MAX_AREAS` areas are already registered.
+    TableFull,
+}
+
+/// Registers a range of virtual memory to be populated on demand.
+///
+/// Nothing is mapped right away. The first access to each page raises a not-present page fault,
+/// which the page fault handler resolves by mapping a zeroed frame with the area's flags.
+///
+/// # Arguments
+/// * `start` - The page-aligned first address of the area.
+/// * `size` - The size of the area in bytes, a non-zero multiple of the page size.
+/// * `flags` - The flags to map the pages with. `PRESENT` is added automatically.
+/// * `name` - The name of the area, used for diagnostics.
+///
+/// # Returns
+/// An error if the range is misaligned, overlaps another area or the registry is full.
+pub fn reserve(
+    start: VirtAddr,
+    size: u64,
+    flags: PageTableFlags,
+    name: &'static str,
+) -> Result<VirtualMemoryArea, VmaError> {
+    if size == 0 || !start.is_aligned(Size4KiB::SIZE) || size & (Size4KiB::SIZE - 1) != 0 {
+        return Err(VmaError::Misaligned);
+    }
+    let area = VirtualMemoryArea {
+        start,
+        end: start + size,
+        flags: flags | PageTableFlags::PRESENT,
+        name,
+    };
+
+    let mut areas = AREAS.lock();
+    if areas
+        .iter()
+        .flatten()
+        .any(|other| area.start < other.end && other.start < area.end)
+    {
+        return Err(VmaError::Overlap);
+    }
+    let slot = areas
+        .iter_mut()
+        .find(|slot| slot.is_none())
+        .ok_or(VmaError::TableFull)?;
+    *slot = Some(area);
+
+    Ok(area)
+}
+
+/// Unregisters the area starting at `start`, unmapping its populated pages and freeing their
+/// frames.
+///
+/// # Arguments
+/// * `start` - The first address of the area, as passed to `reserve`.
+///
+/// # Returns
+/// The released area, or `None` if no area starts at `start`.
+///
+/// # Safety
+/// No references into the area may be used afterwards.
+pub unsafe fn release(start: VirtAddr) -> Option<VirtualMemoryArea> {
+    let area = {
+        let mut areas = AREAS.lock();
+        let slot = areas
+            .iter_mut()
+            .find(|slot| slot.is_some_and(|area| area.start == start))?;
+        slot.take()?
+    };
+
+    // Swapped-out pages are not present, so unmapping would skip them and leak their slots.
+    super::swap::discard(area.start, area.end);
+
+    let mut mapper = mapper();
+    let mut frame_allocator = frame_allocator();
+    let pages = Page::<Size4KiB>::range(
+        Page::containing_address(area.start),
+        Page::containing_address(area.end),
+    );
+    for page in pages {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            frame_allocator.deallocate_frame(frame);
+        }
+    }
+
+    Some(area)
+}
+
+/// Returns the registered area containing `addr`, if any.
+pub fn find(addr: VirtAddr) -> Option<VirtualMemoryArea> {
+    AREAS
+        .lock()
+        .iter()
+        .flatten()
+        .find(|area| area.contains(addr))
+        .copied()
+}
+
+/// Like [`find`], but only tries to lock the registry.
+///
+/// Used on the page fault and reclaim paths, which may run while the registry is held.
+///
+/// # Returns
+/// `None` if the registry is locked, otherwise the registered area containing `addr`, if any.
+pub fn try_find(addr: VirtAddr) -> Option<Option<VirtualMemoryArea>> {
+    let areas = AREAS.try_lock()?;
+    Some(
+        areas
+            .iter()
+            .flatten()
+            .find(|area| area.contains(addr))
+            .copied(),
+    )
+}
+
+/// Calls `f` with every registered area.
+pub fn for_each_area(mut f: impl FnMut(&VirtualMemoryArea)) {
+    let areas = *AREAS.lock();
+    for area in areas.iter().flatten() {
+        f(area);
+    }
+}
+
+/// Resolves a not-present page fault at `addr` by backing the page with a zeroed frame.
+///
+/// Called by the page fault handler. Every lock is only tried, so a fault raised while the
+/// registry, the mapper or the frame allocator is held is reported instead of deadlocking.
+///
+/// # Arguments
+/// * `addr` - The faulting address, as read from `Cr2`.
+///
+/// # Returns
+/// `true` if the page is now mapped and the faulting instruction can be retried, `false` if the
+/// fault has to be treated as an invalid access.
+pub fn handle_page_fault(addr: VirtAddr) -> bool {
+    let Some(Some(area)) = try_find(addr) else {
+        return false;
+    };
+
+    let (Some(mut mapper), Some(mut frame_allocator)) =
+        (try_active_mapper(), try_frame_allocator())
+    else {
+        return false;
+    };
+
+    let page = Page::<Size4KiB>::containing_address(addr);
+    // Another access may have raced us to it; the page is already usable then.
+    if let TranslateResult::Mapped { .. } = mapper.translate(page.start_address()) {
+        return true;
+    }
+
+    let Some(frame) = frame_allocator.allocate_frame() else {
+        return false;
+    };
+    unsafe {
+        let frame_ptr: *mut u8 = phys_to_virt(frame.start_address()).as_mut_ptr();
+        frame_ptr.write_bytes(0, Size4KiB::SIZE as usize);
+
+        match mapper.map_to(page, frame, area.flags, &mut *frame_allocator) {
+            Ok(flush) => {
+                flush.flush();
+                true
+            }
+            Err(_) => {
+                frame_allocator.deallocate_frame(frame);
+                false
+            }
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use super::phys_to_virt;
+use x86_64::structures::paging::{
+    PageSize, PageTable, PageTableFlags, PageTableIndex, Size1GiB, Size2MiB, Size4KiB,
+};
+use x86_64::{PhysAddr, VirtAddr};
+
+/// Flags the CPU updates on access, ignored when merging pages into ranges.
+const VOLATILE_FLAGS: PageTableFlags = PageTableFlags::ACCESSED.union(PageTableFlags::DIRTY);
+
+/// Flags that only take effect if they are set at every level of the walk.
+const ALL_LEVEL_FLAGS: PageTableFlags =
+    PageTableFlags::WRITABLE.union(PageTableFlags::USER_ACCESSIBLE);
+
+/// Flags that take effect if they are set at any level of the walk.
+const ANY_LEVEL_FLAGS: PageTableFlags = PageTableFlags::NO_EXECUTE;
+
+/// Combines the flags inherited from the upper levels with the flags of an entry.
+///
+/// The result holds the entry's own flags, except that `WRITABLE` and `USER_ACCESSIBLE` are
+/// only kept if every level allows them, and `NO_EXECUTE` is set if any level sets it. Start
+/// with `ALL_LEVEL_FLAGS` at the level 4 table.
+fn effective_flags(inherited: PageTableFlags, entry: PageTableFlags) -> PageTableFlags {
+    (entry - ALL_LEVEL_FLAGS - ANY_LEVEL_FLAGS)
+        | (inherited & entry & ALL_LEVEL_FLAGS)
+        | ((inherited | entry) & ANY_LEVEL_FLAGS)
+}
+
+/// A run of virtually contiguous pages that share their size and flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MappedRange {
+    /// The first address of the range.
+    pub start: VirtAddr,
+    /// The address directly after the range.
+    pub end: VirtAddr,
+    /// The size of each page in the range.
+    pub page_size: u64,
+    /// The flags of the leaf entries, without `ACCESSED` and `DIRTY`. `WRITABLE`,
+    /// `USER_ACCESSIBLE` and `NO_EXECUTE` are the effective permissions, which also depend on
+    /// the upper-level entries.
+    pub flags: PageTableFlags,
+}
+
+impl MappedRange {
+    /// Returns the number of pages in the range.
+    pub fn pages(&self) -> u64 {
+        (self.end - self.start) / self.page_size
+    }
+}
+
+/// A single mapped page found by the walker.
+#[derive(Debug, Clone, Copy)]
+struct Leaf {
+    start: VirtAddr,
+    page_size: u64,
+    flags: PageTableFlags,
+}
+
+/// An iterator over the mapped ranges of a page table hierarchy, in ascending address order.
+///
+/// The walk keeps one cursor per level instead of recursing, so it neither allocates nor needs
+/// much stack. Page tables are reached through the physical memory mapping.
+pub struct MappedRanges<'a> {
+    /// The table currently visited at each level, from the level 4 table down to level 1.
+    tables: [Option<&'a PageTable>; 4],
+    /// The index of the next entry to visit at each level.
+    indices: [usize; 4],
+    /// The effective flags of the entries leading to the table visited at each level.
+    inherited: [PageTableFlags; 4],
+    /// The level currently visited, where 0 is the level 4 table.
+    depth: usize,
+    /// A leaf that was read ahead but did not belong to the previous range.
+    pending: Option<Leaf>,
+}
+
+/// Returns an iterator over the mapped ranges reachable from `level_4_table`.
+///
+/// Use `memory::mapper().level_4_table()` for the active table; holding the mapper lock while
+/// iterating keeps the tables from changing underneath the walker.
+pub fn mapped_ranges(level_4_table: &PageTable) -> MappedRanges {
+    MappedRanges {
+        tables: [Some(level_4_table), None, None, None],
+        indices: [0; 4],
+        inherited: [ALL_LEVEL_FLAGS; 4],
+        depth: 0,
+        pending: None,
+    }
+}
+
+impl MappedRanges<'_> {
+    /// Advances to the next present leaf entry.
+    fn next_leaf(&mut self) -> Option<Leaf> {
+        loop {
+            let index = self.indices[self.depth];
+            if index >= 512 {
+                if self.depth == 0 {
+                    return None;
+                }
+                self.depth -= 1;
+                self.indices[self.depth] += 1;
+                continue;
+            }
+
+            let table = self.tables[self.depth]?;
+            let entry = &table[index];
+            let flags = entry.flags();
+            if !flags.contains(PageTableFlags::PRESENT) {
+                self.indices[self.depth] += 1;
+                continue;
+            }
+
+            let flags = effective_flags(self.inherited[self.depth], flags);
+            let is_leaf =
+                self.depth == 3 || (self.depth > 0 && flags.contains(PageTableFlags::HUGE_PAGE));
+            if is_leaf {
+                let leaf = Leaf {
+                    start: self.current_address(),
+                    page_size: page_size(self.depth),
+                    flags: flags - VOLATILE_FLAGS,
+                };
+                self.indices[self.depth] += 1;
+                return Some(leaf);
+            }
+
+            let child: *const PageTable = phys_to_virt(entry.addr()).as_ptr();
+            self.depth += 1;
+            self.tables[self.depth] = Some(unsafe { &*child });
+            self.indices[self.depth] = 0;
+            self.inherited[self.depth] = flags;
+        }
+    }
+
+    /// Returns the virtual address described by the cursors down to the current level.
+    fn current_address(&self) -> VirtAddr {
+        let addr = (0..=self.depth).fold(0u64, |addr, level| {
+            addr | (self.indices[level] as u64) << (39 - 9 * level)
+        });
+        VirtAddr::new_truncate(addr)
+    }
+}
+
+impl Iterator for MappedRanges<'_> {
+    type Item = MappedRange;
+
+    fn next(&mut self) -> Option<MappedRange> {
+        let first = self.pending.take().or_else(|| self.next_leaf())?;
+        let mut range = MappedRange {
+            start: first.start,
+            end: first.start + first.page_size,
+            page_size: first.page_size,
+            flags: first.flags,
+        };
+
+        while let Some(leaf) = self.next_leaf() {
+            if leaf.start != range.end
+                || leaf.page_size != range.page_size
+                || leaf.flags != range.flags
+            {
+                self.pending = Some(leaf);
+                break;
+            }
+            range.end = leaf.start + leaf.page_size;
+        }
+
+        Some(range)
+    }
+}
+
+/// Calls `f` with every mapped range of the active page table.
+///
+/// The kernel's mapper stays locked during the walk, so `f` must not map or unmap memory, nor
+/// allocate from the heap. Use `copy_mapped_ranges` to do either between batches of ranges.
+pub fn for_each_mapped_range(mut f: impl FnMut(MappedRange)) {
+    let mut mapper = super::mapper();
+    for range in mapped_ranges(mapper.level_4_table()) {
+        f(range);
+    }
+}
+
+/// Copies the mapped ranges of the active page table that start at or after `from` into
+/// `buffer`.
+///
+/// The kernel's mapper is only locked while copying. To continue the walk, pass the end of the
+/// last copied range as `from`.
+///
+/// # Returns
+/// The number of ranges copied. The walk is complete once this is less than `buffer.len()`.
+pub fn copy_mapped_ranges(from: VirtAddr, buffer: &mut [MappedRange]) -> usize {
+    let mut mapper = super::mapper();
+    let ranges = mapped_ranges(mapper.level_4_table()).filter(|range| range.start >= from);
+    let mut copied = 0;
+    for (slot, range) in buffer.iter_mut().zip(ranges) {
+        *slot = range;
+        copied += 1;
+    }
+    copied
+}
+
+/// The entry used at one level while translating an address.
+#[derive(Debug, Clone, Copy)]
+pub struct LevelEntry {
+    /// The level of the table, from 4 down to 1.
+    pub level: u8,
+    /// The index of the entry within its table.
+    pub index: PageTableIndex,
+    /// The physical address stored in the entry.
+    pub addr: PhysAddr,
+    /// The flags of the entry.
+    pub flags: PageTableFlags,
+}
+
+/// The result of walking the page tables for a single address.
+#[derive(Debug, Clone)]
+pub struct Translation {
+    /// The translated virtual address.
+    pub addr: VirtAddr,
+    /// The entries visited from level 4 downwards. Levels that were not reached are `None`.
+    pub entries: [Option<LevelEntry>; 4],
+    /// The physical address `addr` maps to, or `None` if it is not mapped.
+    pub phys: Option<PhysAddr>,
+    /// The size of the page containing `addr`, if it is mapped.
+    pub page_size: Option<u64>,
+    /// The effective flags of the mapping, combined across all levels like
+    /// `MappedRange::flags`, if `addr` is mapped.
+    pub flags: Option<PageTableFlags>,
+}
+
+/// Walks the active page table for `addr`, recording the entry used at each level.
+pub fn translate(addr: VirtAddr) -> Translation {
+    let mut mapper = super::mapper();
+    let mut translation = Translation {
+        addr,
+        entries: [None; 4],
+        phys: None,
+        page_size: None,
+        flags: None,
+    };
+
+    let indices = [
+        addr.p4_index(),
+        addr.p3_index(),
+        addr.p2_index(),
+        addr.p1_index(),
+    ];
+    let mut table: &PageTable = mapper.level_4_table();
+    let mut inherited = ALL_LEVEL_FLAGS;
+    for (depth, index) in indices.into_iter().enumerate() {
+        let entry = &table[index];
+        let flags = entry.flags();
+        translation.entries[depth] = Some(LevelEntry {
+            level: 4 - depth as u8,
+            index,
+            addr: entry.addr(),
+            flags,
+        });
+        if !flags.contains(PageTableFlags::PRESENT) {
+            break;
+        }
+
+        inherited = effective_flags(inherited, flags);
+        if depth == 3 || (depth > 0 && flags.contains(PageTableFlags::HUGE_PAGE)) {
+            let size = page_size(depth);
+            translation.phys = Some(entry.addr() + (addr.as_u64() & (size - 1)));
+            translation.page_size = Some(size);
+            translation.flags = Some(inherited);
+            break;
+        }
+
+        let next: *const PageTable = phys_to_virt(entry.addr()).as_ptr();
+        table = unsafe { &*next };
+    }
+
+    translation
+}
+
+/// Returns the size of the pages mapped by leaf entries at the given depth.
+fn page_size(depth: usize) -> u64 {
+    match depth {
+        1 => Size1GiB::SIZE,
+        2 => Size2MiB::SIZE,
+        _ => Size4KiB::SIZE,
+    }
+}
@@ -1,9 +1,19 @@
 use super::{Task, TaskId};
-use alloc::task::Wake;
+use crate::allocator::slab::{self, SlabCache};
+use crate::allocator::Locked;
 use alloc::{collections::BTreeMap, sync::Arc};
-use core::task::Waker;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
 use core::task::{Context, Poll};
+use core::task::{RawWaker, RawWakerVTable, Waker};
 use crossbeam_queue::ArrayQueue;
+use x86_64::instructions::interrupts;
+
+/// The slab cache the `TaskWaker`s of every executor are allocated from.
+///
+/// Wakers are dropped by interrupt handlers, so task code only locks this with interrupts
+/// disabled.
+static WAKER_CACHE: Locked<SlabCache> = Locked::new(SlabCache::for_type::<TaskWaker>("task waker"));
 
 /// A struct representing the Executor, which manages and runs tasks in a cooperative multitasking system.
 pub struct Executor {
@@ -21,6 +31,11 @@ impl Executor {
     /// # Returns
     /// A new `Executor` instance.
     pub fn new() -> Self {
+        static WAKER_CACHE_REGISTERED: AtomicBool = AtomicBool::new(false);
+        if !WAKER_CACHE_REGISTERED.swap(true, Ordering::Relaxed) {
+            slab::register(&WAKER_CACHE);
+        }
+
         Executor {
             tasks: BTreeMap::new(),
             task_queue: Arc::new(ArrayQueue::new(100)),
@@ -106,13 +121,26 @@ impl Default for Executor {
 }
 
 /// A struct representing a `Waker` for a specific task, allowing it to be awakened from a blocking state.
+///
+/// Task wakers live in `WAKER_CACHE` and are reference counted by hand, since `Arc` can only
+/// allocate from the heap.
 struct TaskWaker {
     /// The unique identifier of the task that this `Waker` is responsible for.
     task_id: TaskId,
     /// The queue used to schedule tasks for execution.
     task_queue: Arc<ArrayQueue<TaskId>>,
+    /// The number of `Waker`s referring to this object.
+    refs: AtomicUsize,
 }
 
+/// The functions behind every `Waker` created by `TaskWaker::new`.
+static TASK_WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(
+    TaskWaker::clone_raw,
+    TaskWaker::wake_raw,
+    TaskWaker::wake_by_ref_raw,
+    TaskWaker::drop_raw,
+);
+
 #[allow(clippy::new_ret_no_self)]
 impl TaskWaker {
     /// Creates a new `Waker` for the specified task.
@@ -123,27 +151,62 @@ impl TaskWaker {
     ///
     /// # Returns
     /// A `Waker` instance for the specified task.
+    ///
+    /// # Panics
+    /// Panics if `WAKER_CACHE` needs a new slab and no frame can be allocated.
     fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
-        Waker::from(Arc::new(TaskWaker {
-            task_id,
-            task_queue,
-        }))
+        let object = interrupts::without_interrupts(|| WAKER_CACHE.lock().alloc())
+            .expect("no memory for a task waker")
+            .cast::<TaskWaker>();
+        unsafe {
+            object.as_ptr().write(TaskWaker {
+                task_id,
+                task_queue,
+                refs: AtomicUsize::new(1),
+            });
+            Waker::from_raw(RawWaker::new(
+                object.as_ptr() as *const (),
+                &TASK_WAKER_VTABLE,
+            ))
+        }
     }
 
     /// Wakes the task associated with this `TaskWaker` by pushing its ID onto the task queue.
     fn wake_task(&self) {
         self.task_queue.push(self.task_id).expect("task_queue full");
     }
-}
 
-impl Wake for TaskWaker {
-    /// Wakes the task by calling the `wake_task` method on the `TaskWaker`.
-    fn wake(self: Arc<Self>) {
-        self.wake_task();
+    /// Creates another `Waker` referring to the same `TaskWaker`.
+    unsafe fn clone_raw(data: *const ()) -> RawWaker {
+        (*(data as *const TaskWaker))
+            .refs
+            .fetch_add(1, Ordering::Relaxed);
+        RawWaker::new(data, &TASK_WAKER_VTABLE)
+    }
+
+    /// Wakes the task and drops the `Waker`.
+    unsafe fn wake_raw(data: *const ()) {
+        Self::wake_by_ref_raw(data);
+        Self::drop_raw(data);
     }
 
-    /// Wakes the task by calling the `wake_task` method on the `TaskWaker`, keeping the reference intact.
-    fn wake_by_ref(self: &Arc<Self>) {
-        self.wake_task();
+    /// Wakes the task, keeping the `Waker` intact.
+    unsafe fn wake_by_ref_raw(data: *const ()) {
+        (*(data as *const TaskWaker)).wake_task();
+    }
+
+    /// Drops a `Waker`, returning the `TaskWaker` to `WAKER_CACHE` once the last one is gone.
+    unsafe fn drop_raw(data: *const ()) {
+        let waker = data as *mut TaskWaker;
+        if (*waker).refs.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        atomic::fence(Ordering::Acquire);
+        ptr::drop_in_place(waker);
+        interrupts::without_interrupts(|| {
+            WAKER_CACHE
+                .lock()
+                .free(NonNull::new_unchecked(waker).cast())
+        });
     }
 }
@@ -1,3 +1,4 @@
+use crate::interrupts::{irq, InterruptIndex, PIC_1_OFFSET};
 use crate::{print, println};
 use conquer_once::spin::OnceCell;
 use core::{
@@ -16,6 +17,30 @@ static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 /// A static waker used to wake up the task when new scancodes are available.
 static WAKER: AtomicWaker = AtomicWaker::new();
 
+/// The IRQ line of the PS/2 keyboard.
+const KEYBOARD_IRQ: u8 = InterruptIndex::Keyboard as u8 - PIC_1_OFFSET;
+
+/// Registers the keyboard interrupt handler.
+///
+/// # Panics
+/// Panics if the keyboard IRQ line has no free handler slot.
+pub(crate) fn init() {
+    irq::register_irq(KEYBOARD_IRQ, keyboard_interrupt)
+        .expect("failed to register the keyboard interrupt handler");
+}
+
+/// Handler for the keyboard interrupt, triggered when a key is pressed.
+/// It reads the scancode from the keyboard's data port and adds it to the scancode queue.
+fn keyboard_interrupt(_irq: u8) -> bool {
+    use x86_64::instructions::port::Port;
+
+    // Read the scancode from the keyboard's data port (0x60)
+    let mut port = Port::new(0x60);
+    let scancode: u8 = unsafe { port.read() };
+    add_scancode(scancode);
+    true
+}
+
 /// Adds a scancode to the scancode queue and wakes up any waiting tasks.
 ///
 /// # Arguments
@@ -5,6 +5,7 @@ use core::{future::Future, pin::Pin, task::Context, task::Poll};
 pub mod executor;
 pub mod keyboard;
 pub mod simple_executor;
+pub mod timer;
 
 /// A struct representing a unique task identifier.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
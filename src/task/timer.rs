@@ -0,0 +1,326 @@
+use crate::time::{Duration, Instant};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+use futures_util::stream::Stream;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// The maximum number of timers that can be pending at the same time.
+///
+/// The deadlines live in a fixed-size table so that the timer interrupt never has to allocate.
+/// Futures that find the table full fall back to polling on every executor pass.
+const MAX_TIMERS: usize = 128;
+
+/// The pending deadlines, woken by the timer interrupt.
+///
+/// Task code only locks this with interrupts disabled, so the interrupt handler never finds it
+/// locked on the same core.
+static TIMERS: Mutex<TimerHeap> = Mutex::new(TimerHeap::new());
+
+/// A registered deadline and the waker of the task waiting for it.
+struct Timer {
+    /// The instant at which the waker is woken.
+    deadline: Instant,
+    /// The identifier of the future that registered this timer.
+    id: u64,
+    /// The waker of the waiting task.
+    waker: Waker,
+}
+
+/// A binary min-heap of timers, ordered by deadline.
+struct TimerHeap {
+    timers: [Option<Timer>; MAX_TIMERS],
+    len: usize,
+}
+
+impl TimerHeap {
+    /// Creates an empty heap.
+    const fn new() -> Self {
+        const NONE: Option<Timer> = None;
+        TimerHeap {
+            timers: [NONE; MAX_TIMERS],
+            len: 0,
+        }
+    }
+
+    /// Returns the deadline of the timer at `index`.
+    fn deadline(&self, index: usize) -> Instant {
+        self.timers[index].as_ref().unwrap().deadline
+    }
+
+    /// Registers a timer, or replaces the waker of the timer with the same identifier.
+    ///
+    /// # Returns
+    /// `false` if the heap is full.
+    fn insert(&mut self, deadline: Instant, id: u64, waker: &Waker) -> bool {
+        if let Some(timer) = self.timers[..self.len]
+            .iter_mut()
+            .flatten()
+            .find(|timer| timer.id == id)
+        {
+            if !timer.waker.will_wake(waker) {
+                timer.waker = waker.clone();
+            }
+            return true;
+        }
+        if self.len == MAX_TIMERS {
+            return false;
+        }
+
+        self.timers[self.len] = Some(Timer {
+            deadline,
+            id,
+            waker: waker.clone(),
+        });
+        self.len += 1;
+        self.sift_up(self.len - 1);
+        true
+    }
+
+    /// Unregisters the timer with the given identifier, if any.
+    fn remove(&mut self, id: u64) {
+        let Some(index) = self.timers[..self.len]
+            .iter()
+            .position(|timer| timer.as_ref().is_some_and(|timer| timer.id == id))
+        else {
+            return;
+        };
+        self.take(index);
+    }
+
+    /// Removes and returns the earliest timer if its deadline has passed.
+    fn pop_expired(&mut self, now: Instant) -> Option<Timer> {
+        if self.len > 0 && self.deadline(0) <= now {
+            Some(self.take(0))
+        } else {
+            None
+        }
+    }
+
+    /// Removes the timer at `index`, moving the last timer into its place.
+    fn take(&mut self, index: usize) -> Timer {
+        self.len -= 1;
+        self.timers.swap(index, self.len);
+        let timer = self.timers[self.len].take().unwrap();
+        if index < self.len {
+            self.sift_down(index);
+            self.sift_up(index);
+        }
+        timer
+    }
+
+    /// Moves the timer at `index` up until its parent is not later.
+    fn sift_up(&mut self, mut index: usize) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if self.deadline(parent) <= self.deadline(index) {
+                break;
+            }
+            self.timers.swap(parent, index);
+            index = parent;
+        }
+    }
+
+    /// Moves the timer at `index` down until no child is earlier.
+    fn sift_down(&mut self, mut index: usize) {
+        loop {
+            let mut earliest = index;
+            for child in [2 * index + 1, 2 * index + 2] {
+                if child < self.len && self.deadline(child) < self.deadline(earliest) {
+                    earliest = child;
+                }
+            }
+            if earliest == index {
+                break;
+            }
+            self.timers.swap(index, earliest);
+            index = earliest;
+        }
+    }
+}
+
+/// Wakes every task whose deadline has passed.
+///
+/// Called by the timer interrupt handler after advancing the clock.
+pub(crate) fn wake_expired() {
+    let Some(mut timers) = TIMERS.try_lock() else {
+        return;
+    };
+    let now = Instant::now();
+    while let Some(timer) = timers.pop_expired(now) {
+        timer.waker.wake();
+    }
+}
+
+/// Returns the number of timers currently registered.
+pub fn pending_timers() -> usize {
+    interrupts::without_interrupts(|| TIMERS.lock().len)
+}
+
+/// Returns a new identifier for a timer future.
+fn next_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Registers `waker` to be woken at `deadline`, or wakes it right away if no timer is free.
+fn register(deadline: Instant, id: u64, waker: &Waker) {
+    let registered = interrupts::without_interrupts(|| TIMERS.lock().insert(deadline, id, waker));
+    if !registered {
+        waker.wake_by_ref();
+    }
+}
+
+/// Unregisters the timer with the given identifier.
+fn cancel(id: u64) {
+    interrupts::without_interrupts(|| TIMERS.lock().remove(id));
+}
+
+/// A future that completes at a deadline, created by `sleep` and `sleep_until`.
+#[must_use = "futures do nothing unless polled"]
+pub struct Sleep {
+    /// The instant at which the future completes.
+    deadline: Instant,
+    /// The identifier of this future's timer.
+    id: u64,
+}
+
+impl Sleep {
+    /// Returns the instant at which this future completes.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Moves the deadline, as if the future had been created by `sleep_until(deadline)`.
+    pub fn reset(&mut self, deadline: Instant) {
+        cancel(self.id);
+        self.deadline = deadline;
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    /// Completes once the deadline has passed, registering the task's waker with the timer
+    /// interrupt otherwise.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        register(self.deadline, self.id, cx.waker());
+        // The deadline may have passed before the timer was registered.
+        if Instant::now() >= self.deadline {
+            cancel(self.id);
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        cancel(self.id);
+    }
+}
+
+/// Returns a future that completes after `duration`.
+///
+/// The timer has the resolution of one tick, so the future may complete up to a tick late.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Returns a future that completes at `deadline`.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        id: next_id(),
+    }
+}
+
+/// The error returned by `Timeout` when the inner future did not complete in time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// A future that fails with `Elapsed` if the inner future does not complete in time, created
+/// by `timeout`.
+#[must_use = "futures do nothing unless polled"]
+pub struct Timeout<F> {
+    future: F,
+    sleep: Sleep,
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    /// Polls the inner future first, so a future that is ready at the deadline still succeeds.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // The inner future is never moved out of the pinned `Timeout`.
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Runs `future` for at most `duration`.
+///
+/// # Returns
+/// A future resolving to the output of `future`, or to `Err(Elapsed)` if the time ran out
+/// first, in which case `future` is dropped.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        sleep: sleep(duration),
+    }
+}
+
+/// A stream yielding at a fixed period, created by `interval`.
+#[must_use = "streams do nothing unless polled"]
+pub struct Interval {
+    sleep: Sleep,
+    period: Duration,
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    /// Yields the deadline of each tick once it has passed.
+    ///
+    /// Ticks are scheduled a period apart from each other rather than from the time they were
+    /// consumed, so the interval does not drift. Ticks that were missed entirely, for example
+    /// because the task was busy, are skipped instead of being delivered in a burst.
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Instant>> {
+        if Pin::new(&mut self.sleep).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let deadline = self.sleep.deadline();
+        let now = Instant::now();
+        let mut next = deadline + self.period;
+        if next <= now {
+            next = now + self.period;
+        }
+        self.sleep.reset(next);
+        Poll::Ready(Some(deadline))
+    }
+}
+
+/// Returns a stream that yields every `period`, starting right away.
+///
+/// # Panics
+/// Panics if `period` is zero.
+pub fn interval(period: Duration) -> Interval {
+    assert!(!period.is_zero(), "interval period must be non-zero");
+    Interval {
+        sleep: sleep_until(Instant::now()),
+        period,
+    }
+}
@@ -0,0 +1,179 @@
+use crate::boot_splash::BootScreen;
+use crate::log::LogType;
+use core::ops::{Add, Sub};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+pub use core::time::Duration;
+
+pub mod pit;
+
+/// The timer interrupt frequency programmed by `init`.
+pub const DEFAULT_FREQUENCY_HZ: u32 = 1000;
+
+/// The number of nanoseconds in a second.
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// The number of timer interrupts since boot.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// The time since boot in nanoseconds, advanced by the length of a tick on every interrupt.
+///
+/// Accumulating the time instead of deriving it from `TICKS` keeps it monotonic and accurate
+/// across frequency changes.
+static NANOS: AtomicU64 = AtomicU64::new(0);
+
+/// The length of a tick in nanoseconds, initially the PIT's power-on rate.
+static TICK_NANOS: AtomicU64 = AtomicU64::new(tick_nanos(65536));
+
+/// The programmed timer interrupt frequency in Hz, rounded.
+static FREQUENCY_HZ: AtomicU32 = AtomicU32::new(frequency_hz(65536));
+
+/// Programs the timer to `DEFAULT_FREQUENCY_HZ`.
+///
+/// Called from `crate::init` before interrupts are enabled.
+pub fn init() {
+    BootScreen::log(LogType::Info, "Programming the interval timer");
+    set_frequency(DEFAULT_FREQUENCY_HZ);
+    BootScreen::log(LogType::Success, "Interval timer programmed");
+}
+
+/// Changes the rate of the timer interrupt.
+///
+/// The timer interrupt comes from the PIT, or from the local APIC timer once `apic::init` has
+/// switched to the APIC. Both can only divide their input clock by an integer, so the actual
+/// rate may differ slightly from the requested one; `uptime` accounts for the exact tick length.
+///
+/// # Arguments
+/// * `hz` - The requested frequency. The PIT supports 19 Hz to about 1.19 MHz; the local APIC
+///   timer supports anything up to its own rate, `apic::timer_frequency`.
+///
+/// # Returns
+/// The programmed frequency, rounded to whole Hz.
+pub fn set_frequency(hz: u32) -> u32 {
+    if crate::apic::is_enabled() {
+        return crate::apic::set_timer_frequency(hz);
+    }
+    let divisor = pit::divisor_for(hz);
+    TICK_NANOS.store(tick_nanos(divisor), Ordering::Relaxed);
+    FREQUENCY_HZ.store(frequency_hz(divisor), Ordering::Relaxed);
+    // A divisor of 65536 is written as 0.
+    pit::set_divisor(divisor as u16);
+    frequency_hz(divisor)
+}
+
+/// Returns the programmed timer interrupt frequency in Hz.
+pub fn frequency() -> u32 {
+    FREQUENCY_HZ.load(Ordering::Relaxed)
+}
+
+/// Returns the number of timer interrupts since boot.
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Returns the time since boot, with the resolution of one tick.
+pub fn uptime() -> Duration {
+    Duration::from_nanos(NANOS.load(Ordering::Relaxed))
+}
+
+/// Records the rate of a new timer interrupt source.
+///
+/// # Arguments
+/// * `hz` - The interrupt frequency, rounded to whole Hz.
+/// * `tick_nanos` - The exact length of a tick in nanoseconds.
+pub(crate) fn set_tick_rate(hz: u64, tick_nanos: u64) {
+    TICK_NANOS.store(tick_nanos, Ordering::Relaxed);
+    FREQUENCY_HZ.store(hz as u32, Ordering::Relaxed);
+}
+
+/// Advances the tick counter and the uptime by one tick.
+///
+/// Called by the timer interrupt handler.
+pub(crate) fn tick() {
+    NANOS.fetch_add(TICK_NANOS.load(Ordering::Relaxed), Ordering::Relaxed);
+    TICKS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Returns the length of a tick in nanoseconds for the given PIT divisor.
+const fn tick_nanos(divisor: u32) -> u64 {
+    divisor as u64 * NANOS_PER_SEC / pit::BASE_FREQUENCY as u64
+}
+
+/// Returns the interrupt frequency in whole Hz for the given PIT divisor.
+const fn frequency_hz(divisor: u32) -> u32 {
+    (pit::BASE_FREQUENCY + divisor / 2) / divisor
+}
+
+/// A point in time since boot, measured by the timer interrupt.
+///
+/// Like `std::time::Instant`, instants are only meaningful relative to each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(Duration);
+
+impl Instant {
+    /// Returns the current instant.
+    pub fn now() -> Self {
+        Instant(uptime())
+    }
+
+    /// Returns the time elapsed since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().saturating_duration_since(*self)
+    }
+
+    /// Returns the time from `earlier` to this instant, or zero if `earlier` is later.
+    pub fn saturating_duration_since(&self, earlier: Instant) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+
+    /// Returns the time from `earlier` to this instant, or `None` if `earlier` is later.
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0)
+    }
+
+    /// Returns the instant `duration` after this one, or `None` on overflow.
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_add(duration).map(Instant)
+    }
+
+    /// Returns the instant `duration` before this one, or `None` if that is before boot.
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        self.0.checked_sub(duration).map(Instant)
+    }
+
+    /// Returns the time between boot and this instant.
+    pub fn since_boot(&self) -> Duration {
+        self.0
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    /// # Panics
+    /// Panics on overflow.
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to instant")
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    /// # Panics
+    /// Panics if the result would lie before boot.
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    /// Returns the time between the instants, or zero if `earlier` is later.
+    fn sub(self, earlier: Instant) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+}
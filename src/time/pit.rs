@@ -0,0 +1,39 @@
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+/// The frequency of the PIT's input clock in Hz.
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+/// The data port of channel 0, which drives IRQ 0.
+const CHANNEL_0_PORT: u16 = 0x40;
+
+/// The mode/command port.
+const COMMAND_PORT: u16 = 0x43;
+
+/// Command selecting channel 0, low/high byte access and mode 2 (rate generator).
+const CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+
+/// Programs channel 0 to raise IRQ 0 every `divisor` input clock cycles.
+///
+/// # Arguments
+/// * `divisor` - The reload value. 0 stands for 65536, the slowest rate of about 18.2 Hz.
+pub fn set_divisor(divisor: u16) {
+    let mut command: Port<u8> = Port::new(COMMAND_PORT);
+    let mut data: Port<u8> = Port::new(CHANNEL_0_PORT);
+
+    // The two data writes must not be interleaved with another reprogramming.
+    interrupts::without_interrupts(|| unsafe {
+        command.write(CHANNEL_0_RATE_GENERATOR);
+        data.write(divisor as u8);
+        data.write((divisor >> 8) as u8);
+    });
+}
+
+/// Returns the divisor that comes closest to the requested frequency.
+///
+/// # Arguments
+/// * `hz` - The requested interrupt frequency, clamped to what the PIT can produce.
+pub fn divisor_for(hz: u32) -> u32 {
+    let hz = hz.max(1);
+    ((BASE_FREQUENCY + hz / 2) / hz).clamp(1, 65536)
+}
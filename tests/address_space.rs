@@ -0,0 +1,164 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::memory::address_space::{self, USER_SPACE_END, USER_SPACE_START};
+use marcel_os::memory::{self, protect, region, vma, AddressSpace};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+fn user_page(index: u64) -> Page {
+    Page::containing_address(VirtAddr::new(USER_SPACE_START + index * 4096))
+}
+
+/// Writes `value` to `page` with `space` active, then switches back to the kernel's table.
+fn write_in(space: &AddressSpace, page: Page, value: u64) {
+    let ptr: *mut u64 = page.start_address().as_mut_ptr();
+    unsafe {
+        space.activate();
+        protect::with_user_access(|| ptr.write_volatile(value));
+        address_space::activate_kernel();
+    }
+}
+
+/// Reads from `page` with `space` active, then switches back to the kernel's table.
+fn read_in(space: &AddressSpace, page: Page) -> u64 {
+    let ptr: *const u64 = page.start_address().as_ptr();
+    unsafe {
+        space.activate();
+        let value = protect::with_user_access(|| ptr.read_volatile());
+        address_space::activate_kernel();
+        value
+    }
+}
+
+#[test_case]
+fn user_pages_are_private() {
+    let mut first = AddressSpace::new().unwrap();
+    let mut second = AddressSpace::new().unwrap();
+    let page = user_page(0);
+    first.map_user(page, PageTableFlags::WRITABLE).unwrap();
+    second.map_user(page, PageTableFlags::WRITABLE).unwrap();
+    assert_ne!(
+        first.translate(page.start_address()),
+        second.translate(page.start_address())
+    );
+
+    write_in(&first, page, 1);
+    write_in(&second, page, 2);
+    assert_eq!(read_in(&first, page), 1);
+    assert_eq!(read_in(&second, page), 2);
+}
+
+#[test_case]
+fn kernel_half_is_shared() {
+    let mut space = AddressSpace::new().unwrap();
+    let kernel_addr = VirtAddr::from_ptr(user_page as *const ());
+    let expected = memory::walk::translate(kernel_addr).phys;
+    assert_eq!(space.translate(kernel_addr), expected);
+    assert_eq!(space.translate(user_page(0).start_address()), None);
+}
+
+#[test_case]
+fn activation_switches_cr3() {
+    let space = AddressSpace::new().unwrap();
+    let kernel_frame = Cr3::read().0;
+    unsafe { space.activate() };
+    assert!(space.is_active());
+    assert_eq!(Cr3::read().0, space.level_4_frame());
+    unsafe { address_space::activate_kernel() };
+    assert!(!space.is_active());
+    assert_eq!(Cr3::read().0, kernel_frame);
+}
+
+#[test_case]
+fn teardown_frees_all_frames() {
+    let used = memory::frame_allocator().used_frames();
+    {
+        let mut space = AddressSpace::new().unwrap();
+        for index in 0..4 {
+            space
+                .map_user(user_page(index), PageTableFlags::WRITABLE)
+                .unwrap();
+        }
+        // A page near the end of the user part needs page tables of its own.
+        let last = Page::containing_address(VirtAddr::new(USER_SPACE_END - 4096));
+        space.map_user(last, PageTableFlags::empty()).unwrap();
+        assert!(memory::frame_allocator().used_frames() > used + 5);
+    }
+    assert_eq!(memory::frame_allocator().used_frames(), used);
+}
+
+#[test_case]
+fn unmapped_user_pages_are_freed() {
+    let mut space = AddressSpace::new().unwrap();
+    let page = user_page(7);
+    space.map_user(page, PageTableFlags::WRITABLE).unwrap();
+    let used = memory::frame_allocator().used_frames();
+    assert!(unsafe { space.unmap_user(page) });
+    assert!(!unsafe { space.unmap_user(page) });
+    assert_eq!(memory::frame_allocator().used_frames(), used - 1);
+    assert_eq!(space.translate(page.start_address()), None);
+}
+
+#[test_case]
+fn demand_paging_works_in_other_spaces() {
+    let space = AddressSpace::new().unwrap();
+    let region = region::alloc(4096).unwrap();
+    vma::reserve(region.start(), 4096, PageTableFlags::WRITABLE, "test").unwrap();
+    let ptr: *mut u64 = region.start().as_mut_ptr();
+    unsafe {
+        space.activate();
+        ptr.write_volatile(42);
+        address_space::activate_kernel();
+        assert_eq!(ptr.read_volatile(), 42);
+        vma::release(region.start()).unwrap();
+    }
+    region::free(region);
+}
+
+#[test_case]
+fn later_kernel_entries_are_shared() {
+    let space = AddressSpace::new().unwrap();
+    // Each region starts a level 4 entry of its own, so mapping it may create the entry.
+    let before = region::alloc_aligned(4096, 1 << 39).unwrap();
+    let during = region::alloc_aligned(4096, 1 << 39).unwrap();
+    region::map_region(&before, PageTableFlags::WRITABLE).unwrap();
+    let before_ptr: *mut u64 = before.start().as_mut_ptr();
+    let during_ptr: *mut u64 = during.start().as_mut_ptr();
+    unsafe {
+        before_ptr.write_volatile(1);
+        space.activate();
+        let before_value = before_ptr.read_volatile();
+        // Mapped through the kernel's page table while the address space is active.
+        region::map_region(&during, PageTableFlags::WRITABLE).unwrap();
+        during_ptr.write_volatile(2);
+        address_space::activate_kernel();
+        assert_eq!(before_value, 1);
+        assert_eq!(during_ptr.read_volatile(), 2);
+
+        region::unmap_region(&before);
+        region::unmap_region(&during);
+    }
+    region::free(before);
+    region::free(during);
+}
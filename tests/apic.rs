@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::{acpi, apic, time};
+use x86_64::instructions::hlt;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::test_init(boot_info);
+    assert!(apic::init(), "APIC initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn madt_describes_an_io_apic() {
+    let madt = acpi::find_madt().expect("no MADT");
+    assert!(madt.io_apics.iter().flatten().any(|io| io.gsi_base == 0));
+    assert!(madt.processors.iter().flatten().count() >= 1);
+}
+
+#[test_case]
+fn apic_is_enabled() {
+    assert!(apic::is_enabled());
+    assert!(apic::local_apic_id().is_some());
+    assert!(apic::timer_frequency() > 0);
+}
+
+#[test_case]
+fn apic_timer_drives_ticks() {
+    let ticks = time::ticks();
+    for _ in 0..3 {
+        hlt();
+    }
+    assert!(time::ticks() > ticks);
+}
+
+#[test_case]
+fn apic_timer_keeps_frequency() {
+    let frequency = time::frequency();
+    assert!((990..=1010).contains(&frequency));
+
+    let start = time::uptime();
+    let ticks = time::ticks();
+    while time::ticks() < ticks + 20 {
+        hlt();
+    }
+    let elapsed = (time::uptime() - start).as_millis();
+    assert!((19..=22).contains(&elapsed));
+}
+
+#[test_case]
+fn apic_timer_frequency_can_change() {
+    let actual = time::set_frequency(500);
+    assert!((495..=505).contains(&actual));
+    let ticks = time::ticks();
+    for _ in 0..3 {
+        hlt();
+    }
+    assert!(time::ticks() > ticks);
+    time::set_frequency(time::DEFAULT_FREQUENCY_HZ);
+}
+
+#[test_case]
+fn only_keyboard_irq_is_unmasked() {
+    assert_eq!(apic::is_isa_irq_masked(1), Some(false));
+    for irq in [0, 3, 4, 8, 12, 14] {
+        assert_eq!(apic::is_isa_irq_masked(irq), Some(true));
+    }
+}
+
+#[test_case]
+fn isa_irqs_can_be_masked() {
+    assert!(apic::set_isa_irq_masked(4, false));
+    assert_eq!(apic::is_isa_irq_masked(4), Some(false));
+    assert!(apic::set_isa_irq_masked(4, true));
+    assert_eq!(apic::is_isa_irq_masked(4), Some(true));
+}
@@ -0,0 +1,148 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use bootloader::{entry_point, BootInfo};
+use core::future::{pending, Future};
+use core::panic::PanicInfo;
+use core::pin::pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+use futures_util::stream::StreamExt;
+use marcel_os::task::timer::{self, Elapsed};
+use marcel_os::time::{Duration, Instant};
+use x86_64::instructions::interrupts;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use marcel_os::allocator;
+
+    marcel_os::test_init(boot_info);
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+/// A waker that records whether it was woken.
+struct FlagWaker(AtomicBool);
+
+impl Wake for FlagWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Polls `future` to completion, halting until its waker is woken between polls.
+///
+/// Only the timer can wake the future, so this hangs if the timer interrupt never wakes it.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let flag = Arc::new(FlagWaker(AtomicBool::new(false)));
+    let waker = Waker::from(flag.clone());
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        interrupts::disable();
+        if flag.0.swap(false, Ordering::SeqCst) {
+            interrupts::enable();
+        } else {
+            interrupts::enable_and_hlt();
+        }
+    }
+}
+
+#[test_case]
+fn sleep_waits_for_the_duration() {
+    let start = Instant::now();
+    block_on(timer::sleep(Duration::from_millis(20)));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert_eq!(timer::pending_timers(), 0);
+}
+
+#[test_case]
+fn sleep_until_past_deadline_is_ready() {
+    let now = Instant::now();
+    let mut sleep = pin!(timer::sleep_until(now));
+    let waker = Waker::from(Arc::new(FlagWaker(AtomicBool::new(false))));
+    let mut context = Context::from_waker(&waker);
+    assert_eq!(sleep.as_mut().poll(&mut context), Poll::Ready(()));
+    assert_eq!(timer::pending_timers(), 0);
+}
+
+#[test_case]
+fn dropped_sleeps_are_unregistered() {
+    let waker = Waker::from(Arc::new(FlagWaker(AtomicBool::new(false))));
+    let mut context = Context::from_waker(&waker);
+    {
+        let mut sleep = pin!(timer::sleep(Duration::from_secs(10)));
+        assert_eq!(sleep.as_mut().poll(&mut context), Poll::Pending);
+        assert_eq!(timer::pending_timers(), 1);
+    }
+    assert_eq!(timer::pending_timers(), 0);
+}
+
+#[test_case]
+fn timeout_expires() {
+    let start = Instant::now();
+    let result = block_on(timer::timeout(Duration::from_millis(10), pending::<()>()));
+    assert_eq!(result, Err(Elapsed));
+    assert!(start.elapsed() >= Duration::from_millis(10));
+}
+
+#[test_case]
+fn timeout_passes_output_through() {
+    let future = async {
+        timer::sleep(Duration::from_millis(5)).await;
+        42
+    };
+    let result = block_on(timer::timeout(Duration::from_secs(1), future));
+    assert_eq!(result, Ok(42));
+    assert_eq!(timer::pending_timers(), 0);
+}
+
+#[test_case]
+fn interval_ticks_periodically() {
+    let period = Duration::from_millis(5);
+    let mut interval = timer::interval(period);
+    let first = block_on(interval.next()).unwrap();
+    let mut last = first;
+    for _ in 0..4 {
+        let tick = block_on(interval.next()).unwrap();
+        assert!(tick - last >= period);
+        assert!(Instant::now() >= tick);
+        last = tick;
+    }
+    assert!(last - first >= period * 4);
+}
+
+#[test_case]
+fn earliest_deadline_wakes_first() {
+    let order = block_on(async {
+        let mut short = pin!(timer::sleep(Duration::from_millis(5)));
+        let mut long = pin!(timer::sleep(Duration::from_millis(15)));
+        futures_util::future::poll_fn(|cx| {
+            match (short.as_mut().poll(cx), long.as_mut().poll(cx)) {
+                (Poll::Ready(()), Poll::Pending) => Poll::Ready("short"),
+                (_, Poll::Ready(())) => Poll::Ready("long"),
+                _ => Poll::Pending,
+            }
+        })
+        .await
+    });
+    assert_eq!(order, "short");
+}
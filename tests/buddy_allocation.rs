@@ -0,0 +1,137 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use conquer_once::spin::OnceCell;
+use core::panic::PanicInfo;
+use marcel_os::memory::buddy::{BuddyFrameAllocator, MAX_ORDER};
+use spin::Mutex;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size2MiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+static FRAME_ALLOCATOR: OnceCell<Mutex<BuddyFrameAllocator>> = OnceCell::uninit();
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    FRAME_ALLOCATOR
+        .try_init_once(|| {
+            Mutex::new(unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) })
+        })
+        .expect("frame allocator should only be initialized once");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+fn frame_allocator() -> spin::MutexGuard<'static, BuddyFrameAllocator> {
+    FRAME_ALLOCATOR.try_get().unwrap().lock()
+}
+
+#[test_case]
+fn single_frames() {
+    let mut allocator = frame_allocator();
+    let used = allocator.used_frames();
+    let first: PhysFrame = allocator.allocate_frame().unwrap();
+    let second = allocator.allocate_frame().unwrap();
+    assert_ne!(first, second);
+    assert_eq!(allocator.used_frames(), used + 2);
+    unsafe {
+        allocator.deallocate_frame(first);
+        allocator.deallocate_frame(second);
+    }
+    assert_eq!(allocator.used_frames(), used);
+}
+
+#[test_case]
+fn blocks_are_aligned() {
+    let mut allocator = frame_allocator();
+    for order in 0..=9 {
+        let frame = allocator.allocate_contiguous(order).unwrap();
+        let block_size = 4096u64 << order;
+        assert_eq!(frame.start_address().as_u64() % block_size, 0);
+        unsafe { allocator.deallocate_contiguous(frame, order) };
+    }
+}
+
+#[test_case]
+fn buddies_are_merged() {
+    let mut allocator = frame_allocator();
+    let blocks = allocator.free_blocks(MAX_ORDER);
+    let free = allocator.free_frames();
+
+    // Splitting a maximal block and freeing both halves must restore it.
+    let frame = allocator.allocate_contiguous(MAX_ORDER).unwrap();
+    unsafe { allocator.deallocate_contiguous(frame, MAX_ORDER) };
+    let low = allocator.allocate_contiguous(MAX_ORDER - 1).unwrap();
+    let high = allocator.allocate_contiguous(MAX_ORDER - 1).unwrap();
+    unsafe {
+        allocator.deallocate_contiguous(low, MAX_ORDER - 1);
+        allocator.deallocate_contiguous(high, MAX_ORDER - 1);
+    }
+
+    assert_eq!(allocator.free_blocks(MAX_ORDER), blocks);
+    assert_eq!(allocator.free_frames(), free);
+}
+
+#[test_case]
+fn order_for_frames() {
+    assert_eq!(BuddyFrameAllocator::order_for_frames(1), 0);
+    assert_eq!(BuddyFrameAllocator::order_for_frames(2), 1);
+    assert_eq!(BuddyFrameAllocator::order_for_frames(3), 2);
+    assert_eq!(BuddyFrameAllocator::order_for_frames(512), 9);
+}
+
+#[test_case]
+fn huge_frames_are_aligned() {
+    let mut allocator = frame_allocator();
+    let used = allocator.used_frames();
+    let frame: PhysFrame<Size2MiB> = allocator.allocate_frame().unwrap();
+    assert!(frame.start_address().is_aligned(Size2MiB::SIZE));
+    assert_eq!(allocator.used_frames(), used + 512);
+    unsafe { allocator.deallocate_frame(frame) };
+    assert_eq!(allocator.used_frames(), used);
+}
+
+#[test_case]
+fn shared_frames_are_freed_last() {
+    let mut allocator = frame_allocator();
+    let used = allocator.used_frames();
+    let frame: PhysFrame = allocator.allocate_frame().unwrap();
+    allocator.share_frame(frame);
+    allocator.share_frame(frame);
+    assert_eq!(allocator.ref_count(frame), 3);
+
+    unsafe {
+        assert!(!allocator.release_frame(frame));
+        assert!(!allocator.release_frame(frame));
+        assert_eq!(allocator.ref_count(frame), 1);
+        assert!(allocator.release_frame(frame));
+    }
+    assert_eq!(allocator.used_frames(), used);
+}
+
+#[test_case]
+fn unmanaged_frames_are_ignored() {
+    let mut allocator = frame_allocator();
+    let used = allocator.used_frames();
+    // The VGA text buffer is device memory, and nothing is usable at 1 TiB in the test machine.
+    let vga = PhysFrame::containing_address(PhysAddr::new(0xb8000));
+    let beyond = PhysFrame::containing_address(PhysAddr::new(1 << 40));
+    for frame in [vga, beyond] {
+        assert!(!allocator.manages(frame));
+        assert_eq!(allocator.ref_count(frame), 1);
+        assert!(!unsafe { allocator.release_frame(frame) });
+    }
+    assert_eq!(allocator.used_frames(), used);
+}
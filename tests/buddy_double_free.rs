@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::memory::buddy::BuddyFrameAllocator;
+use marcel_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use x86_64::structures::paging::{PageSize, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("buddy_double_free::interior_frame_of_free_block...\t");
+
+    marcel_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+
+    // Free a block of four frames, then free its second frame as if it were still allocated.
+    let block = allocator.allocate_contiguous(2).unwrap();
+    unsafe { allocator.deallocate_contiguous(block, 2) };
+    let interior =
+        PhysFrame::<Size4KiB>::containing_address(block.start_address() + Size4KiB::SIZE);
+    unsafe { allocator.deallocate_contiguous(interior, 0) };
+
+    serial_println!("[freeing an interior frame of a free block was accepted]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_expect_panic(info, "that is not allocated")
+}
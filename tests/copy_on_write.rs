@@ -0,0 +1,151 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::memory::region::{self, VirtRegion};
+use marcel_os::memory::{self, cow};
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame, Translate};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+/// Returns the frame and flags `addr` is mapped with.
+fn mapping(addr: VirtAddr) -> (PhysFrame, PageTableFlags) {
+    match memory::mapper().translate(addr) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(frame),
+            flags,
+            ..
+        } => (frame, flags),
+        other => panic!("{:?} is not mapped with a 4 KiB page: {:?}", addr, other),
+    }
+}
+
+/// Maps a zeroed page starting with `value` and an unmapped page right after it.
+///
+/// # Returns
+/// The region holding both pages, to be freed with `region::free` once both are unmapped, and
+/// the two pages.
+fn mapped_and_free_page(value: u64) -> (VirtRegion, Page, Page) {
+    let region = region::alloc(2 * 4096).unwrap();
+    region::map_region(&region.sub_region(0, 4096), PageTableFlags::WRITABLE).unwrap();
+    let ptr: *mut u64 = region.start().as_mut_ptr();
+    unsafe {
+        ptr.cast::<u8>().write_bytes(0, 4096);
+        ptr.write_volatile(value);
+    }
+    let (source, dest) = (
+        Page::containing_address(region.start()),
+        Page::containing_address(region.start() + 4096u64),
+    );
+    (region, source, dest)
+}
+
+/// Shares `source` at `dest` copy-on-write.
+fn share(source: Page, dest: Page) {
+    let mut mapper = memory::mapper();
+    cow::share_page(&mut mapper, source, dest, &mut memory::frame_allocator()).unwrap();
+}
+
+/// Unmaps a page through `cow::unmap_page`, returning whether its frame was freed.
+fn unmap(page: Page) -> bool {
+    let mut mapper = memory::mapper();
+    unsafe { cow::unmap_page(&mut mapper, page, &mut memory::frame_allocator()) }
+}
+
+#[test_case]
+fn shared_pages_are_read_only() {
+    let (region, source, dest) = mapped_and_free_page(7);
+    share(source, dest);
+
+    let (frame, flags) = mapping(source.start_address());
+    let (dest_frame, dest_flags) = mapping(dest.start_address());
+    assert_eq!(frame, dest_frame);
+    assert_eq!(flags, dest_flags);
+    assert!(flags.contains(cow::COW));
+    assert!(!flags.contains(PageTableFlags::WRITABLE));
+    assert_eq!(memory::frame_allocator().ref_count(frame), 2);
+
+    let ptr: *const u64 = dest.start_address().as_ptr();
+    assert_eq!(unsafe { ptr.read_volatile() }, 7);
+
+    assert!(!unmap(source));
+    assert!(unmap(dest));
+    region::free(region);
+}
+
+#[test_case]
+fn writes_copy_the_frame() {
+    let (region, source, dest) = mapped_and_free_page(1);
+    share(source, dest);
+    let (frame, _) = mapping(source.start_address());
+
+    let dest_ptr: *mut u64 = dest.start_address().as_mut_ptr();
+    unsafe {
+        dest_ptr.add(1).write_volatile(2);
+        assert_eq!(dest_ptr.read_volatile(), 1);
+    }
+
+    let (dest_frame, dest_flags) = mapping(dest.start_address());
+    assert_ne!(dest_frame, frame);
+    assert!(dest_flags.contains(PageTableFlags::WRITABLE));
+    assert!(!dest_flags.contains(cow::COW));
+    assert_eq!(memory::frame_allocator().ref_count(frame), 1);
+
+    // The source still sees the original contents.
+    let source_ptr: *const u64 = source.start_address().as_ptr();
+    assert_eq!(unsafe { source_ptr.add(1).read_volatile() }, 0);
+
+    assert!(unmap(source));
+    assert!(unmap(dest));
+    region::free(region);
+}
+
+#[test_case]
+fn last_reference_is_reused() {
+    let (region, source, dest) = mapped_and_free_page(3);
+    share(source, dest);
+    let (frame, _) = mapping(source.start_address());
+    assert!(!unmap(dest));
+
+    let used = memory::frame_allocator().used_frames();
+    let ptr: *mut u64 = source.start_address().as_mut_ptr();
+    unsafe { ptr.write_volatile(4) };
+
+    let (new_frame, flags) = mapping(source.start_address());
+    assert_eq!(new_frame, frame);
+    assert!(flags.contains(PageTableFlags::WRITABLE));
+    assert_eq!(memory::frame_allocator().used_frames(), used);
+
+    assert!(unmap(source));
+    region::free(region);
+}
+
+#[test_case]
+fn frames_are_freed_with_the_last_mapping() {
+    let (region, source, dest) = mapped_and_free_page(5);
+    share(source, dest);
+    let used = memory::frame_allocator().used_frames();
+    assert!(!unmap(source));
+    assert_eq!(memory::frame_allocator().used_frames(), used);
+    assert!(unmap(dest));
+    assert_eq!(memory::frame_allocator().used_frames(), used - 1);
+    region::free(region);
+}
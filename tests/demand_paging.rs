@@ -0,0 +1,85 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::memory::{self, region, vma};
+use x86_64::structures::paging::PageTableFlags;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn pages_are_mapped_on_first_access() {
+    let region = region::alloc(4 * 4096).unwrap();
+    let start = region.start();
+    let flags = PageTableFlags::WRITABLE;
+    vma::reserve(start, 4 * 4096, flags, "test").unwrap();
+
+    let used = memory::frame_allocator().used_frames();
+    let ptr: *mut u64 = (start + 4096u64).as_mut_ptr();
+    unsafe {
+        assert_eq!(ptr.read_volatile(), 0);
+        ptr.write_volatile(42);
+        assert_eq!(ptr.read_volatile(), 42);
+    }
+    // Only the touched page (plus possibly intermediate page tables) was populated.
+    let populated = memory::frame_allocator().used_frames() - used;
+    assert!((1..=4).contains(&populated));
+
+    unsafe { vma::release(start).unwrap() };
+    assert!(memory::frame_allocator().used_frames() < used + populated);
+    region::free(region);
+}
+
+#[test_case]
+fn overlapping_areas_are_rejected() {
+    let region = region::alloc(9 * 4096).unwrap();
+    let start = region.start();
+    let flags = PageTableFlags::WRITABLE;
+    vma::reserve(start, 8 * 4096, flags, "first").unwrap();
+    assert_eq!(
+        vma::reserve(start + 4096u64, 4096, flags, "second"),
+        Err(vma::VmaError::Overlap)
+    );
+    assert_eq!(
+        vma::reserve(start + (8u64 * 4096 + 1), 4096, flags, "misaligned"),
+        Err(vma::VmaError::Misaligned)
+    );
+    assert!(vma::find(start + 100u64).is_some());
+    unsafe { vma::release(start).unwrap() };
+    assert!(vma::find(start + 100u64).is_none());
+    region::free(region);
+}
+
+#[test_case]
+fn released_area_can_be_reserved_again() {
+    let region = region::alloc(4096).unwrap();
+    let start = region.start();
+    let flags = PageTableFlags::WRITABLE;
+    for value in 1..=3u8 {
+        vma::reserve(start, 4096, flags, "reused").unwrap();
+        let ptr: *mut u8 = start.as_mut_ptr();
+        unsafe {
+            // Every reservation starts out with zeroed memory.
+            assert_eq!(ptr.read_volatile(), 0);
+            ptr.write_volatile(value);
+            vma::release(start).unwrap();
+        }
+    }
+    region::free(region);
+}
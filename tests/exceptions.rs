@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+use core::fmt::Write;
+use core::panic::PanicInfo;
+use marcel_os::interrupts::exceptions::{decode_error_code, exception_name};
+use marcel_os::{exit_qemu, serial_print, serial_println, MessageBuffer, QemuExitCode};
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    serial_print!("exceptions::error_codes_are_decoded...\t");
+    assert_eq!(
+        exception_name(13),
+        Some(("#GP", "GENERAL PROTECTION FAULT"))
+    );
+    assert_eq!(exception_name(15), None);
+    assert_eq!(decoded(13, 0x10).as_str(), "Gdt selector index 2");
+    assert_eq!(
+        decoded(11, 0x1B).as_str(),
+        "Idt selector index 3, external event"
+    );
+    assert_eq!(decoded(13, 0).as_str(), "no selector");
+    assert_eq!(decoded(21, 3).as_str(), "missing ENDBRANCH");
+    assert!(decoded(14, 0b11).as_str().contains("PROTECTION_VIOLATION"));
+    serial_println!("[ok]");
+
+    marcel_os::init();
+
+    serial_print!("exceptions::breakpoint_resumes...\t");
+    x86_64::instructions::interrupts::int3();
+    serial_println!("[ok]");
+
+    serial_print!("exceptions::invalid_opcode_is_reported...\t");
+    unsafe { core::arch::asm!("ud2") };
+
+    serial_println!("[execution continued after invalid opcode]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+/// Returns the decoding of an error code.
+fn decoded(vector: u8, error_code: u64) -> MessageBuffer {
+    let mut buffer = MessageBuffer::new();
+    write!(buffer, "{}", decode_error_code(vector, error_code)).unwrap();
+    buffer
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_expect_panic(info, "EXCEPTION: INVALID OPCODE")
+}
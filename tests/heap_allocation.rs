@@ -7,24 +7,22 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
+use alloc::vec;
 use alloc::vec::Vec;
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use marcel_os::allocator::HEAP_SIZE;
+use marcel_os::allocator::{HEAP_MAX_SIZE, HEAP_SIZE};
+use marcel_os::serial_println;
 
 entry_point!(main);
 
 fn main(boot_info: &'static BootInfo) -> ! {
     use marcel_os::allocator;
-    use marcel_os::memory::{self, BootInfoFrameAllocator};
-    use x86_64::VirtAddr;
 
-    marcel_os::init();
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    marcel_os::test_init(boot_info);
+    allocator::init_heap().expect("heap initialization failed");
 
+    serial_println!("heap allocator: {}", allocator::HEAP_ALLOCATOR_NAME);
     test_main();
     loop {}
 }
@@ -59,3 +57,106 @@ fn many_boxes() {
         assert_eq!(*x, i);
     }
 }
+
+#[test_case]
+fn heap_grows_beyond_initial_size() {
+    let n = HEAP_SIZE * 4;
+    let vec = vec![1u8; n];
+    assert_eq!(vec.len(), n);
+    assert_eq!(vec.iter().map(|&x| x as usize).sum::<usize>(), n);
+}
+
+#[test_case]
+fn allocation_beyond_max_size_fails() {
+    let mut vec: Vec<u8> = Vec::new();
+    assert!(vec.try_reserve_exact(HEAP_MAX_SIZE * 2).is_err());
+}
+
+#[test_case]
+fn try_alloc_succeeds() {
+    use alloc::alloc::{dealloc, Layout};
+    use marcel_os::allocator::try_alloc;
+
+    let layout = Layout::from_size_align(512, 64).unwrap();
+    let ptr = try_alloc(layout).expect("allocation should succeed");
+    assert_eq!(ptr.as_ptr() as usize % 64, 0);
+    unsafe { dealloc(ptr.as_ptr(), layout) };
+}
+
+#[test_case]
+fn try_alloc_reports_exhaustion() {
+    use alloc::alloc::Layout;
+    use marcel_os::allocator::{reserve, try_alloc, try_box};
+
+    let layout = Layout::from_size_align(HEAP_MAX_SIZE * 2, 8).unwrap();
+    let error = try_alloc(layout).unwrap_err();
+    assert_eq!(error.layout, layout);
+    assert!(!reserve::is_released());
+
+    let boxed = try_box([3u32; 16]).expect("small allocation should succeed");
+    assert_eq!(boxed[15], 3);
+}
+
+#[test_case]
+fn stats_track_allocations() {
+    use marcel_os::allocator::heap_info;
+
+    let before = heap_info().stats;
+    let value = Box::new([0u8; 64]);
+    let during = heap_info().stats;
+    assert_eq!(during.live_allocations, before.live_allocations + 1);
+    assert_eq!(during.allocated_bytes, before.allocated_bytes + 64);
+    assert!(during.peak_bytes >= during.allocated_bytes);
+
+    drop(value);
+    let after = heap_info().stats;
+    assert_eq!(after.live_allocations, before.live_allocations);
+    assert_eq!(after.allocated_bytes, before.allocated_bytes);
+}
+
+#[test_case]
+fn growth_fails_while_mapper_is_held() {
+    use alloc::alloc::{dealloc, Layout};
+    use marcel_os::allocator::{heap_info, try_alloc, GrowError};
+    use marcel_os::memory;
+
+    let layout = Layout::from_size_align(4 * 1024 * 1024, 8).unwrap();
+    let heap_size = heap_info().heap_size;
+    {
+        let _mapper = memory::mapper();
+        assert!(try_alloc(layout).is_err());
+    }
+    let info = heap_info();
+    assert_eq!(info.heap_size, heap_size);
+    assert_eq!(info.last_grow_error, Some(GrowError::Busy));
+
+    let ptr = try_alloc(layout).expect("allocation should succeed once the mapper is free");
+    unsafe { dealloc(ptr.as_ptr(), layout) };
+}
+
+#[test_case]
+fn large_growth_uses_huge_pages() {
+    use marcel_os::allocator::{heap_info, HEAP_START};
+    use marcel_os::memory;
+    use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+    use x86_64::structures::paging::{PageSize, Size2MiB, Translate};
+    use x86_64::VirtAddr;
+
+    let vec = vec![1u8; 6 * 1024 * 1024];
+    let heap = VirtAddr::new(HEAP_START as u64);
+    let heap_end = heap + heap_info().heap_size as u64;
+    let mut chunk = heap.align_up(Size2MiB::SIZE);
+    let mut huge_pages = 0;
+    while chunk + Size2MiB::SIZE <= heap_end {
+        if let TranslateResult::Mapped {
+            frame: MappedFrame::Size2MiB(_),
+            ..
+        } = memory::mapper().translate(chunk)
+        {
+            huge_pages += 1;
+        }
+        chunk += Size2MiB::SIZE;
+    }
+    assert!(huge_pages >= 1);
+    drop(vec);
+}
@@ -0,0 +1,76 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc, Layout};
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::allocator::debug::{ALLOC_POISON, FREE_POISON};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use marcel_os::allocator;
+
+    marcel_os::test_init(boot_info);
+    allocator::init_heap().expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn fresh_memory_is_poisoned() {
+    for size in [1, 8, 100, 4096] {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        unsafe {
+            let ptr = alloc(layout);
+            assert!(!ptr.is_null());
+            for offset in 0..size {
+                assert_eq!(*ptr.add(offset), ALLOC_POISON);
+            }
+            dealloc(ptr, layout);
+        }
+    }
+}
+
+#[test_case]
+fn freed_memory_is_poisoned() {
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    unsafe {
+        let ptr = alloc(layout);
+        ptr.write_bytes(0, layout.size());
+        dealloc(ptr, layout);
+        for offset in 0..layout.size() {
+            assert_eq!(ptr.add(offset).read_volatile(), FREE_POISON);
+        }
+    }
+}
+
+#[test_case]
+fn alignment_is_preserved() {
+    for align in [8, 64, 512, 4096] {
+        let layout = Layout::from_size_align(24, align).unwrap();
+        unsafe {
+            let ptr = alloc(layout);
+            assert_eq!(ptr as usize % align, 0);
+            dealloc(ptr, layout);
+        }
+    }
+}
+
+#[test_case]
+fn regular_allocations_still_work() {
+    let value = Box::new([7u64; 32]);
+    assert!(value.iter().all(|&x| x == 7));
+}
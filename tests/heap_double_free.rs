@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc, Layout};
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use marcel_os::allocator;
+
+    serial_print!("heap_double_free::double_free_is_detected...\t");
+
+    marcel_os::test_init(boot_info);
+    allocator::init_heap().expect("heap initialization failed");
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let ptr = alloc(layout);
+        dealloc(ptr, layout);
+        dealloc(ptr, layout);
+    }
+
+    serial_println!("[double free not detected]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // The allocator must have been unlocked before panicking.
+    drop(Box::new(42u64));
+    marcel_os::test_expect_panic(info, "HEAP: double free of")
+}
@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::alloc::{dealloc, Layout};
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use marcel_os::allocator;
+
+    serial_print!("heap_foreign_free::foreign_pointer_is_detected...\t");
+
+    marcel_os::test_init(boot_info);
+    allocator::init_heap().expect("heap initialization failed");
+
+    let layout = Layout::from_size_align(16, 8).unwrap();
+    let mut local = [0u8; 16];
+    unsafe { dealloc(local.as_mut_ptr(), layout) };
+
+    serial_println!("[foreign pointer not detected]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // The allocator must have been unlocked before panicking.
+    drop(Box::new(42u64));
+    marcel_os::test_expect_panic(info, "HEAP: free of foreign pointer")
+}
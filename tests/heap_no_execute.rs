@@ -0,0 +1,65 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use lazy_static::lazy_static;
+use marcel_os::memory;
+use marcel_os::{allocator, exit_qemu, serial_print, serial_println, QemuExitCode};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("heap_no_execute::executing_heap_memory_faults...\t");
+
+    marcel_os::test_init(boot_info);
+    allocator::init_heap().expect("heap initialization failed");
+
+    if !memory::protect::enabled().no_execute {
+        serial_println!("[ok, no-execute pages not supported]");
+        exit_qemu(QemuExitCode::Success);
+    }
+
+    TEST_IDT.load();
+
+    // A single `ret` instruction placed on the heap.
+    let code = Box::leak(Box::new([0xc3u8; 16]));
+    let function: extern "C" fn() = unsafe { core::mem::transmute(code.as_ptr()) };
+    function();
+
+    serial_println!("[heap memory was executable]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[unexpected page fault: {:?}]", error_code);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
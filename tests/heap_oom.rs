@@ -0,0 +1,51 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::allocator::{reserve, try_alloc};
+use marcel_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    use marcel_os::allocator;
+
+    marcel_os::test_init(boot_info);
+    allocator::init_heap().expect("heap initialization failed");
+
+    serial_print!("heap_oom::heap_oom...\t");
+
+    // Exhaust the heap with fallible allocations, from large chunks down to the smallest block.
+    let mut size = 64 * 1024;
+    while size >= 8 {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        while try_alloc(layout).is_ok() {}
+        size /= 2;
+    }
+
+    // This allocation has to fail and end up in the allocation error handler.
+    let value = Box::new(0u64);
+
+    serial_println!("[allocation did not fail: {:p}]", value);
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    // The heap is exhausted, so this allocation can only succeed from the released reserve.
+    let value = Box::new([1u8; 64]);
+    if reserve::is_released() && reserve::contains(value.as_ptr()) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[reserve was not used]");
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
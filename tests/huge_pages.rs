@@ -0,0 +1,108 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::memory::{self, region};
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageSize, PageTableFlags, PhysFrame, Size2MiB, Translate,
+};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+/// Returns the frame `addr` is mapped to.
+fn mapped_frame(addr: VirtAddr) -> MappedFrame {
+    match memory::mapper().translate(addr) {
+        TranslateResult::Mapped { frame, .. } => frame,
+        other => panic!("{:?} is not mapped: {:?}", addr, other),
+    }
+}
+
+#[test_case]
+fn aligned_region_is_mapped_with_a_2mib_page() {
+    let size = Size2MiB::SIZE;
+    let region = region::alloc_aligned(size, size).unwrap();
+    let used = memory::frame_allocator().used_frames();
+
+    region::map_region(&region, PageTableFlags::WRITABLE).unwrap();
+    assert!(matches!(
+        mapped_frame(region.start()),
+        MappedFrame::Size2MiB(_)
+    ));
+
+    // Touch the first and the last byte of the page.
+    let first: *mut u8 = region.start().as_mut_ptr();
+    let last: *mut u8 = (region.end() - 1u64).as_mut_ptr();
+    unsafe {
+        first.write_volatile(1);
+        last.write_volatile(2);
+        assert_eq!(first.read_volatile(), 1);
+        assert_eq!(last.read_volatile(), 2);
+    }
+
+    unsafe { region::unmap_region(&region) };
+    assert!(memory::mapper().translate_addr(region.start()).is_none());
+    // At most the page tables created for the mapping stay allocated.
+    assert!(memory::frame_allocator().used_frames() < used + 4);
+    region::free(region);
+}
+
+#[test_case]
+fn unaligned_parts_fall_back_to_4kib_pages() {
+    let region = region::alloc_aligned(Size2MiB::SIZE + 8192, Size2MiB::SIZE).unwrap();
+    region::map_region(&region, PageTableFlags::WRITABLE).unwrap();
+
+    assert!(matches!(
+        mapped_frame(region.start()),
+        MappedFrame::Size2MiB(_)
+    ));
+    let tail = region.start() + Size2MiB::SIZE;
+    assert!(matches!(mapped_frame(tail), MappedFrame::Size4KiB(_)));
+    unsafe { tail.as_mut_ptr::<u64>().write_volatile(7) };
+
+    unsafe { region::unmap_region(&region) };
+    region::free(region);
+}
+
+#[test_case]
+fn physical_window_uses_huge_pages() {
+    let frame: PhysFrame<Size2MiB> = memory::frame_allocator().allocate_frame().unwrap();
+    let through_offset: *mut u64 = memory::phys_to_virt(frame.start_address()).as_mut_ptr();
+    unsafe { through_offset.add(1000).write_volatile(0xfeed_f00d) };
+
+    let window = region::alloc_aligned(Size2MiB::SIZE, Size2MiB::SIZE).unwrap();
+    unsafe { region::map_physical(&window, frame.start_address(), PageTableFlags::WRITABLE) }
+        .unwrap();
+    assert!(matches!(
+        mapped_frame(window.start()),
+        MappedFrame::Size2MiB(mapped) if mapped == frame
+    ));
+    let through_window: *mut u64 = window.start().as_mut_ptr();
+    assert_eq!(
+        unsafe { through_window.add(1000).read_volatile() },
+        0xfeed_f00d
+    );
+
+    unsafe {
+        region::unmap_physical(&window);
+        memory::frame_allocator().deallocate_frame(frame);
+    }
+    region::free(window);
+}
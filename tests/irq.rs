@@ -0,0 +1,161 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use marcel_os::interrupts::irq::{self, IrqError, MAX_SHARED_HANDLERS};
+use marcel_os::interrupts::PIC_1_OFFSET;
+
+/// The IRQ line used by the tests, which no device raises in QEMU.
+const TEST_IRQ: u8 = 5;
+
+static CLAIMED_CALLS: AtomicU64 = AtomicU64::new(0);
+static UNCLAIMED_CALLS: AtomicU64 = AtomicU64::new(0);
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    marcel_os::init();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+fn claiming_handler(irq: u8) -> bool {
+    assert_eq!(irq, TEST_IRQ);
+    CLAIMED_CALLS.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+fn irq7_handler(_irq: u8) -> bool {
+    CLAIMED_CALLS.fetch_add(1, Ordering::Relaxed);
+    true
+}
+
+fn unclaiming_handler(irq: u8) -> bool {
+    assert_eq!(irq, TEST_IRQ);
+    UNCLAIMED_CALLS.fetch_add(1, Ordering::Relaxed);
+    false
+}
+
+/// Raises the test IRQ's vector in software.
+fn raise_test_irq() {
+    unsafe { core::arch::asm!("int {}", const PIC_1_OFFSET + TEST_IRQ) };
+}
+
+#[test_case]
+fn reserved_lines_are_rejected() {
+    assert_eq!(
+        irq::register_irq(0, claiming_handler),
+        Err(IrqError::Reserved)
+    );
+    assert_eq!(
+        irq::register_irq(2, claiming_handler),
+        Err(IrqError::Reserved)
+    );
+    assert_eq!(
+        irq::register_irq(16, claiming_handler),
+        Err(IrqError::InvalidIrq)
+    );
+    assert_eq!(irq::mask_irq(0), Err(IrqError::Reserved));
+}
+
+#[test_case]
+fn keyboard_is_registered() {
+    assert!(irq::has_handlers(1));
+    assert!(!irq::is_masked(1));
+}
+
+#[test_case]
+fn registration_unmasks_the_line() {
+    assert!(irq::is_masked(TEST_IRQ));
+    let id = irq::register_irq(TEST_IRQ, claiming_handler).unwrap();
+    assert_eq!(id.irq(), TEST_IRQ);
+    assert!(!irq::is_masked(TEST_IRQ));
+    irq::unregister_irq(id).unwrap();
+    assert!(irq::is_masked(TEST_IRQ));
+}
+
+#[test_case]
+fn explicit_masking() {
+    let id = irq::register_irq(TEST_IRQ, claiming_handler).unwrap();
+    irq::mask_irq(TEST_IRQ).unwrap();
+    assert!(irq::is_masked(TEST_IRQ));
+    irq::unmask_irq(TEST_IRQ).unwrap();
+    assert!(!irq::is_masked(TEST_IRQ));
+    irq::unregister_irq(id).unwrap();
+}
+
+#[test_case]
+fn shared_handlers_are_chained() {
+    let claimed = CLAIMED_CALLS.load(Ordering::Relaxed);
+    let unclaimed = UNCLAIMED_CALLS.load(Ordering::Relaxed);
+    let count = irq::interrupt_count(TEST_IRQ);
+    let unhandled = irq::unhandled_count(TEST_IRQ);
+
+    let first = irq::register_irq(TEST_IRQ, unclaiming_handler).unwrap();
+    let second = irq::register_irq(TEST_IRQ, claiming_handler).unwrap();
+    raise_test_irq();
+    assert_eq!(CLAIMED_CALLS.load(Ordering::Relaxed), claimed + 1);
+    assert_eq!(UNCLAIMED_CALLS.load(Ordering::Relaxed), unclaimed + 1);
+    assert_eq!(irq::interrupt_count(TEST_IRQ), count + 1);
+    assert_eq!(irq::unhandled_count(TEST_IRQ), unhandled);
+
+    irq::unregister_irq(first).unwrap();
+    irq::unregister_irq(second).unwrap();
+}
+
+#[test_case]
+fn unclaimed_interrupts_are_counted() {
+    let unhandled = irq::unhandled_count(TEST_IRQ);
+    let id = irq::register_irq(TEST_IRQ, unclaiming_handler).unwrap();
+    raise_test_irq();
+    assert_eq!(irq::unhandled_count(TEST_IRQ), unhandled + 1);
+    irq::unregister_irq(id).unwrap();
+}
+
+#[test_case]
+fn unregistered_handlers_are_not_called() {
+    let id = irq::register_irq(TEST_IRQ, claiming_handler).unwrap();
+    irq::unregister_irq(id).unwrap();
+    let claimed = CLAIMED_CALLS.load(Ordering::Relaxed);
+    raise_test_irq();
+    assert_eq!(CLAIMED_CALLS.load(Ordering::Relaxed), claimed);
+    assert_eq!(irq::unregister_irq(id), Err(IrqError::NotRegistered));
+}
+
+#[test_case]
+fn chain_is_bounded() {
+    let mut ids = [None; MAX_SHARED_HANDLERS];
+    for id in ids.iter_mut() {
+        *id = Some(irq::register_irq(TEST_IRQ, claiming_handler).unwrap());
+    }
+    assert_eq!(
+        irq::register_irq(TEST_IRQ, claiming_handler),
+        Err(IrqError::ChainFull)
+    );
+    for id in ids.into_iter().flatten() {
+        irq::unregister_irq(id).unwrap();
+    }
+    assert!(!irq::has_handlers(TEST_IRQ));
+}
+
+#[test_case]
+fn spurious_interrupts_are_not_dispatched() {
+    // Raised in software, the interrupt is not in service at the PIC, like a spurious one.
+    let spurious = irq::spurious_count(7);
+    let count = irq::interrupt_count(7);
+    let claimed = CLAIMED_CALLS.load(Ordering::Relaxed);
+    let id = irq::register_irq(7, irq7_handler).unwrap();
+    unsafe { core::arch::asm!("int {}", const PIC_1_OFFSET + 7) };
+    assert_eq!(irq::spurious_count(7), spurious + 1);
+    assert_eq!(irq::interrupt_count(7), count);
+    assert_eq!(CLAIMED_CALLS.load(Ordering::Relaxed), claimed);
+    irq::unregister_irq(id).unwrap();
+}
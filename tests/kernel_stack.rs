@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::memory::{self, stack};
+use x86_64::structures::paging::Translate;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn stack_is_mapped_above_an_unmapped_guard_page() {
+    let stack = stack::alloc("test", 4).unwrap();
+    assert_eq!(stack.top() - stack.bottom(), 4 * 4096);
+    assert_eq!(stack.guard_page().start_address() + 4096u64, stack.bottom());
+
+    let mapper = memory::mapper();
+    assert!(mapper
+        .translate_addr(stack.guard_page().start_address())
+        .is_none());
+    assert!(mapper.translate_addr(stack.bottom()).is_some());
+    assert!(mapper.translate_addr(stack.top() - 1u64).is_some());
+    drop(mapper);
+
+    // The whole stack is writable.
+    let bytes = (stack.top() - stack.bottom()) as usize;
+    unsafe { stack.bottom().as_mut_ptr::<u8>().write_bytes(0x5a, bytes) };
+}
+
+#[test_case]
+fn guard_pages_are_attributed_to_their_stack() {
+    let first = stack::alloc("first", 2).unwrap();
+    let second = stack::alloc("second", 2).unwrap();
+    assert!(first.top() <= second.guard_page().start_address());
+
+    let guard = first.guard_page().start_address();
+    assert_eq!(stack::guard_page_owner(guard + 8u64), Some("first"));
+    assert_eq!(
+        stack::guard_page_owner(second.guard_page().start_address()),
+        Some("second")
+    );
+    assert_eq!(stack::guard_page_owner(first.bottom()), None);
+}
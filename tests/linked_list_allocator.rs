@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::panic::PanicInfo;
+use marcel_os::allocator::linked_list::LinkedListAllocator;
+use marcel_os::allocator::{HeapUsage, Locked};
+use marcel_os::{exit_qemu, QemuExitCode};
+
+const HEAP_SIZE: usize = 64 * 1024;
+
+#[repr(align(4096))]
+struct Heap([u8; HEAP_SIZE]);
+
+static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+static ALLOCATOR: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator::new());
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    unsafe {
+        let heap_start = &raw mut HEAP.0 as usize;
+        ALLOCATOR.lock().init(heap_start, HEAP_SIZE);
+    }
+
+    test_main();
+    exit_qemu(QemuExitCode::Success);
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn interleaved_frees_coalesce() {
+    const SIZES: [usize; 8] = [16, 256, 64, 1024, 24, 512, 128, 2048];
+    let mut blocks = [(core::ptr::null_mut(), Layout::new::<u8>()); 4 * SIZES.len()];
+
+    for (i, block) in blocks.iter_mut().enumerate() {
+        let layout = Layout::from_size_align(SIZES[i % SIZES.len()], 8).unwrap();
+        let ptr = unsafe { ALLOCATOR.alloc(layout) };
+        assert!(!ptr.is_null());
+        *block = (ptr, layout);
+    }
+
+    // Free every other block first so that no two free regions touch, then fill the gaps.
+    for (ptr, layout) in blocks.iter().step_by(2) {
+        unsafe { ALLOCATOR.dealloc(*ptr, *layout) };
+    }
+    for (ptr, layout) in blocks.iter().skip(1).step_by(2) {
+        unsafe { ALLOCATOR.dealloc(*ptr, *layout) };
+    }
+
+    assert_eq!(ALLOCATOR.lock().free_bytes(), HEAP_SIZE);
+
+    let layout = Layout::from_size_align(HEAP_SIZE, 8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(layout) };
+    assert!(!ptr.is_null());
+    unsafe { ALLOCATOR.dealloc(ptr, layout) };
+}
+
+#[test_case]
+fn freed_neighbours_are_reused() {
+    let layout = Layout::from_size_align(1024, 8).unwrap();
+    let (a, b, c) = unsafe {
+        (
+            ALLOCATOR.alloc(layout),
+            ALLOCATOR.alloc(layout),
+            ALLOCATOR.alloc(layout),
+        )
+    };
+
+    // Freeing the middle block last must merge all three into one region.
+    unsafe {
+        ALLOCATOR.dealloc(a, layout);
+        ALLOCATOR.dealloc(c, layout);
+        ALLOCATOR.dealloc(b, layout);
+    }
+
+    let large = Layout::from_size_align(3 * 1024, 8).unwrap();
+    let ptr = unsafe { ALLOCATOR.alloc(large) };
+    assert_eq!(ptr, a.min(b).min(c));
+    unsafe { ALLOCATOR.dealloc(ptr, large) };
+}
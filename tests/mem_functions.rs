@@ -0,0 +1,150 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use marcel_os::memory::{memcmp, memcpy, memmove, memset};
+
+/// The largest length tested, long enough to exercise the word-sized loops and their tails.
+const MAX_LEN: usize = 80;
+
+/// The largest source/destination offset tested, covering every alignment within a word.
+const MAX_OFFSET: usize = 8;
+
+const BUFFER_SIZE: usize = MAX_LEN + 2 * MAX_OFFSET + 16;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+/// Returns a buffer filled with a pattern that differs between neighbouring bytes.
+fn pattern(seed: u8) -> [u8; BUFFER_SIZE] {
+    let mut buffer = [0; BUFFER_SIZE];
+    for (i, byte) in buffer.iter_mut().enumerate() {
+        *byte = (i as u8).wrapping_mul(7).wrapping_add(seed);
+    }
+    buffer
+}
+
+#[test_case]
+fn memset_all_alignments() {
+    for offset in 0..MAX_OFFSET {
+        for len in 0..=MAX_LEN {
+            let mut buffer = pattern(1);
+            let expected = pattern(1);
+            let ptr = buffer.as_mut_ptr().wrapping_add(offset);
+            assert_eq!(unsafe { memset(ptr, 0xA5, len) }, ptr);
+            for (i, &byte) in buffer.iter().enumerate() {
+                if i >= offset && i < offset + len {
+                    assert_eq!(byte, 0xA5);
+                } else {
+                    assert_eq!(byte, expected[i], "byte outside the range was modified");
+                }
+            }
+        }
+    }
+}
+
+#[test_case]
+fn memcpy_all_alignments() {
+    let source = pattern(3);
+    for src_offset in 0..MAX_OFFSET {
+        for dest_offset in 0..MAX_OFFSET {
+            for len in 0..=MAX_LEN {
+                let mut dest = [0u8; BUFFER_SIZE];
+                let dest_ptr = dest.as_mut_ptr().wrapping_add(dest_offset);
+                let src_ptr = source.as_ptr().wrapping_add(src_offset);
+                assert_eq!(unsafe { memcpy(dest_ptr, src_ptr, len) }, dest_ptr);
+                for (i, &byte) in dest.iter().enumerate() {
+                    if i >= dest_offset && i < dest_offset + len {
+                        assert_eq!(byte, source[i - dest_offset + src_offset]);
+                    } else {
+                        assert_eq!(byte, 0, "byte outside the range was modified");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test_case]
+fn memmove_overlapping() {
+    // Moves within one buffer, with the destination both before and after the source.
+    for src_offset in 0..2 * MAX_OFFSET {
+        for dest_offset in 0..2 * MAX_OFFSET {
+            for len in 0..=MAX_LEN {
+                let mut buffer = pattern(5);
+                let original = pattern(5);
+                let base = buffer.as_mut_ptr();
+                let dest_ptr = base.wrapping_add(dest_offset);
+                let src_ptr = base.wrapping_add(src_offset);
+                assert_eq!(unsafe { memmove(dest_ptr, src_ptr, len) }, dest_ptr);
+                for (i, &byte) in buffer.iter().enumerate() {
+                    if i >= dest_offset && i < dest_offset + len {
+                        assert_eq!(byte, original[i - dest_offset + src_offset]);
+                    } else {
+                        assert_eq!(byte, original[i], "byte outside the range was modified");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test_case]
+fn memmove_restores_direction_flag() {
+    let mut buffer = pattern(7);
+    let base = buffer.as_mut_ptr();
+    unsafe { memmove(base.wrapping_add(1), base, 64) };
+
+    let flags: u64;
+    unsafe { core::arch::asm!("pushfq", "pop {}", out(reg) flags) };
+    assert_eq!(flags & (1 << 10), 0, "direction flag left set");
+}
+
+#[test_case]
+fn memcmp_all_alignments() {
+    let first = pattern(9);
+    for offset in 0..MAX_OFFSET {
+        for len in 0..=MAX_LEN {
+            let ptr = first.as_ptr().wrapping_add(offset);
+            let mut second = pattern(9);
+            let other = second.as_mut_ptr().wrapping_add(offset);
+            assert_eq!(unsafe { memcmp(ptr, other, len) }, 0);
+
+            // A single differing byte decides the sign, wherever it is.
+            for diff in 0..len {
+                second[offset + diff] = first[offset + diff].wrapping_add(1);
+                let other = second.as_ptr().wrapping_add(offset);
+                let expected = first[offset + diff] as i32 - second[offset + diff] as i32;
+                assert_eq!(
+                    unsafe { memcmp(ptr, other, len) }.signum(),
+                    expected.signum()
+                );
+                assert_eq!(
+                    unsafe { memcmp(other, ptr, len) }.signum(),
+                    -expected.signum()
+                );
+                second[offset + diff] = first[offset + diff];
+            }
+        }
+    }
+}
+
+#[test_case]
+fn memcmp_reports_first_difference() {
+    let first = [1u8, 2, 3, 4, 5, 6, 7, 8, 9, 10];
+    let second = [1u8, 2, 3, 4, 5, 6, 7, 8, 0, 20];
+    assert!(unsafe { memcmp(first.as_ptr(), second.as_ptr(), 10) } > 0);
+    assert!(unsafe { memcmp(second.as_ptr(), first.as_ptr(), 10) } < 0);
+    assert_eq!(unsafe { memcmp(first.as_ptr(), second.as_ptr(), 8) }, 0);
+}
@@ -0,0 +1,73 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::memory::{self, map_mmio};
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, PageTableFlags, PhysFrame, Translate,
+};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn mapping_is_uncached_and_removed_on_drop() {
+    // A spare RAM frame stands in for device memory.
+    let frame: PhysFrame = memory::frame_allocator().allocate_frame().unwrap();
+    let phys = frame.start_address() + 0x10u64;
+
+    let mmio = unsafe { map_mmio(phys, 0x20).unwrap() };
+    assert_eq!(mmio.phys(), phys);
+    assert_eq!(mmio.base().as_u64() & 0xfff, 0x10);
+
+    match memory::mapper().translate(mmio.base()) {
+        TranslateResult::Mapped {
+            frame: MappedFrame::Size4KiB(mapped),
+            offset,
+            flags,
+        } => {
+            assert_eq!(mapped, frame);
+            assert_eq!(offset, 0x10);
+            assert!(flags.contains(PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH));
+        }
+        other => panic!("unexpected translation {:?}", other),
+    }
+
+    let base = mmio.base();
+    drop(mmio);
+    assert!(memory::mapper().translate_addr(base).is_none());
+
+    unsafe { memory::frame_allocator().deallocate_frame(frame) };
+}
+
+#[test_case]
+fn volatile_accessors() {
+    let frame: PhysFrame = memory::frame_allocator().allocate_frame().unwrap();
+    let mmio = unsafe { map_mmio(frame.start_address(), 4096).unwrap() };
+
+    mmio.write_u32(0x0, 0xdead_beef);
+    mmio.write_u64(0x8, 0x0123_4567_89ab_cdef);
+    mmio.write::<u8>(0xfff, 0x5a);
+    assert_eq!(mmio.read_u32(0x0), 0xdead_beef);
+    assert_eq!(mmio.read_u64(0x8), 0x0123_4567_89ab_cdef);
+    assert_eq!(mmio.read::<u8>(0xfff), 0x5a);
+
+    drop(mmio);
+    unsafe { memory::frame_allocator().deallocate_frame(frame) };
+}
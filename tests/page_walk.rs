@@ -0,0 +1,160 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::memory::{self, region, walk};
+use x86_64::structures::paging::{PageTableFlags, Translate};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn mapped_region_shows_up_as_one_range() {
+    let region = region::alloc(3 * 4096).unwrap();
+    region::map_region(&region, PageTableFlags::WRITABLE).unwrap();
+
+    let mut found = None;
+    walk::for_each_mapped_range(|range| {
+        if range.start <= region.start() && region.start() < range.end {
+            found = Some(range);
+        }
+    });
+    let range = found.expect("mapped region not found by the walker");
+    assert!(range.end >= region.end());
+    assert_eq!(range.page_size, 4096);
+    assert!(range
+        .flags
+        .contains(PageTableFlags::PRESENT | PageTableFlags::WRITABLE));
+
+    unsafe { region::unmap_region(&region) };
+    region::free(region);
+}
+
+#[test_case]
+fn ranges_are_sorted_and_disjoint() {
+    let mut previous_end = None;
+    let mut count = 0;
+    walk::for_each_mapped_range(|range| {
+        assert!(range.start < range.end);
+        if let Some(end) = previous_end {
+            assert!(end <= range.start);
+        }
+        previous_end = Some(range.end);
+        count += 1;
+    });
+    assert!(count > 0);
+}
+
+#[test_case]
+fn translate_matches_mapper() {
+    let value = 42u64;
+    let addr = VirtAddr::from_ptr(&value);
+
+    let translation = walk::translate(addr);
+    assert_eq!(translation.phys, memory::mapper().translate_addr(addr));
+    assert!(translation.phys.is_some());
+    assert_eq!(translation.entries[0].unwrap().level, 4);
+    assert_eq!(translation.entries[0].unwrap().index, addr.p4_index());
+
+    let unmapped = region::alloc(4096).unwrap();
+    assert_eq!(walk::translate(unmapped.start()).phys, None);
+    region::free(unmapped);
+}
+
+#[test_case]
+fn flags_are_combined_across_levels() {
+    use x86_64::structures::paging::{PageSize, PageTable, Size2MiB};
+
+    let region = region::alloc_aligned(Size2MiB::SIZE, Size2MiB::SIZE).unwrap();
+    let page = region.sub_region(0, 4096);
+    let leaf_flags = PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    region::map_region(&page, leaf_flags).unwrap();
+
+    // The level 1 table only maps this region, so its level 2 entry can be restricted.
+    let entries = walk::translate(page.start()).entries;
+    let (p2_table, p2_index) = (entries[1].unwrap().addr, entries[2].unwrap().index);
+    let no_execute = memory::protect::enabled().no_execute;
+    let restrict = |restricted: bool| {
+        let _mapper = memory::mapper();
+        let table: *mut PageTable = memory::phys_to_virt(p2_table).as_mut_ptr();
+        let entry = &mut unsafe { &mut *table }[p2_index];
+        let mut flags = entry.flags();
+        flags.set(PageTableFlags::USER_ACCESSIBLE, !restricted);
+        flags.set(PageTableFlags::NO_EXECUTE, restricted && no_execute);
+        entry.set_flags(flags);
+        x86_64::instructions::tlb::flush_all();
+    };
+    restrict(true);
+
+    let translation = walk::translate(page.start());
+    let leaf = translation.entries[3].unwrap().flags;
+    assert!(leaf.contains(PageTableFlags::USER_ACCESSIBLE));
+    assert!(!leaf.contains(PageTableFlags::NO_EXECUTE));
+    let flags = translation.flags.unwrap();
+    assert!(flags.contains(PageTableFlags::WRITABLE));
+    assert!(!flags.contains(PageTableFlags::USER_ACCESSIBLE));
+    assert_eq!(flags.contains(PageTableFlags::NO_EXECUTE), no_execute);
+
+    let mut found = None;
+    walk::for_each_mapped_range(|range| {
+        if range.start == page.start() {
+            found = Some(range.flags);
+        }
+    });
+    assert_eq!(
+        found.map(|flags| flags - PageTableFlags::ACCESSED),
+        Some(flags)
+    );
+
+    restrict(false);
+    unsafe { region::unmap_region(&region) };
+    region::free(region);
+}
+
+#[test_case]
+fn copied_ranges_match_the_walk() {
+    use marcel_os::memory::walk::MappedRange;
+
+    let mut expected = 0;
+    let mut first = None;
+    walk::for_each_mapped_range(|range| {
+        first.get_or_insert(range);
+        expected += 1;
+    });
+
+    let mut batch = [first.unwrap(); 4];
+    let mut from = VirtAddr::zero();
+    let mut copied_total = 0;
+    let mut previous: Option<MappedRange> = None;
+    loop {
+        let copied = walk::copy_mapped_ranges(from, &mut batch);
+        for range in &batch[..copied] {
+            if let Some(previous) = previous {
+                assert!(previous.end <= range.start);
+            }
+            previous = Some(*range);
+        }
+        copied_total += copied;
+        if copied < batch.len() {
+            break;
+        }
+        from = batch[copied - 1].end;
+    }
+    assert_eq!(copied_total, expected);
+}
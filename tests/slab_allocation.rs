@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::ptr::NonNull;
+use marcel_os::allocator::slab::{self, SlabCache};
+use marcel_os::allocator::Locked;
+use marcel_os::memory;
+
+#[allow(dead_code)]
+struct Node {
+    value: u64,
+    next: Option<NonNull<Node>>,
+}
+
+static NODE_CACHE: Locked<SlabCache> = Locked::new(SlabCache::for_type::<Node>("node"));
+static PAGE_CACHE: Locked<SlabCache> = Locked::new(SlabCache::new(
+    "half-page",
+    match core::alloc::Layout::from_size_align(2000, 64) {
+        Ok(layout) => layout,
+        Err(_) => panic!("invalid layout"),
+    },
+));
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::test_init(boot_info);
+
+    slab::register(&NODE_CACHE);
+    slab::register(&PAGE_CACHE);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn objects_are_distinct_and_writable() {
+    let mut cache = NODE_CACHE.lock();
+    let a = cache.alloc().unwrap().cast::<Node>();
+    let b = cache.alloc().unwrap().cast::<Node>();
+    assert_ne!(a, b);
+
+    unsafe {
+        a.as_ptr().write(Node {
+            value: 1,
+            next: Some(b),
+        });
+        b.as_ptr().write(Node {
+            value: 2,
+            next: None,
+        });
+        assert_eq!((*a.as_ptr()).value, 1);
+        assert_eq!((*b.as_ptr()).value, 2);
+
+        cache.free(a.cast());
+        cache.free(b.cast());
+    }
+    assert_eq!(cache.stats().objects_in_use, 0);
+}
+
+#[test_case]
+fn objects_are_aligned() {
+    let mut cache = PAGE_CACHE.lock();
+    let stats = cache.stats();
+    assert_eq!(stats.objects_per_slab, 1);
+
+    let object = cache.alloc().unwrap();
+    assert_eq!(object.as_ptr() as usize % 64, 0);
+    unsafe { cache.free(object) };
+}
+
+#[test_case]
+fn empty_slabs_are_reclaimed() {
+    const OBJECTS: usize = 1000;
+
+    let mut cache = NODE_CACHE.lock();
+    cache.reclaim();
+    let used_frames = memory::frame_allocator().used_frames();
+
+    let mut objects = [NonNull::dangling(); OBJECTS];
+    for object in objects.iter_mut() {
+        *object = cache.alloc().unwrap();
+    }
+
+    let stats = cache.stats();
+    let slabs = OBJECTS.div_ceil(stats.objects_per_slab);
+    assert_eq!(stats.objects_in_use, OBJECTS);
+    assert_eq!(stats.full_slabs + stats.partial_slabs, slabs);
+    assert_eq!(memory::frame_allocator().used_frames(), used_frames + slabs);
+
+    for object in objects.iter() {
+        unsafe { cache.free(*object) };
+    }
+
+    let stats = cache.stats();
+    assert_eq!(stats.objects_in_use, 0);
+    assert_eq!(stats.full_slabs + stats.partial_slabs, 0);
+
+    cache.reclaim();
+    assert_eq!(cache.stats().empty_slabs, 0);
+    assert_eq!(memory::frame_allocator().used_frames(), used_frames);
+}
+
+#[test_case]
+fn held_frame_allocator_is_not_waited_for() {
+    let mut cache = PAGE_CACHE.lock();
+    cache.reclaim();
+
+    let first = cache.alloc().unwrap();
+    let second = {
+        let _frame_allocator = memory::frame_allocator();
+        // Every slab holds a single object, so this needs a new slab.
+        assert!(cache.alloc().is_none());
+        assert_eq!(cache.reclaim(), 0);
+        unsafe { cache.free(first) };
+        cache.alloc().expect("the freed slab should be reused")
+    };
+
+    unsafe { cache.free(second) };
+    assert_eq!(cache.reclaim(), 1);
+}
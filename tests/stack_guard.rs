@@ -0,0 +1,32 @@
+#![no_std]
+#![no_main]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("stack_guard::overflow_is_reported_by_name...\t");
+
+    marcel_os::test_init(boot_info);
+    marcel_os::gdt::init_guarded_stacks();
+
+    stack_overflow();
+
+    serial_println!("[execution continued after stack overflow]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+#[allow(unconditional_recursion)]
+fn stack_overflow() {
+    stack_overflow();
+    volatile::Volatile::new(0).read();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_expect_panic(info, "stack overflow in kernel")
+}
@@ -0,0 +1,190 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::memory::address_space::{self, USER_SPACE_START};
+use marcel_os::memory::region::{self, VirtRegion};
+use marcel_os::memory::{self, protect, swap, vma, AddressSpace};
+use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::test_init(boot_info);
+    swap::init(16).expect("swap store initialization failed");
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+/// Reserves a demand-paged area of `pages` pages and marks it reclaimable.
+fn reclaimable_area(pages: u64) -> VirtRegion {
+    let region = region::alloc(pages * 4096).unwrap();
+    let start = region.start();
+    vma::reserve(start, pages * 4096, PageTableFlags::WRITABLE, "swap test").unwrap();
+    swap::mark_reclaimable(start, pages * 4096).unwrap();
+    region
+}
+
+/// Releases an area created by `reclaimable_area`, including its virtual region.
+fn release(region: VirtRegion) {
+    unsafe { vma::release(region.start()).unwrap() };
+    swap::unmark_reclaimable(region.start()).unwrap();
+    region::free(region);
+}
+
+fn page_ptr(start: VirtAddr, page: u64) -> *mut u64 {
+    (start + page * 4096).as_mut_ptr()
+}
+
+fn is_mapped(addr: VirtAddr) -> bool {
+    memory::walk::translate(addr).phys.is_some()
+}
+
+#[test_case]
+fn swapped_pages_are_restored() {
+    let area = reclaimable_area(2);
+    let start = area.start();
+    let ptr = page_ptr(start, 0);
+    unsafe { ptr.write_volatile(0x1234) };
+
+    let stats = swap::stats();
+    swap::swap_out(start).unwrap();
+    assert!(!is_mapped(start));
+    assert_eq!(swap::stats().used_slots, stats.used_slots + 1);
+    assert_eq!(swap::stats().swapped_out, stats.swapped_out + 1);
+
+    assert_eq!(unsafe { ptr.read_volatile() }, 0x1234);
+    assert!(is_mapped(start));
+    assert_eq!(swap::stats().used_slots, stats.used_slots);
+    assert_eq!(swap::stats().swapped_in, stats.swapped_in + 1);
+
+    release(area);
+}
+
+#[test_case]
+fn clean_pages_are_dropped() {
+    let area = reclaimable_area(1);
+    let start = area.start();
+    let ptr = page_ptr(start, 0);
+    assert_eq!(unsafe { ptr.read_volatile() }, 0);
+
+    let stats = swap::stats();
+    swap::swap_out(start).unwrap();
+    assert!(!is_mapped(start));
+    assert_eq!(swap::stats().dropped, stats.dropped + 1);
+    assert_eq!(swap::stats().used_slots, stats.used_slots);
+
+    assert_eq!(unsafe { ptr.read_volatile() }, 0);
+    release(area);
+}
+
+#[test_case]
+fn reclaim_evicts_cold_pages() {
+    let area = reclaimable_area(4);
+    let start = area.start();
+    for page in 0..4u64 {
+        unsafe { page_ptr(start, page).write_volatile(page + 100) };
+    }
+
+    // Every page was just accessed, so each needs a second visit before it is evicted.
+    let stats = swap::stats();
+    assert_eq!(swap::reclaim(4), 4);
+    assert_eq!(swap::stats().used_slots, stats.used_slots + 4);
+    for page in 0..4u64 {
+        assert!(!is_mapped(start + page * 4096));
+    }
+
+    for page in 0..4u64 {
+        assert_eq!(unsafe { page_ptr(start, page).read_volatile() }, page + 100);
+    }
+    assert_eq!(swap::stats().used_slots, stats.used_slots);
+    release(area);
+}
+
+#[test_case]
+fn releasing_an_area_frees_its_slots() {
+    let area = reclaimable_area(2);
+    let start = area.start();
+    unsafe { page_ptr(start, 1).write_volatile(7) };
+    let used = swap::stats().used_slots;
+    swap::swap_out(start + 4096u64).unwrap();
+    assert_eq!(swap::stats().used_slots, used + 1);
+
+    release(area);
+    assert_eq!(swap::stats().used_slots, used);
+}
+
+#[test_case]
+fn only_reclaimable_pages_are_evicted() {
+    let region = region::alloc(4096).unwrap();
+    let start = region.start();
+    vma::reserve(start, 4096, PageTableFlags::WRITABLE, "pinned").unwrap();
+    unsafe { page_ptr(start, 0).write_volatile(1) };
+    assert_eq!(swap::swap_out(start), Err(swap::SwapError::NotReclaimable));
+    unsafe { vma::release(start).unwrap() };
+    region::free(region);
+}
+
+#[test_case]
+fn unmapping_a_region_frees_its_slots() {
+    let region = region::alloc(4096).unwrap();
+    let start = region.start();
+    region::map_region(&region, PageTableFlags::WRITABLE).unwrap();
+    swap::mark_reclaimable(start, 4096).unwrap();
+    unsafe { page_ptr(start, 0).write_volatile(3) };
+    let used = swap::stats().used_slots;
+    swap::swap_out(start).unwrap();
+    assert_eq!(swap::stats().used_slots, used + 1);
+
+    unsafe { region::unmap_region(&region) };
+    assert_eq!(swap::stats().used_slots, used);
+    swap::unmark_reclaimable(start).unwrap();
+    region::free(region);
+}
+
+#[test_case]
+fn user_pages_are_reclaimed() {
+    let stats = swap::stats();
+    let mut space = AddressSpace::new().unwrap();
+    let pages = [0u64, 1]
+        .map(|index| Page::containing_address(VirtAddr::new(USER_SPACE_START + index * 4096)));
+    for page in pages {
+        space.map_user(page, PageTableFlags::WRITABLE).unwrap();
+    }
+    let ptr = |page: Page| page.start_address().as_mut_ptr::<u64>();
+    unsafe {
+        space.activate();
+        for (value, &page) in pages.iter().enumerate() {
+            protect::with_user_access(|| ptr(page).write_volatile(value as u64 + 10));
+        }
+        address_space::activate_kernel();
+    }
+
+    assert_eq!(swap::reclaim(2), 2);
+    assert_eq!(swap::stats().used_slots, stats.used_slots + 2);
+    assert_eq!(space.translate(pages[0].start_address()), None);
+
+    let value = unsafe {
+        space.activate();
+        let value = protect::with_user_access(|| ptr(pages[0]).read_volatile());
+        address_space::activate_kernel();
+        value
+    };
+    assert_eq!(value, 10);
+    assert_eq!(swap::stats().swapped_in, stats.swapped_in + 1);
+
+    // The page still swapped out releases its slot with the address space.
+    drop(space);
+    assert_eq!(swap::stats().used_slots, stats.used_slots);
+}
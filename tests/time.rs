@@ -0,0 +1,83 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+use marcel_os::time::{self, Duration, Instant};
+use x86_64::instructions::hlt;
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    marcel_os::init();
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn timer_runs_at_default_frequency() {
+    assert_eq!(time::frequency(), time::DEFAULT_FREQUENCY_HZ);
+}
+
+#[test_case]
+fn ticks_advance() {
+    let ticks = time::ticks();
+    for _ in 0..3 {
+        hlt();
+    }
+    assert!(time::ticks() > ticks);
+}
+
+#[test_case]
+fn uptime_is_monotonic() {
+    let mut last = time::uptime();
+    for _ in 0..10 {
+        hlt();
+        let now = time::uptime();
+        assert!(now >= last);
+        last = now;
+    }
+}
+
+#[test_case]
+fn uptime_follows_ticks() {
+    let start = Instant::now();
+    let ticks = time::ticks();
+    while time::ticks() < ticks + 10 {
+        hlt();
+    }
+    // Ten ticks at 1000 Hz take ten milliseconds, give or take a tick.
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(9));
+    assert!(elapsed <= Duration::from_millis(12));
+}
+
+#[test_case]
+fn instant_arithmetic() {
+    let now = Instant::now();
+    let later = now + Duration::from_millis(5);
+    assert_eq!(later - now, Duration::from_millis(5));
+    assert_eq!(now - later, Duration::ZERO);
+    assert_eq!(later - Duration::from_millis(5), now);
+    assert_eq!(now.checked_duration_since(later), None);
+    assert!(later > now);
+}
+
+#[test_case]
+fn frequency_can_be_changed() {
+    assert_eq!(time::set_frequency(100), 100);
+    assert_eq!(time::frequency(), 100);
+    let ticks = time::ticks();
+    let start = Instant::now();
+    while time::ticks() < ticks + 3 {
+        hlt();
+    }
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    time::set_frequency(time::DEFAULT_FREQUENCY_HZ);
+}
@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(marcel_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use marcel_os::memory::{self, region};
+use x86_64::structures::paging::{PageTableFlags, Translate};
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::test_init(boot_info);
+
+    test_main();
+    loop {}
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+#[test_case]
+fn regions_do_not_overlap() {
+    let first = region::alloc(3 * 4096).unwrap();
+    let second = region::alloc(1).unwrap();
+    assert_eq!(first.size(), 3 * 4096);
+    assert_eq!(second.size(), 4096);
+    assert!(first.end() <= second.start() || second.end() <= first.start());
+    region::free(first);
+    region::free(second);
+}
+
+#[test_case]
+fn freed_regions_are_merged() {
+    let free = region::free_bytes();
+    let regions = [
+        region::alloc(4096).unwrap(),
+        region::alloc(4096).unwrap(),
+        region::alloc(4096).unwrap(),
+    ];
+    assert_eq!(region::free_bytes(), free - 3 * 4096);
+    for region in regions {
+        region::free(region);
+    }
+    assert_eq!(region::free_bytes(), free);
+
+    // After merging, a single large allocation fits at the same place again.
+    let large = region::alloc(region::free_bytes()).unwrap();
+    assert_eq!(region::free_bytes(), 0);
+    region::free(large);
+}
+
+#[test_case]
+fn aligned_and_fixed_regions() {
+    let aligned = region::alloc_aligned(4096, 0x20_0000).unwrap();
+    assert!(aligned.start().is_aligned(0x20_0000u64));
+
+    let fixed = region::reserve(aligned.end(), 2 * 4096).unwrap();
+    assert_eq!(fixed.start(), aligned.end());
+    assert!(region::reserve(aligned.start(), 4096).is_none());
+
+    region::free(aligned);
+    region::free(fixed);
+}
+
+#[test_case]
+fn map_and_unmap_region_release_frames() {
+    let used = memory::frame_allocator().used_frames();
+    let region = region::alloc(8 * 4096).unwrap();
+    region::map_region(&region, PageTableFlags::WRITABLE).unwrap();
+    assert!(memory::frame_allocator().used_frames() >= used + 8);
+
+    let ptr: *mut u64 = region.start().as_mut_ptr();
+    unsafe {
+        ptr.write_bytes(0x11, region.size() as usize / 8);
+        assert_eq!(ptr.add(100).read_volatile(), 0x1111_1111_1111_1111);
+    }
+
+    let data_frames = memory::frame_allocator().used_frames();
+    unsafe { region::unmap_region(&region) };
+    assert_eq!(memory::frame_allocator().used_frames(), data_frames - 8);
+    assert!(memory::mapper().translate_addr(region.start()).is_none());
+    region::free(region);
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+use bootloader::{entry_point, BootInfo};
+use core::hint::black_box;
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use marcel_os::memory;
+use marcel_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+/// Immutable data, placed in `.rodata`.
+static RODATA: [u64; 4] = [1, 2, 3, 4];
+
+/// Mutable data, placed in `.data`.
+static DATA: AtomicU64 = AtomicU64::new(1);
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+        idt
+    };
+}
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    marcel_os::test_init(boot_info);
+    memory::protect::remap_kernel();
+    let protections = memory::protect::enabled();
+    assert!(protections.write_protect);
+
+    serial_print!("write_protect::sections_are_mapped_w_xor_x...\t");
+    let code = flags_of(main as *const ());
+    assert!(!code.contains(PageTableFlags::WRITABLE));
+    assert!(!code.contains(PageTableFlags::NO_EXECUTE));
+    let rodata = flags_of(black_box(&RODATA).as_ptr());
+    assert!(!rodata.contains(PageTableFlags::WRITABLE));
+    DATA.fetch_add(1, Ordering::Relaxed);
+    let data = flags_of(black_box(&DATA));
+    assert!(data.contains(PageTableFlags::WRITABLE));
+    if protections.no_execute {
+        assert!(rodata.contains(PageTableFlags::NO_EXECUTE));
+        assert!(data.contains(PageTableFlags::NO_EXECUTE));
+    }
+    serial_println!("[ok]");
+
+    serial_print!("write_protect::write_to_code_faults...\t");
+    TEST_IDT.load();
+
+    // Overwrite the first instruction of a kernel function.
+    let code = main as *const () as *mut u8;
+    unsafe { code.write_volatile(0xcc) };
+
+    serial_println!("[write to code did not fault]");
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
+/// Returns the effective flags of the page mapping `ptr`.
+fn flags_of<T>(ptr: *const T) -> PageTableFlags {
+    memory::walk::translate(VirtAddr::from_ptr(ptr))
+        .flags
+        .expect("kernel address is not mapped")
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    marcel_os::test_panic_handler(info)
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let expected = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::CAUSED_BY_WRITE;
+    if error_code.contains(expected) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[unexpected page fault: {:?}]", error_code);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}